@@ -0,0 +1,17 @@
+// Three rings for bass, mids and highs on a noise background, uses prelude 2.
+//! prelude 2
+
+fn ring(p: vec2<f32>, radius: f32, level: f32) -> f32 {
+	let d = abs(sd_circle(p, radius + level * 0.1)) - 0.005 - level * 0.02;
+	return smoothstep(0.01, 0.0, d);
+}
+
+fn fs_user(uv: vec2<f32>) -> vec3<f32> {
+	let p = rotate2d(util.time * 0.1) * centered_uv(uv);
+	let background = fbm(p * 3.0 + util.time * 0.05, 4) * 0.15;
+	var col = vec3<f32>(background);
+	col += hsv_to_rgb(vec3<f32>(0.0, 0.8, 1.0)) * ring(p, 0.1, fft_bass());
+	col += hsv_to_rgb(vec3<f32>(0.33, 0.8, 1.0)) * ring(p, 0.25, fft_mids());
+	col += hsv_to_rgb(vec3<f32>(0.66, 0.8, 1.0)) * ring(p, 0.4, fft_highs());
+	return col * (1.0 + util.beat * 0.5);
+}
@@ -87,7 +87,8 @@ fn fs_user(coord: vec2<f32>) -> vec3<f32> {
     var thD = .035; // + smoothstep(-0.2, 0.2, sin(iTime*0.75 - 3.14159*0.4))*0.025;
 
     // Only a few iterations seemed to be enough. Obviously, more looks better, but is slower.
-	for(var i=0; i<56; i++)	{
+	let steps = i32(56.0 * util.quality);
+	for(var i=0; i<steps; i++)	{
 
         // Break conditions. Anything that can help you bail early usually increases frame rate.
         if(layers > 15 || col.x>1. || t>10.) {
@@ -0,0 +1,18 @@
+// Spectrum bars leaving trails, buffer_a keeps the trails and zooms them out every frame.
+//! pass buffer_a format=rgba16float
+
+fn bars(uv: vec2<f32>) -> f32 {
+	let fft = fft_sample(uv.x, 0);
+	return select(0.0, 1.0, abs(uv.y - 0.5) < fft * 0.5);
+}
+
+fn fs_buffer_a(uv: vec2<f32>) -> vec4<f32> {
+	let zoomed = (uv - 0.5) * 0.98 + 0.5;
+	let trail = feedback_sample(buffer_a, zoomed).rgb * 0.96;
+	let color = vec3<f32>(0.2 + 0.5 * uv.x, 0.4, 1.0 - uv.x) * bars(uv);
+	return vec4<f32>(max(trail, color), 1.0);
+}
+
+fn fs_user(uv: vec2<f32>) -> vec3<f32> {
+	return feedback_sample(buffer_a, uv).rgb;
+}
@@ -1,6 +1,7 @@
 // Based on
 // https://www.shadertoy.com/view/4ddSDS
 // Created by Stephane Cuillerdier - @Aiekick/2016
+//! author Stephane Cuillerdier (@Aiekick)
 
 fn getRotZMat(a: f32) -> mat3x3<f32> {
 	return mat3x3<f32>(
@@ -1,4 +1,5 @@
 // Derived from https://www.shadertoy.com/view/WllyzM
+//! camera orbit eye=0.95,1.9,-3.8 target=0,0.4,0
 fn GetGraphDist(pp: vec3<f32>) -> f32 {
     // Switch Y and Z
     let p = pp.xzy;
@@ -13,80 +14,7 @@ fn GetGraphDist(pp: vec3<f32>) -> f32 {
     return d;
 }
 
-const arrowThickness: f32 = 0.3;
-fn Arrow(
-	rayOrigin: vec3<f32>,
-	rayDirection: vec3<f32>,
-	newP_in: vec3<f32>,
-	offset: f32,
-	invTip:  f32
-) -> vec3<f32> {
-	var newP = newP_in;
-    // Move arrow position
-	newP.z += 2.3 + offset;
-
-    // Center position
-    newP.x -= 0.025;
-
-    // See if intersection point lays within the arrow
-    if(abs(newP.x / newP.z) <= arrowThickness &&
-       newP.z * 8.0/3.0 * invTip <= 1.0 &&
-       newP.z * invTip >= 0.0)
-    {
-        return vec3<f32>(0.0);
-    }
-
-    return vec3<f32>(1.0);
-}
-
-fn AllArrows(rayOrigin: vec3<f32>, rayDirection: vec3<f32>) -> vec3<f32>
-{
-	var col = vec3<f32>(1.0);
-
-    // 2 arrows in xz-plane
-	var t = (0.0 - rayOrigin.y) / rayDirection.y;
-    var newP = rayOrigin + t * rayDirection;
-    col *= Arrow(rayOrigin, rayDirection, newP.xyz, 0.3, 1.0);
-    col *= Arrow(rayOrigin, rayDirection, newP.zyx, 0.0, 1.0);
-
-    // 1 arrow in yz-plane
-	t = (0.0 - rayOrigin.z) / rayDirection.z;
-    newP = rayOrigin + t * rayDirection;
-    col *= Arrow(rayOrigin, rayDirection, newP.xzy, -4.6, -1.0);
-
-    return col;
-}
-
-fn AllAxes(rayOrigin: vec3<f32>, rayDirection: vec3<f32>) -> vec3<f32>
-{
-    let thickness = 0.05;
-
-    // X-axis
-    var ty = (0.0 - rayOrigin.y) / rayDirection.y;
-    var newPy = rayOrigin + ty * rayDirection;
-    if(newPy.x <= 2.0 && newPy.x >= -2.0 && newPy.z <= 0.0 && newPy.z >= -thickness)
-    {
-        return vec3<f32>(0.0);
-    }
-
-    // Y-axis
-    let tz = (0.0 - rayOrigin.z) / rayDirection.z;
-    let newPz = rayOrigin + tz * rayDirection;
-    if(newPz.x <= thickness && newPz.x >= 0.0 && newPz.y <= 2.0 && newPz.y >= -2.0)
-    {
-        return vec3<f32>(0.0);
-    }
-
-    // Z-axis
-    ty = (0.0 - rayOrigin.y) / rayDirection.y;
-    newPy = rayOrigin + ty * rayDirection;
-    if(newPy.x <= thickness && newPy.x >= 0.0 && newPy.z <= 2.0 && newPy.z >= -2.5)
-    {
-        return vec3<f32>(0.0);
-    }
-
-    return vec3<f32>(1.0);
-}
+#include "lib/axes.wgsl"
 
 // Extreme values for a good quality
 //const f32 STEP_SIZE = 0.001;
@@ -96,22 +24,10 @@ const STEP_SIZE = 0.003;
 const MAX_NUM_STEPS = 2200.0;
 const HIT_DIST = 0.000001;
 fn fs_user(uvv: vec2<f32>) -> vec3<f32> {
-    let aspect = util.res_width / util.res_height;
-    var uv = uvv;
-	uv -= vec2<f32>(0.5, 0.5);
-    uv.x *= aspect;
-
-    // Camera setup
-    let camOrigin = vec3<f32>(1.0 + sin(util.time)*0.1, 2.0, -4.0) * 0.95;
-    let camLookAt = vec3<f32>(0.0, 0.4, 0.0);
-
-    let camForward = normalize(camLookAt - camOrigin);
-    let camRight = normalize(cross(camForward, vec3<f32>(0.0, 1.0, 0.0)));
-    let camUp = normalize(cross(camRight, camForward));
-
-    let zoom = 1.0;
-    var rayOrigin = camOrigin + camForward * zoom + camRight * uv.x + camUp * uv.y;
-    let rayDirection = normalize(rayOrigin - camOrigin);
+    // Camera setup, drag to orbit and scroll to zoom.
+    let rayDirection = camera_ray(uvv);
+    // Start one unit in front of the camera.
+    var rayOrigin = camera.eye.xyz + rayDirection;
 
 
     var col = vec3<f32>(1.0);
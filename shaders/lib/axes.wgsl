@@ -0,0 +1,75 @@
+// Arrows and axes along x, y and z for raymarched graphs, shared by graph.wgsl and heart.wgsl.
+const arrowThickness: f32 = 0.3;
+fn Arrow(
+	rayOrigin: vec3<f32>,
+	rayDirection: vec3<f32>,
+	newP_in: vec3<f32>,
+	offset: f32,
+	invTip:  f32
+) -> vec3<f32> {
+	var newP = newP_in;
+    // Move arrow position
+	newP.z += 2.3 + offset;
+
+    // Center position
+    newP.x -= 0.025;
+
+    // See if intersection point lays within the arrow
+    if(abs(newP.x / newP.z) <= arrowThickness &&
+       newP.z * 8.0/3.0 * invTip <= 1.0 &&
+       newP.z * invTip >= 0.0)
+    {
+        return vec3<f32>(0.0);
+    }
+
+    return vec3<f32>(1.0);
+}
+
+fn AllArrows(rayOrigin: vec3<f32>, rayDirection: vec3<f32>) -> vec3<f32>
+{
+	var col = vec3<f32>(1.0);
+
+    // 2 arrows in xz-plane
+	var t = (0.0 - rayOrigin.y) / rayDirection.y;
+    var newP = rayOrigin + t * rayDirection;
+    col *= Arrow(rayOrigin, rayDirection, newP.xyz, 0.3, 1.0);
+    col *= Arrow(rayOrigin, rayDirection, newP.zyx, 0.0, 1.0);
+
+    // 1 arrow in yz-plane
+	t = (0.0 - rayOrigin.z) / rayDirection.z;
+    newP = rayOrigin + t * rayDirection;
+    col *= Arrow(rayOrigin, rayDirection, newP.xzy, -4.6, -1.0);
+
+    return col;
+}
+
+fn AllAxes(rayOrigin: vec3<f32>, rayDirection: vec3<f32>) -> vec3<f32>
+{
+    let thickness = 0.05;
+
+    // X-axis
+    var ty = (0.0 - rayOrigin.y) / rayDirection.y;
+    var newPy = rayOrigin + ty * rayDirection;
+    if(newPy.x <= 2.0 && newPy.x >= -2.0 && newPy.z <= 0.0 && newPy.z >= -thickness)
+    {
+        return vec3<f32>(0.0);
+    }
+
+    // Y-axis
+    let tz = (0.0 - rayOrigin.z) / rayDirection.z;
+    let newPz = rayOrigin + tz * rayDirection;
+    if(newPz.x <= thickness && newPz.x >= 0.0 && newPz.y <= 2.0 && newPz.y >= -2.0)
+    {
+        return vec3<f32>(0.0);
+    }
+
+    // Z-axis
+    ty = (0.0 - rayOrigin.y) / rayDirection.y;
+    newPy = rayOrigin + ty * rayDirection;
+    if(newPy.x <= thickness && newPy.x >= 0.0 && newPy.z <= 2.0 && newPy.z >= -2.5)
+    {
+        return vec3<f32>(0.0);
+    }
+
+    return vec3<f32>(1.0);
+}
@@ -1,5 +1,5 @@
-// mi-ku/Altair
-// https://www.shadertoy.com/view/Xsj3zy
+//! author mi-ku/Altair
+//! source https://www.shadertoy.com/view/Xsj3zy
 
 const MULT = 10.0;
 const BLUR_EPS = 0.001;
@@ -0,0 +1,10 @@
+// Scrolling noise from textures/noise.png, warped by the spectrum.
+//! texture noise noise.png wrap=repeat
+
+fn fs_user(uv: vec2<f32>) -> vec3<f32> {
+	let fft = fft_sample(uv.x, 0);
+	let warp = textureSample(noise, noise_sampler, uv * 0.5 + vec2<f32>(0.0, util.time * 0.05)).rg - 0.5;
+	let n = textureSample(noise, noise_sampler, uv * 2.0 + warp * fft).rgb;
+	let bars = smoothstep(0.0, 0.02, fft - uv.y);
+	return mix(n * 0.3, n, bars) * vec3<f32>(0.6 + util.beat * 0.4, 0.5, 1.0);
+}
@@ -0,0 +1,38 @@
+// Particles orbiting the center, the bass pushes them out and every beat kicks them.
+//! particles count=8192
+//! param spin 0 1 0.3
+//! post bloom strength=0.8 audio=1 threshold=0.4
+//! post vignette strength=0.7
+//! post lut warm_lut.png strength=0.8
+
+fn hash11(p: f32) -> f32 {
+	return fract(sin(p * 127.1) * 43758.5453);
+}
+
+fn cs_particle(index: u32, particle: Particle) -> Particle {
+	var p = particle;
+	let i = f32(index);
+	let aspect = iResolution.x / iResolution.y;
+	let dt = 1.0 / 60.0;
+
+	let bass = fft_sample(0.02 + 0.05 * hash11(i), 0);
+	let offset = (p.position.xy - 0.5) * vec2<f32>(aspect, 1.0);
+	let dist = max(length(offset), 0.001);
+	let dir = offset / dist;
+	let tangent = vec2<f32>(-dir.y, dir.x);
+
+	// Pull towards a ring whose radius follows the bass.
+	let ring = 0.15 + 0.3 * bass;
+	var force = dir * (ring - dist) * 4.0 + tangent * params.spin * (0.7 + hash11(i + 1.0));
+	force += dir * util.beat * 2.0 * hash11(i + 2.0);
+
+	p.velocity = vec4<f32>((p.velocity.xy + force * dt) * 0.97, 0.0, 0.0);
+	p.position = vec4<f32>(p.position.xy + p.velocity.xy / vec2<f32>(aspect, 1.0) * dt, 0.0, 0.002 + 0.004 * bass);
+	p.color = vec4<f32>(0.3 + 0.7 * bass, 0.4 + 0.3 * hash11(i + 3.0), 1.0, 0.4);
+	return p;
+}
+
+fn fs_user(uv: vec2<f32>) -> vec3<f32> {
+	let center = length((uv - 0.5) * vec2<f32>(iResolution.x / iResolution.y, 1.0));
+	return vec3<f32>(0.02, 0.0, 0.05) * (1.0 - center) + vec3<f32>(0.1, 0.0, 0.2) * util.beat;
+}
@@ -0,0 +1,16 @@
+// Shadertoy GLSL is translated on load, iChannel0 is the audio input.
+// Paste the code of a Shadertoy image tab into a .glsl file to run it.
+
+void mainImage(out vec4 fragColor, in vec2 fragCoord)
+{
+    vec2 uv = fragCoord / iResolution.xy;
+
+    float fft = texture(iChannel0, vec2(uv.x, 0.25)).x;
+    float wave = texture(iChannel0, vec2(uv.x, 0.75)).x;
+
+    vec3 col = 0.5 + 0.5 * cos(iTime + uv.xyx + vec3(0.0, 2.0, 4.0));
+    col *= step(uv.y, fft);
+    col += vec3(1.0 - smoothstep(0.0, 0.01, abs(wave - uv.y)));
+
+    fragColor = vec4(col, 1.0);
+}
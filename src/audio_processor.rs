@@ -1,12 +1,16 @@
-use crate::{fft_buffer::FFTDimensions, state::State};
+use crate::{fft_buffer::FFTDimensions, settings::AudioSettings, state::State};
+use anyhow::{bail, ensure, Context};
 use core::f32::consts::PI;
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     BufferSize, Stream, StreamConfig, SupportedBufferSize,
 };
-use ringbuf::{Consumer, HeapRb};
+use ringbuf::{Consumer, HeapRb, Producer};
 use rustfft::{num_complex::Complex32, FftPlanner};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::VecDeque,
+    ops::Range,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
@@ -16,6 +20,116 @@ use std::{
 };
 
 pub type TextureHandle = Arc<Mutex<Vec<f32>>>;
+pub type BeatHandle = Arc<Mutex<Beat>>;
+pub type AnalysisHandle = Arc<Mutex<Analysis>>;
+
+/// How the fft runs are turned into the texture, can be changed while running.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Analysis {
+    /// How much of the last run is kept, 0 follows the audio right away.
+    pub smoothing: f32,
+    /// The levels that end up as 0 and 1 in the texture.
+    pub db_min: f32,
+    pub db_max: f32,
+    pub binning: Binning,
+}
+
+impl Default for Analysis {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.8,
+            db_min: -100.,
+            db_max: -10.,
+            binning: Binning::default(),
+        }
+    }
+}
+
+impl Analysis {
+    /// For values from files, the ui sliders can't get out of range.
+    pub fn check(&self) -> anyhow::Result<()> {
+        ensure!(
+            (0.0..1.0).contains(&self.smoothing),
+            "the smoothing {} is not between 0 and 1",
+            self.smoothing
+        );
+        ensure!(
+            self.db_min.is_finite() && self.db_max.is_finite(),
+            "the dB range has to be numbers"
+        );
+        ensure!(
+            self.db_min < self.db_max && self.db_max <= 0.,
+            "the dB range {} to {} is backwards or above 0",
+            self.db_min,
+            self.db_max
+        );
+        Ok(())
+    }
+}
+
+/// How the fft bins are spread over the width of the fft texture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Binning {
+    /// A bin per texel, what `fft_hz` in the prelude expects.
+    #[default]
+    Linear,
+    /// Every octave gets the same width, so the bass isn't squeezed into the first few texels.
+    Log,
+}
+
+impl Binning {
+    pub const ALL: [Binning; 2] = [Binning::Linear, Binning::Log];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Binning::Linear => "Linear",
+            Binning::Log => "Log",
+        }
+    }
+
+    /// The bins that end up in texel `x` of a `width` wide row.
+    pub fn bins(&self, x: usize, width: usize) -> Range<usize> {
+        match self {
+            Binning::Linear => x..x + 1,
+            Binning::Log => {
+                // Bin 0 is the DC offset, so it's left out.
+                let edge = |x: usize| (width as f32).powf(x as f32 / width as f32) as usize;
+                let start = edge(x).min(width - 1);
+                let end = edge(x + 1).clamp(start + 1, width);
+                start..end
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Beat {
+    pub last: Option<Instant>,
+    pub count: u32,
+    /// Beats come from outside, like over OSC, and detected ones are ignored.
+    pub external: bool,
+}
+
+impl Beat {
+    pub fn hit(&mut self, at: Instant) {
+        self.last = Some(at);
+        self.count = self.count.wrapping_add(1);
+    }
+
+    /// 1 on the beat and fading out after, for driving visuals.
+    pub fn pulse(&self) -> f32 {
+        self.pulse_at(Instant::now())
+    }
+
+    pub fn pulse_at(&self, now: Instant) -> f32 {
+        const FADE_PER_SECOND: f32 = 8.;
+        self.last
+            .map(|t| (-now.saturating_duration_since(t).as_secs_f32() * FADE_PER_SECOND).exp())
+            .unwrap_or(0.)
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Default)]
@@ -30,50 +144,23 @@ pub struct FFTStats {
 
 #[allow(dead_code)]
 pub struct AudioProcessor {
-    // wave_texture: TextureHandle,
+    wave_texture: TextureHandle,
     fft_texture: TextureHandle,
-    // beat_texture: TextureHandle,
+    beat: BeatHandle,
+    analysis: AnalysisHandle,
     fft_stats: Arc<Mutex<FFTStats>>,
-    input_stream: Stream,
+    // None when a file is analysed instead.
+    input_stream: Option<Stream>,
     stream_config: StreamConfig,
     // This is kept so the thread doesnt become detached
     fft_thread: JoinHandle<()>,
     kill_signal: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
 }
 
 impl AudioProcessor {
-    pub fn new(state: &State) -> Self {
+    pub fn new(state: &State, settings: &AudioSettings, analysis: Analysis) -> Self {
         let dimensions = state.fft_dimensions;
-        // Setup CPAL for recording
-        let host = cpal::default_host();
-        let device = host.default_input_device().unwrap();
-
-        let mut config = device
-            .default_input_config()
-            .expect("Failed to get default input config")
-            .config();
-        let sample_rate = config.sample_rate.0 as f32;
-        let supported = device.default_input_config().unwrap();
-        // TODO: Somethingsfucky about the buffersize
-        let _data_size = supported.sample_format().sample_size();
-        let fft_size = dimensions.fft_size as u32;
-
-        let sr_ms = sample_rate / 1_000.;
-        let sr_us = sr_ms / 1_000.;
-        let fft_delay_us = (fft_size as f32 / sr_us).round() as u128;
-
-        let bz = if let SupportedBufferSize::Range { .. } = supported.buffer_size() {
-            BufferSize::Fixed(fft_size)
-        } else {
-            BufferSize::Default
-        };
-        // TODO: 2 Channels, the data will be interleaved [L, R, L, R]
-        // I think we just combine the data v = 0.5 * (left_v + right_v)
-        // But it requires a larget buffer and so on..
-        config.channels = 1;
-        config.buffer_size = bz;
-        // config.buffer_size = BufferSize::Fixed(FrameCount)
-        let channels = config.channels as usize;
 
         // Ring buffer for communication between CPAL and fft.
         let ring_buffer = HeapRb::<f32>::new(dimensions.ring_size());
@@ -81,52 +168,68 @@ impl AudioProcessor {
         for _ in 0..dimensions.ring_size() {
             producer.push(0.).unwrap();
         }
+        let kill_signal = Arc::new(AtomicBool::from(false));
 
-        // Set up CPAL listening
-        fn err_fn(err: cpal::StreamError) {
-            eprintln!("an error occurred on stream: {}", err);
-        }
-        let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            let mut output_fell_behind = false;
-            for &sample in data {
-                if producer.push(sample).is_err() {
-                    output_fell_behind = true;
-                }
+        let wav = settings.file.as_ref().and_then(|path| {
+            read_wav(path)
+                .map_err(|e| eprintln!("Failed to read {}: {:#}", path.display(), e))
+                .ok()
+        });
+        let (input_stream, config) = match wav {
+            Some(wav) => {
+                let config = StreamConfig {
+                    channels: 1,
+                    sample_rate: cpal::SampleRate(wav.sample_rate),
+                    buffer_size: BufferSize::Default,
+                };
+                let kill_feed = kill_signal.clone();
+                thread::spawn(move || feed_wav(wav, producer, kill_feed));
+                (None, config)
             }
-            if output_fell_behind {
-                eprintln!("Output stream fell behind: try increasing latency");
+            None => {
+                let (stream, config) = listen(settings.device.as_deref(), dimensions, producer);
+                (Some(stream), config)
             }
         };
 
-        let input_stream = device
-            .build_input_stream(&config, input_data_fn, err_fn, None)
-            .unwrap();
-        let _ = input_stream.play();
-
         let fft_stats = Arc::new(Mutex::new(FFTStats::default()));
 
-        let kill_signal = Arc::new(AtomicBool::from(false));
-
         let kill_thread = kill_signal.clone();
+        let paused = Arc::new(AtomicBool::new(false));
+        let thread_paused = paused.clone();
         // Better performance with Arc<[Atomic]> instead of Arc<Mutex>
         let fft_texture: TextureHandle = Arc::new(Mutex::new(vec![0.; dimensions.texture_size()]));
         let thread_fft_tex = fft_texture.clone();
+        let wave_texture: TextureHandle = Arc::new(Mutex::new(vec![0.; dimensions.fft_size]));
+        let thread_wave_tex = wave_texture.clone();
+        let beat = BeatHandle::default();
+        let thread_beat = beat.clone();
+        let analysis = Arc::new(Mutex::new(analysis));
+        let thread_analysis = analysis.clone();
         let fft_thread = thread::spawn(move || {
             fft_analysis(
                 consumer,
                 thread_fft_tex,
+                thread_wave_tex,
+                thread_beat,
+                thread_analysis,
                 config.sample_rate,
                 dimensions,
                 kill_thread,
+                thread_paused,
             );
         });
 
         Self {
+            wave_texture,
             fft_texture,
+            beat,
+            analysis,
             fft_thread,
             input_stream,
             stream_config: config,
             kill_signal,
+            paused,
             fft_stats,
         }
     }
@@ -135,16 +238,54 @@ impl AudioProcessor {
         self.fft_texture.clone()
     }
 
+    pub fn sample_rate(&self) -> u32 {
+        self.stream_config.sample_rate.0
+    }
+
+    pub fn beat(&self) -> Beat {
+        *self.beat.lock().unwrap()
+    }
+
+    /// A beat from outside, detection stays off until `detect_beats`.
+    pub fn external_beat(&self) {
+        let mut beat = self.beat.lock().unwrap();
+        beat.external = true;
+        beat.hit(Instant::now());
+    }
+
+    pub fn detect_beats(&self) {
+        self.beat.lock().unwrap().external = false;
+    }
+
+    pub fn analysis(&self) -> Analysis {
+        *self.analysis.lock().unwrap()
+    }
+
+    /// Takes effect from the next fft run.
+    pub fn set_analysis(&self, analysis: Analysis) {
+        *self.analysis.lock().unwrap() = analysis;
+    }
+
+    /// The raw samples that went into the newest fft run.
+    pub fn wave_texture(&self) -> TextureHandle {
+        self.wave_texture.clone()
+    }
+
     pub fn update(&mut self, state: &State) {
         // Do something about the fft_texture.
     }
 
-    // TODO: Pause and start should just completely reconstruct the inputstream and FFT_analysis
-    // and the ring_buffer.
-    pub fn start(&mut self) {}
-    pub fn pause(&mut self) {
-        let _ = self.input_stream.pause();
-        self.kill_signal.store(true, Ordering::SeqCst);
+    pub fn start(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// The analysis keeps running on silence, so the visuals settle down.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
     }
     pub fn new_analysis_thread(&mut self) {
         //dimensions: &FFTDimensions
@@ -152,6 +293,178 @@ impl AudioProcessor {
     }
 }
 
+/// Sets up CPAL to record from the named input device, or the default one.
+fn listen(
+    name: Option<&str>,
+    dimensions: FFTDimensions,
+    mut producer: Producer<f32, Arc<HeapRb<f32>>>,
+) -> (Stream, StreamConfig) {
+    let host = cpal::default_host();
+    let device = name
+        .and_then(|name| {
+            let device = host
+                .input_devices()
+                .ok()?
+                .find(|d| d.name().is_ok_and(|n| n == name));
+            if device.is_none() {
+                eprintln!("No input device called `{}`, using the default", name);
+            }
+            device
+        })
+        .or_else(|| host.default_input_device())
+        .unwrap();
+
+    let mut config = device
+        .default_input_config()
+        .expect("Failed to get default input config")
+        .config();
+    let sample_rate = config.sample_rate.0 as f32;
+    let supported = device.default_input_config().unwrap();
+    // TODO: Somethingsfucky about the buffersize
+    let _data_size = supported.sample_format().sample_size();
+    let fft_size = dimensions.fft_size as u32;
+
+    let sr_ms = sample_rate / 1_000.;
+    let sr_us = sr_ms / 1_000.;
+    let fft_delay_us = (fft_size as f32 / sr_us).round() as u128;
+
+    let bz = if let SupportedBufferSize::Range { .. } = supported.buffer_size() {
+        BufferSize::Fixed(fft_size)
+    } else {
+        BufferSize::Default
+    };
+    // TODO: 2 Channels, the data will be interleaved [L, R, L, R]
+    // I think we just combine the data v = 0.5 * (left_v + right_v)
+    // But it requires a larget buffer and so on..
+    config.channels = 1;
+    config.buffer_size = bz;
+    // config.buffer_size = BufferSize::Fixed(FrameCount)
+    let channels = config.channels as usize;
+
+    // Set up CPAL listening
+    fn err_fn(err: cpal::StreamError) {
+        eprintln!("an error occurred on stream: {}", err);
+    }
+    let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
+        let mut output_fell_behind = false;
+        for &sample in data {
+            if producer.push(sample).is_err() {
+                output_fell_behind = true;
+            }
+        }
+        if output_fell_behind {
+            eprintln!("Output stream fell behind: try increasing latency");
+        }
+    };
+
+    let input_stream = device
+        .build_input_stream(&config, input_data_fn, err_fn, None)
+        .unwrap();
+    let _ = input_stream.play();
+
+    (input_stream, config)
+}
+
+/// The names of the input devices, for `--audio-device`.
+pub fn input_device_names() -> Vec<String> {
+    cpal::default_host()
+        .input_devices()
+        .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// A wav file mixed down to mono.
+pub struct Wav {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+/// Lower than any real recording, the analysis doesn't work with much less.
+const MIN_SAMPLE_RATE: u32 = 8000;
+
+/// Reads 16 bit, 24 bit and float wav files.
+pub fn read_wav(path: &std::path::Path) -> anyhow::Result<Wav> {
+    let data = std::fs::read(path)?;
+    ensure!(
+        data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE",
+        "not a wav file"
+    );
+    let mut format = None;
+    let mut samples = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into()?) as usize;
+        let chunk = data
+            .get(pos + 8..pos + 8 + len)
+            .context("the file is cut short")?;
+        match id {
+            b"fmt " => {
+                ensure!(chunk.len() >= 16, "the format chunk is too short");
+                let tag = u16::from_le_bytes([chunk[0], chunk[1]]);
+                let channels = u16::from_le_bytes([chunk[2], chunk[3]]) as usize;
+                let sample_rate = u32::from_le_bytes(chunk[4..8].try_into()?);
+                let bits = u16::from_le_bytes([chunk[14], chunk[15]]);
+                format = Some((tag, channels.max(1), sample_rate, bits));
+            }
+            b"data" => samples = Some(chunk),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + len + (len & 1);
+    }
+    let (tag, channels, sample_rate, bits) = format.context("no format chunk")?;
+    ensure!(
+        sample_rate >= MIN_SAMPLE_RATE,
+        "the sample rate is {} Hz, it has to be at least {}",
+        sample_rate,
+        MIN_SAMPLE_RATE
+    );
+    let samples = samples.context("no data chunk")?;
+
+    // 0xFFFE is the extensible format, which is assumed to hold plain samples too.
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (1 | 0xFFFE, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.,
+        (1 | 0xFFFE, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.,
+        (3 | 0xFFFE, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        _ => bail!("unsupported wav format {} with {} bits", tag, bits),
+    };
+    let frame_len = channels * bits as usize / 8;
+    let samples = samples
+        .chunks_exact(frame_len)
+        .map(|frame| {
+            frame
+                .chunks_exact(bits as usize / 8)
+                .map(decode)
+                .sum::<f32>()
+                / channels as f32
+        })
+        .collect();
+    Ok(Wav {
+        sample_rate,
+        samples,
+    })
+}
+
+/// Pushes the file into the ring buffer as fast as it would play, on repeat.
+fn feed_wav(wav: Wav, mut producer: Producer<f32, Arc<HeapRb<f32>>>, kill_signal: Arc<AtomicBool>) {
+    if wav.samples.is_empty() {
+        return;
+    }
+    let mut samples = wav.samples.iter().cycle();
+    let start = Instant::now();
+    let mut pushed = 0u64;
+    while !kill_signal.load(Ordering::SeqCst) {
+        let due = (start.elapsed().as_secs_f64() * wav.sample_rate as f64) as u64;
+        while pushed < due {
+            // Like a device would, drop what the analysis can't keep up with.
+            let _ = producer.push(*samples.next().unwrap());
+            pushed += 1;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
 // Some different windows to preprocessing.
 
 pub fn blackman_single(sample: f32, n: f32, len: f32) -> f32 {
@@ -171,25 +484,154 @@ pub fn hann_single(sample: f32, i: usize, samples_len: usize) -> f32 {
     multiplier * sample
 }
 
+/// Energy based beat detection on the low end, a beat is when the energy of
+/// the newest run stands out from the last second or so.
+/// https://mziccard.me/2015/05/28/beats-detection-algorithms-1
+struct BeatDetector {
+    history: VecDeque<f32>,
+    history_len: usize,
+    // Counted in runs so it works the same faster than real time.
+    runs_since_beat: usize,
+}
+
+impl BeatDetector {
+    fn new(history_len: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(history_len + 1),
+            history_len,
+            runs_since_beat: 0,
+        }
+    }
+
+    fn detect(&mut self, energy: f32) -> bool {
+        // How much louder than the average counts as a beat.
+        const SENSITIVITY: f32 = 1.4;
+        // Ignore beats in near silence.
+        const MIN_ENERGY: f32 = 1e-6;
+        // Faster than 300 bpm is probably noise, the history is a second long.
+        let min_interval = self.history_len / 5;
+
+        let average = self.history.iter().sum::<f32>() / self.history.len().max(1) as f32;
+        let is_beat = self.history.len() == self.history_len
+            && energy > SENSITIVITY * average
+            && energy > MIN_ENERGY
+            && self.runs_since_beat > min_interval;
+
+        self.history.push_back(energy);
+        if self.history.len() > self.history_len {
+            self.history.pop_front();
+        }
+        self.runs_since_beat += 1;
+        if is_beat {
+            self.runs_since_beat = 0;
+        }
+        is_beat
+    }
+}
+
+/// Turns one fft run at a time into the newest row of the fft texture. Used by the analysis
+/// thread and by the thumbnails, which go through a clip faster than real time.
+pub struct Analyzer {
+    dimensions: FFTDimensions,
+    fft: Arc<dyn rustfft::Fft<f32>>,
+    scratch: Vec<Complex32>,
+    amplitudes: Vec<f32>,
+    fft_buf: Vec<Complex32>,
+    bass_bins: usize,
+    beat_detector: BeatDetector,
+}
+
+impl Analyzer {
+    pub fn new(dimensions: FFTDimensions, sample_rate: u32) -> Self {
+        let fft_size = dimensions.fft_size;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        // Kick drums and bass live below ~150Hz, skip the DC bin.
+        let bass_bins = ((150. * fft_size as f32 / sample_rate.max(1) as f32).ceil() as usize)
+            .clamp(1, fft_size / 2);
+        let runs_per_second = sample_rate as usize / fft_size;
+        Self {
+            dimensions,
+            scratch: vec![Complex32::default(); fft.get_inplace_scratch_len()],
+            fft,
+            amplitudes: vec![0.; fft_size / 2],
+            fft_buf: vec![Complex32::default(); fft_size],
+            bass_bins,
+            beat_detector: BeatDetector::new(runs_per_second.max(1)),
+        }
+    }
+
+    /// Runs the fft over `wave`, `fft_size` samples, returns whether there's a beat.
+    pub fn process(&mut self, wave: &[f32]) -> bool {
+        let fft_size = self.dimensions.fft_size;
+        for (i, x) in wave.iter().enumerate() {
+            // Apply windowing function to the input
+            self.fft_buf[i] = Complex32::new(blackman_single(*x, i as f32, fft_size as f32), 0.);
+        }
+        self.fft
+            .process_with_scratch(&mut self.fft_buf, &mut self.scratch);
+
+        let bass_energy = self.fft_buf[1..=self.bass_bins]
+            .iter()
+            .map(|c| (c / fft_size as f32).norm_sqr())
+            .sum::<f32>();
+        self.beat_detector.detect(bass_energy)
+    }
+
+    /// Writes the last run to the texture with the analysis settings.
+    pub fn write_texture(&mut self, texture: &mut [f32], analysis: Analysis) {
+        let fft_size = self.dimensions.fft_size;
+        let texture_width = self.dimensions.texture_width() as usize;
+        // The buffer has the last TEXTURE_HEIGHT fft runs.
+        // With the first TEXTURE_WIDTH elements being the newest run.
+        // So rotate the elements back one TEXTURE_WIDTH and write the
+        // new run to the buffer at the front.
+        texture.rotate_right(texture_width);
+
+        let freq_amp = self.fft_buf.iter().take(texture_width).enumerate();
+        for (i, amp) in freq_amp {
+            let amp = amp / fft_size as f32;
+            let amp_prev = self.amplitudes[i];
+            let amp = analysis.smoothing * amp_prev + (1. - analysis.smoothing) * amp.norm();
+            self.amplitudes[i] = amp;
+        }
+        // Texels that share bins get the loudest one.
+        for (x, texel) in texture[..texture_width].iter_mut().enumerate() {
+            let bins = analysis.binning.bins(x, texture_width);
+            *texel = self.amplitudes[bins].iter().copied().fold(0., f32::max);
+        }
+        // Kept apart so a range set backwards doesn't divide by zero.
+        let db_lo = analysis.db_min.min(analysis.db_max - 1.);
+        let db_hi = analysis.db_max;
+        for amp in texture.iter_mut().take(texture_width) {
+            let db = 20. * f32::log10(*amp);
+            let db = db.clamp(db_lo, db_hi);
+            let normalized = (db - db_lo) / (db_hi - db_lo);
+            *amp = normalized;
+        }
+    }
+}
+
 // The main function that analysis the audio data
 
+#[allow(clippy::too_many_arguments)]
 fn fft_analysis(
     mut consumer: Consumer<f32, Arc<HeapRb<f32>>>,
     texture_handle: TextureHandle,
+    wave_handle: TextureHandle,
+    beat_handle: BeatHandle,
+    analysis_handle: AnalysisHandle,
     cpal::SampleRate(sample_rate): cpal::SampleRate,
     dimensions: FFTDimensions,
     kill_signal: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
 ) {
     let fft_size = dimensions.fft_size;
     let sr_ms = sample_rate as f32 / 1_000.;
     let sr_us = sr_ms / 1_000.;
     let fft_delay_us = (fft_size as f32 / sr_us).round() as u128;
-    let mut planner = FftPlanner::new();
-    let fft = planner.plan_fft_forward(fft_size);
-    let mut scratch = vec![Complex32::default(); fft.get_inplace_scratch_len()];
-
-    let mut amplitudes: Vec<f32> = vec![0.; fft_size / 2];
-    let mut fft_buf: Vec<Complex32> = vec![Complex32::default(); fft_size];
+    let mut analyzer = Analyzer::new(dimensions, sample_rate);
+    let mut wave_buf: Vec<f32> = vec![0.; fft_size];
     let mut timer = Instant::now();
 
     loop {
@@ -203,60 +645,46 @@ fn fft_analysis(
             // let time_drift = elapsed - fft_delay_us;
 
             let mut input_fell_behind = false;
-            for i in 0..fft_size {
-                let x = match consumer.pop() {
+            for x in wave_buf.iter_mut() {
+                *x = match consumer.pop() {
                     Some(s) => s,
                     None => {
                         input_fell_behind = true;
                         0.
                     }
                 };
-                // Apply windowing function to the input
-                fft_buf[i] = Complex32::new(blackman_single(x, i as f32, fft_size as f32), 0.);
             }
 
             if input_fell_behind {
                 eprintln!("Input stream fell behind: try increasing latency");
             }
+            // Still taken from the ring so it doesn't fill up.
+            if paused.load(Ordering::SeqCst) {
+                wave_buf.fill(0.);
+            }
+
+            let is_beat = analyzer.process(&wave_buf);
+
+            // Don't wait for the renderer, it's fine to skip a wave.
+            if let Ok(mut wave) = wave_handle.try_lock() {
+                wave.copy_from_slice(&wave_buf);
+            }
 
-            fft.process_with_scratch(&mut fft_buf, &mut scratch);
+            if is_beat {
+                let mut beat = beat_handle.lock().unwrap();
+                if !beat.external {
+                    beat.hit(Instant::now());
+                }
+            }
 
             // let _bin_freq = sample_rate / fft_size as f32;
 
+            let analysis = *analysis_handle.lock().unwrap();
             let Ok(mut texture) = texture_handle.lock() else {
                 panic!("TEXTURE MUTEX FFT SIDE");
             };
-
-            let texture_width = dimensions.texture_width() as usize;
             // TODO: Maybe move this out of this loop and to the main thread?
-            // The buffer has the last TEXTURE_HEIGHT fft runs.
-            // With the first TEXTURE_WIDTH elements being the newest run.
-            // So rotate the elements back one TEXTURE_WIDTH and write the
-            // new run to the buffer at the front.
-            texture.rotate_right(texture_width);
-            texture[..texture_width].copy_from_slice(&vec![0.; texture_width]);
-
-            let freq_amp = fft_buf.iter().take(texture_width).enumerate();
-            for (i, amp) in freq_amp {
-                let amp = amp / fft_size as f32;
-                let amp_prev = amplitudes[i];
-                let amp =
-                    dimensions.smoothing * amp_prev + (1. - dimensions.smoothing) * amp.norm();
-                amplitudes[i] = amp;
-
-                if i < texture_width {
-                    texture[i] += amp;
-                }
-            }
-            // TODO Move to config.
-            const DB_LO: f32 = -100.;
-            const DB_HI: f32 = -10.;
-            for amp in texture.iter_mut().take(texture_width) {
-                let db = 20. * f32::log10(*amp);
-                let db = db.clamp(DB_LO, DB_HI);
-                let normalized = (db - DB_LO) / (DB_HI - DB_LO);
-                *amp = normalized;
-            }
+            analyzer.write_texture(&mut texture, analysis);
 
             // Done with the texture so drop it so the rendering can use it.
             drop(texture);
@@ -275,3 +703,38 @@ fn fft_analysis(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_bins_cover_the_row() {
+        let width = 512;
+        let mut next = 1;
+        for x in 0..width {
+            let bins = Binning::Log.bins(x, width);
+            assert!(!bins.is_empty() && bins.end <= width, "{} {:?}", x, bins);
+            // Texels can share a bin in the bass, but no bin is skipped.
+            assert!(bins.start <= next, "{} {:?}", x, bins);
+            next = next.max(bins.end);
+        }
+        assert_eq!(next, width);
+    }
+
+    #[test]
+    fn analysis_ranges() {
+        assert!(Analysis::default().check().is_ok());
+        let backwards = Analysis {
+            db_min: -10.,
+            db_max: -100.,
+            ..Analysis::default()
+        };
+        assert!(backwards.check().is_err());
+        let stuck = Analysis {
+            smoothing: 1.0,
+            ..Analysis::default()
+        };
+        assert!(stuck.check().is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::program::PassResources;
+use crate::shaders::Vertex;
+
+/// How the frame is scaled to the surface when it's rendered at a different size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpscaleFilter {
+    Nearest,
+    #[default]
+    Linear,
+    Bicubic,
+}
+
+impl UpscaleFilter {
+    pub const ALL: [UpscaleFilter; 3] = [
+        UpscaleFilter::Nearest,
+        UpscaleFilter::Linear,
+        UpscaleFilter::Bicubic,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpscaleFilter::Nearest => "Nearest",
+            UpscaleFilter::Linear => "Linear",
+            UpscaleFilter::Bicubic => "Bicubic",
+        }
+    }
+}
+
+/// How colors above 1 are brought into the range of the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    /// Clips at 1.
+    #[default]
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl Tonemap {
+    pub const ALL: [Tonemap; 3] = [Tonemap::None, Tonemap::Reinhard, Tonemap::Aces];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tonemap::None => "None",
+            Tonemap::Reinhard => "Reinhard",
+            Tonemap::Aces => "ACES",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlitUniform {
+    tonemap: u32,
+    // Unorm targets get sRGB encoded in the shader, sRGB ones by the gpu.
+    encode_srgb: u32,
+    _padding: [u32; 2],
+}
+
+/// Draws a texture over a whole target, used to put the offscreen frame on the surface.
+/// The frame is linear, it's tonemapped and encoded for the format of the target.
+pub struct Blitter {
+    pipeline: wgpu::RenderPipeline,
+    bicubic_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    nearest_sampler: wgpu::Sampler,
+    linear_sampler: wgpu::Sampler,
+    encode_srgb: bool,
+}
+
+impl Blitter {
+    /// `transparent` puts out alpha for a transparent window, see `DisplaySettings`.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, transparent: bool) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("blit_shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("blit.wgsl"))),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("blit_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("blit_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let make_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("blit_pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[Vertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        let make_sampler = |filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("blit_sampler"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            })
+        };
+
+        let (main, bicubic) = if transparent {
+            ("fs_main_overlay", "fs_bicubic_overlay")
+        } else {
+            ("fs_main", "fs_bicubic")
+        };
+        Self {
+            pipeline: make_pipeline(main),
+            bicubic_pipeline: make_pipeline(bicubic),
+            bind_group_layout,
+            nearest_sampler: make_sampler(wgpu::FilterMode::Nearest),
+            linear_sampler: make_sampler(wgpu::FilterMode::Linear),
+            encode_srgb: !format.describe().srgb,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn blit(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        filter: UpscaleFilter,
+        tonemap: Tonemap,
+    ) {
+        let (pipeline, sampler) = match filter {
+            UpscaleFilter::Nearest => (&self.pipeline, &self.nearest_sampler),
+            UpscaleFilter::Linear => (&self.pipeline, &self.linear_sampler),
+            UpscaleFilter::Bicubic => (&self.bicubic_pipeline, &self.linear_sampler),
+        };
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Blit Buffer"),
+            contents: bytemuck::cast_slice(&[BlitUniform {
+                tonemap: tonemap as u32,
+                encode_srgb: self.encode_srgb as u32,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("blit_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        resources.draw_quad(&mut render_pass);
+    }
+}
@@ -0,0 +1,119 @@
+// Copies a finished frame onto the surface, tonemapped and encoded for it.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+	@location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+	@location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+struct BlitUniform {
+	// 0 none, 1 Reinhard, 2 ACES, like `Tonemap`.
+	tonemap: u32,
+	// sRGB targets are encoded by the gpu.
+	encode_srgb: u32,
+};
+
+@group(0) @binding(2)
+var<uniform> blit: BlitUniform;
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+	return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+fn linear_to_srgb(x: vec3<f32>) -> vec3<f32> {
+	let low = x * 12.92;
+	let high = 1.055 * pow(x, vec3<f32>(1.0 / 2.4)) - 0.055;
+	return select(high, low, x <= vec3<f32>(0.0031308));
+}
+
+// The frame is linear and can go above 1.
+fn finish(color: vec4<f32>) -> vec4<f32> {
+	var rgb = max(color.rgb, vec3<f32>(0.0));
+	if blit.tonemap == 1u {
+		rgb = rgb / (1.0 + rgb);
+	} else if blit.tonemap == 2u {
+		rgb = aces(rgb);
+	}
+	rgb = clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+	if blit.encode_srgb != 0u {
+		rgb = linear_to_srgb(rgb);
+	}
+	return vec4<f32>(rgb, color.a);
+}
+
+// Nearest or linear, depending on the sampler.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+	return finish(textureSample(source, source_sampler, in.tex_coords));
+}
+
+// Catmull-Rom with 9 linear samples instead of 16 point samples, sharper than linear when upscaling.
+// From https://gist.github.com/TheRealMJP/c83b8c0f46b63f3a88a5986f4fa982b1
+fn bicubic(uv: vec2<f32>) -> vec4<f32> {
+	let size = vec2<f32>(textureDimensions(source));
+	let sample_pos = uv * size;
+	let tex_pos1 = floor(sample_pos - 0.5) + 0.5;
+	let f = sample_pos - tex_pos1;
+
+	let w0 = f * (-0.5 + f * (1.0 - 0.5 * f));
+	let w1 = 1.0 + f * f * (-2.5 + 1.5 * f);
+	let w2 = f * (0.5 + f * (2.0 - 1.5 * f));
+	let w3 = f * f * (-0.5 + 0.5 * f);
+
+	// The middle two taps are merged into one linear sample.
+	let w12 = w1 + w2;
+	let tex_pos0 = (tex_pos1 - 1.0) / size;
+	let tex_pos3 = (tex_pos1 + 2.0) / size;
+	let tex_pos12 = (tex_pos1 + w2 / w12) / size;
+
+	var result = vec4<f32>(0.0);
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos0.x, tex_pos0.y)) * w0.x * w0.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos12.x, tex_pos0.y)) * w12.x * w0.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos3.x, tex_pos0.y)) * w3.x * w0.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos0.x, tex_pos12.y)) * w0.x * w12.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos12.x, tex_pos12.y)) * w12.x * w12.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos3.x, tex_pos12.y)) * w3.x * w12.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos0.x, tex_pos3.y)) * w0.x * w3.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos12.x, tex_pos3.y)) * w12.x * w3.y;
+	result += textureSample(source, source_sampler, vec2<f32>(tex_pos3.x, tex_pos3.y)) * w3.x * w3.y;
+	// The negative lobes can overshoot.
+	return max(result, vec4<f32>(0.0));
+}
+
+@fragment
+fn fs_bicubic(in: VertexOutput) -> @location(0) vec4<f32> {
+	return finish(bicubic(in.tex_coords));
+}
+
+// For a transparent window, black is see-through and the rest is premultiplied by its brightest channel.
+fn overlay(color: vec4<f32>) -> vec4<f32> {
+	return vec4<f32>(color.rgb, max(color.r, max(color.g, color.b)));
+}
+
+@fragment
+fn fs_main_overlay(in: VertexOutput) -> @location(0) vec4<f32> {
+	return overlay(finish(textureSample(source, source_sampler, in.tex_coords)));
+}
+
+@fragment
+fn fs_bicubic_overlay(in: VertexOutput) -> @location(0) vec4<f32> {
+	return overlay(finish(bicubic(in.tex_coords)));
+}
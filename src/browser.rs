@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::shader_meta::ShaderInfo;
+use crate::shaders;
+
+/// Whether the shader builds, checked in the background without a device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Checking,
+    Ok,
+    Failed(String),
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    /// The path in the shader directory like `tunnels/warp.wgsl`, favorites and ratings go by it.
+    pub key: String,
+    /// The subdirectory it's in, empty at the top.
+    pub category: String,
+    pub name: String,
+    pub info: ShaderInfo,
+    pub status: Status,
+}
+
+impl Entry {
+    fn matches(&self, search: &str) -> bool {
+        let info = &self.info;
+        [
+            Some(&self.name),
+            Some(&self.category),
+            info.author.as_ref(),
+            info.description.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|text| text.to_lowercase().contains(search))
+    }
+}
+
+/// The shaders in the shader directory, subdirectories are categories.
+pub struct ShaderBrowser {
+    pub dir: PathBuf,
+    /// Sorted by path, the order the keys step through them.
+    pub list: Vec<PathBuf>,
+    pub entries: Vec<Entry>,
+    pub search: String,
+    pub favorites_only: bool,
+    pub favorites: BTreeSet<String>,
+    /// From 1 to 5 stars, unrated ones aren't in here.
+    pub ratings: BTreeMap<String, u8>,
+    checked: Option<Receiver<(PathBuf, Status)>>,
+}
+
+impl ShaderBrowser {
+    pub fn new(dir: &Path, favorites: BTreeSet<String>, ratings: BTreeMap<String, u8>) -> Self {
+        let mut browser = Self {
+            dir: dir.to_path_buf(),
+            list: vec![],
+            entries: vec![],
+            search: String::new(),
+            favorites_only: false,
+            favorites,
+            ratings,
+            checked: None,
+        };
+        browser.refresh();
+        browser
+    }
+
+    /// Lists the directory again and checks every shader over, the last status stays up
+    /// until the new one is in.
+    pub fn refresh(&mut self) {
+        self.list = shaders::list_shaders(&self.dir).unwrap_or_default();
+        let mut old: BTreeMap<PathBuf, Status> = self
+            .entries
+            .drain(..)
+            .map(|entry| (entry.path, entry.status))
+            .collect();
+        self.entries = self
+            .list
+            .iter()
+            .map(|path| {
+                let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+                let category = relative.parent().map(key).unwrap_or_default();
+                let src = fs::read_to_string(path).unwrap_or_default();
+                Entry {
+                    path: path.clone(),
+                    key: key(relative),
+                    category,
+                    name: relative
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    info: ShaderInfo::parse(&src),
+                    status: old.remove(path).unwrap_or(Status::Checking),
+                }
+            })
+            .collect();
+
+        let (sender, receiver) = mpsc::channel();
+        let paths = self.list.clone();
+        let dir = self.dir.clone();
+        thread::spawn(move || {
+            for path in paths {
+                let status = match shaders::check(&path, &dir) {
+                    Ok(()) => Status::Ok,
+                    Err(e) => Status::Failed(format!("{:#}", e)),
+                };
+                // The browser was refreshed again in the meantime.
+                if sender.send((path, status)).is_err() {
+                    return;
+                }
+            }
+        });
+        self.checked = Some(receiver);
+    }
+
+    /// Takes in the statuses that were checked since the last call.
+    pub fn update(&mut self) {
+        let Some(checked) = &self.checked else {
+            return;
+        };
+        for (path, status) in checked.try_iter() {
+            if let Some(entry) = self.entries.iter_mut().find(|e| e.path == path) {
+                entry.status = status;
+            }
+        }
+    }
+
+    /// The entries that pass the search and the favorites filter, by category.
+    /// The ones at the top come first, under an empty category.
+    pub fn filtered(&self) -> BTreeMap<&str, Vec<&Entry>> {
+        let search = self.search.trim().to_lowercase();
+        let mut categories: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+        for entry in &self.entries {
+            if self.favorites_only && !self.favorites.contains(&entry.key) {
+                continue;
+            }
+            if !search.is_empty() && !entry.matches(&search) {
+                continue;
+            }
+            categories.entry(&entry.category).or_default().push(entry);
+        }
+        categories
+    }
+
+    pub fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    pub fn toggle_favorite(&mut self, key: &str) {
+        if !self.favorites.remove(key) {
+            self.favorites.insert(key.to_string());
+        }
+    }
+
+    pub fn rating(&self, key: &str) -> u8 {
+        self.ratings.get(key).copied().unwrap_or(0)
+    }
+
+    /// 0 takes the rating away.
+    pub fn rate(&mut self, key: &str, stars: u8) {
+        if stars == 0 {
+            self.ratings.remove(key);
+        } else {
+            self.ratings.insert(key.to_string(), stars.min(5));
+        }
+    }
+}
+
+/// With `/` between the directories on every platform, so the settings carry over.
+fn key(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
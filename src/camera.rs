@@ -1,10 +1,55 @@
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use std::time::Instant;
+
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
 
 #[repr(C)]
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    // w is always 1.
+    pub eye: glam::Vec4,
+    // `target` is reserved in WGSL.
+    pub look_at: glam::Vec4,
     pub view_proj: glam::Mat4,
+    // For turning screen positions back into rays.
+    pub inv_view_proj: glam::Mat4,
+}
+
+impl CameraUniform {
+    pub fn new(camera: &Camera) -> Self {
+        let view_proj = camera.build_view_projection_matrix();
+        Self {
+            eye: camera.eye.extend(1.0),
+            look_at: camera.target.extend(1.0),
+            view_proj,
+            inv_view_proj: view_proj.inverse(),
+        }
+    }
+}
+
+/// How the camera moves, shaders pick one with `//! camera <mode>`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Drag to orbit around the target and scroll to zoom, WASD works too.
+    #[default]
+    Orbit,
+    /// WASD to move, space and shift for up and down, drag to look around.
+    Fly,
+    /// Circles the target by itself, sped up and pulled in by the beat.
+    Audio,
+}
+
+impl CameraMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "orbit" => Some(CameraMode::Orbit),
+            "fly" => Some(CameraMode::Fly),
+            "audio" => Some(CameraMode::Audio),
+            _ => None,
+        }
+    }
 }
 
 pub struct Camera {
@@ -18,36 +63,79 @@ pub struct Camera {
 }
 
 impl Camera {
+    pub fn new(aspect: f32) -> Self {
+        Self {
+            eye: glam::vec3(0.0, 1.0, -4.0),
+            target: glam::Vec3::ZERO,
+            up: glam::Vec3::Y,
+            aspect,
+            fov_y: 50f32.to_radians(),
+            z_near: 0.1,
+            z_far: 100.0,
+        }
+    }
+
     pub fn build_view_projection_matrix(&self) -> glam::Mat4 {
         let view = glam::Mat4::look_at_rh(self.eye, self.target, self.up);
         let proj = glam::Mat4::perspective_rh(self.fov_y, self.aspect, self.z_near, self.z_far);
-        return proj * view;
+        proj * view
     }
 }
 
+/// Radians per pixel dragged.
+const ROTATE_SPEED: f32 = 0.005;
+
 pub struct CameraController {
+    /// Units per second.
     speed: f32,
+    mode: CameraMode,
     is_up_pressed: bool,
     is_down_pressed: bool,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+
+    is_dragging: bool,
+    cursor: Option<glam::Vec2>,
+    // Mouse input since the last update, in pixels and scroll lines.
+    drag: glam::Vec2,
+    scroll: f32,
+
+    // The audio path.
+    phase: f32,
+    radius: f32,
+    last_update: Instant,
 }
 
 impl CameraController {
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
+            mode: CameraMode::default(),
             is_up_pressed: false,
             is_down_pressed: false,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_dragging: false,
+            cursor: None,
+            drag: glam::Vec2::ZERO,
+            scroll: 0.0,
+            phase: 0.0,
+            radius: 4.0,
+            last_update: Instant::now(),
         }
     }
 
+    /// Switches the mode, the audio path circles at the cameras current distance to the target.
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &Camera) {
+        self.mode = mode;
+        self.phase = 0.0;
+        self.radius = camera.eye.distance(camera.target);
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -88,22 +176,96 @@ impl CameraController {
                     _ => false,
                 }
             }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.is_dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = glam::vec2(position.x as f32, position.y as f32);
+                if let (true, Some(cursor)) = (self.is_dragging, self.cursor) {
+                    self.drag += position - cursor;
+                }
+                self.cursor = Some(position);
+                self.is_dragging
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Roughly one line.
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 50.0,
+                };
+                true
+            }
             _ => false,
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    /// `beat` is the beat pulse, it only matters for the audio path.
+    pub fn update_camera(&mut self, camera: &mut Camera, beat: f32) {
+        // Clamped so a hitch doesn't send the camera flying.
+        let dt = self.last_update.elapsed().as_secs_f32().min(0.1);
+        self.last_update = Instant::now();
+        let step = self.speed * dt;
+        let (yaw, pitch) = (-self.drag.x * ROTATE_SPEED, -self.drag.y * ROTATE_SPEED);
+        // Every line scrolled gets 10% closer.
+        let zoom = 0.9f32.powf(self.scroll);
+        self.drag = glam::Vec2::ZERO;
+        self.scroll = 0.0;
+
+        match self.mode {
+            CameraMode::Orbit => {
+                self.update_orbit(camera, step);
+                let offset = rotate(camera.eye - camera.target, camera.up, yaw, pitch) * zoom;
+                camera.eye = camera.target + offset.clamp_length_min(camera.z_near);
+            }
+            CameraMode::Fly => {
+                let forward = (camera.target - camera.eye).normalize();
+                let right = forward.cross(camera.up).normalize();
+                let axis = |pos: bool, neg: bool| pos as i32 as f32 - neg as i32 as f32;
+                let movement = forward * axis(self.is_forward_pressed, self.is_backward_pressed)
+                    + right * axis(self.is_right_pressed, self.is_left_pressed)
+                    + camera.up * axis(self.is_up_pressed, self.is_down_pressed);
+                // Scrolling moves forward too, in steps of half a unit.
+                let delta = movement * step + forward * (1.0 - zoom) * 5.0;
+                camera.eye += delta;
+                camera.target = camera.eye + rotate(forward, camera.up, yaw, pitch);
+            }
+            CameraMode::Audio => {
+                self.radius = (self.radius * zoom).max(camera.z_near);
+                self.phase += dt * (0.2 + 2.0 * beat);
+                let radius = self.radius * (1.0 - 0.15 * beat);
+                let offset = glam::vec3(
+                    self.phase.sin(),
+                    0.4 * (self.phase * 0.7).sin(),
+                    -self.phase.cos(),
+                );
+                camera.eye = camera.target + offset.normalize() * radius;
+            }
+        }
+    }
+
+    fn update_orbit(&self, camera: &mut Camera, step: f32) {
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.length();
 
         // Prevents glitching when camera gets too close to the
         // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+        if self.is_forward_pressed && forward_mag > step {
+            camera.eye += forward_norm * step;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+            camera.eye -= forward_norm * step;
+        }
+        if self.is_up_pressed {
+            camera.eye += camera.up * step;
+        }
+        if self.is_down_pressed {
+            camera.eye -= camera.up * step;
         }
 
         let right = forward_norm.cross(camera.up);
@@ -116,11 +278,24 @@ impl CameraController {
             // Rescale the distance between the target and eye so
             // that it doesn't change. The eye therefore still
             // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward + right * step).normalize() * forward_mag;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward - right * step).normalize() * forward_mag;
         }
     }
 }
 
+/// Turns `v` around `up` by `yaw` and then up or down by `pitch`,
+/// stopping short of pointing straight along `up`.
+fn rotate(v: glam::Vec3, up: glam::Vec3, yaw: f32, pitch: f32) -> glam::Vec3 {
+    let v = glam::Quat::from_axis_angle(up, yaw) * v;
+    let right = v.cross(up).normalize_or_zero();
+    let pitched = glam::Quat::from_axis_angle(right, pitch) * v;
+    let angle = pitched.angle_between(up);
+    if angle > 0.05 && angle < std::f32::consts::PI - 0.05 {
+        pitched
+    } else {
+        v
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::blit::{Blitter, Tonemap, UpscaleFilter};
+use crate::program::PassResources;
+use crate::render_target::RenderTarget;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Frames copied out but not read back yet, recording skips frames past this.
+const MAX_IN_FLIGHT: usize = 4;
+const CAPTURE_DIR: &str = "captures";
+
+/// Screenshots and a rolling recording of the last few seconds, of the frame without the ui.
+/// Frames are copied into buffers that are read back a frame or two later, without stalling.
+pub struct Capture {
+    /// Keeps the last `seconds` of frames in memory while on.
+    pub recording: bool,
+    pub seconds: f32,
+    /// How many frames a second get recorded.
+    pub fps: f32,
+    // The frame tonemapped and encoded to 8 bit sRGB, like on the screen.
+    target: Option<RenderTarget>,
+    blitter: Blitter,
+    // Where the next frame is saved to.
+    screenshot_requested: Option<PathBuf>,
+    record_requested: bool,
+    last_recorded: Option<Instant>,
+    free: Vec<wgpu::Buffer>,
+    in_flight: Vec<Readback>,
+    frames: VecDeque<(Instant, image::RgbaImage)>,
+    saving: Vec<JoinHandle<()>>,
+}
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    size: wgpu::Extent3d,
+    time: Instant,
+    screenshot: Option<PathBuf>,
+    record: bool,
+    mapping: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+impl Capture {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            recording: false,
+            seconds: 5.0,
+            fps: 30.0,
+            target: None,
+            blitter: Blitter::new(device, FORMAT, false),
+            screenshot_requested: None,
+            record_requested: false,
+            last_recorded: None,
+            free: vec![],
+            in_flight: vec![],
+            frames: VecDeque::new(),
+            saving: vec![],
+        }
+    }
+
+    /// Saves the next frame to a png in `captures/`.
+    pub fn screenshot(&mut self) {
+        let path = Path::new(CAPTURE_DIR).join(format!("screenshot_{}.png", timestamp()));
+        self.screenshot_to(path);
+    }
+
+    pub fn screenshot_to(&mut self, path: PathBuf) {
+        self.screenshot_requested = Some(path);
+    }
+
+    /// Adds the next frame to the recording, whether it's on or not.
+    pub fn record_next(&mut self) {
+        self.record_requested = true;
+    }
+
+    /// The recorded frames, oldest first, and starts over.
+    pub fn take_recording(&mut self) -> Vec<image::RgbaImage> {
+        std::mem::take(&mut self.frames)
+            .into_iter()
+            .map(|(_, image)| image)
+            .collect()
+    }
+
+    /// Waits for the frames still being read back and the screenshots still being saved.
+    pub fn finish(&mut self, device: &wgpu::Device) {
+        while !self.in_flight.is_empty() {
+            device.poll(wgpu::Maintain::Wait);
+            self.poll(device);
+        }
+        for saving in self.saving.drain(..) {
+            let _ = saving.join();
+        }
+    }
+
+    /// The frames recorded so far and their size in bytes.
+    pub fn recorded(&self) -> (usize, usize) {
+        let bytes = self.frames.iter().map(|(_, image)| image.len()).sum();
+        (self.frames.len(), bytes)
+    }
+
+    /// Writes the recording to a numbered png sequence on another thread and starts over.
+    pub fn dump(&mut self) {
+        if self.frames.is_empty() {
+            eprintln!("Nothing recorded to save");
+            return;
+        }
+        let dir = Path::new(CAPTURE_DIR).join(format!("recording_{}", timestamp()));
+        let frames = std::mem::take(&mut self.frames);
+        std::thread::spawn(move || {
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                eprintln!("Failed to create {}: {}", dir.display(), e);
+                return;
+            }
+            let count = frames.len();
+            for (i, (_, image)) in frames.into_iter().enumerate() {
+                let path = dir.join(format!("{:05}.png", i));
+                if let Err(e) = image.save(&path) {
+                    eprintln!("Failed to save {}: {}", path.display(), e);
+                    return;
+                }
+            }
+            println!("Saved {} frames to {}", count, dir.display());
+        });
+    }
+
+    /// Reads back the frames that are ready, call once a frame.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if self.in_flight.is_empty() {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        let (ready, waiting) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|r| r.mapped.load(Ordering::Acquire));
+        self.in_flight = waiting;
+        self.saving.retain(|saving| !saving.is_finished());
+
+        for readback in ready {
+            let image = readback.image();
+            readback.buffer.unmap();
+            if readback.size == self.target.as_ref().map(|t| t.size).unwrap_or_default() {
+                self.free.push(readback.buffer);
+            }
+            if let Some(path) = readback.screenshot {
+                self.saving.push(save_png(path, image.clone()));
+            }
+            if readback.record {
+                self.frames.push_back((readback.time, image));
+            }
+        }
+
+        let keep = Duration::from_secs_f32(self.seconds.max(0.0));
+        while let Some((time, _)) = self.frames.front() {
+            if time.elapsed() <= keep {
+                break;
+            }
+            self.frames.pop_front();
+        }
+    }
+
+    /// Copies `frame` out if there's a screenshot to take or a frame to record.
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        frame: &RenderTarget,
+        tonemap: Tonemap,
+    ) {
+        let now = Instant::now();
+        let record = self.record_requested
+            || self.recording
+                && self.last_recorded.is_none_or(|last| {
+                    now.duration_since(last).as_secs_f32() >= 1.0 / self.fps.max(1.0)
+                });
+        let screenshot = self.screenshot_requested.is_some();
+        if !(record || screenshot) || self.in_flight.len() >= MAX_IN_FLIGHT {
+            return;
+        }
+
+        if self.target.as_ref().map(|t| t.size) != Some(frame.size) {
+            self.target = Some(RenderTarget::new(
+                device,
+                "capture_target",
+                frame.size.width,
+                frame.size.height,
+                FORMAT,
+            ));
+            self.free.clear();
+        }
+        let target = self.target.as_ref().unwrap();
+        self.blitter.blit(
+            device,
+            encoder,
+            resources,
+            &frame.view,
+            &target.view,
+            UpscaleFilter::Nearest,
+            tonemap,
+        );
+
+        let buffer = self.free.pop().unwrap_or_else(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Capture Buffer"),
+                size: (padded_row(target.size.width) * target.size.height) as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        });
+        encoder.copy_texture_to_buffer(
+            target.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row(target.size.width)),
+                    rows_per_image: None,
+                },
+            },
+            target.size,
+        );
+
+        self.in_flight.push(Readback {
+            buffer,
+            size: target.size,
+            time: now,
+            screenshot: self.screenshot_requested.take(),
+            record,
+            mapping: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+        });
+        if record {
+            self.last_recorded = Some(now);
+            self.record_requested = false;
+        }
+    }
+
+    /// Starts reading the copies back, the frame has to be submitted by now.
+    pub fn after_submit(&mut self) {
+        for readback in self.in_flight.iter_mut().filter(|r| !r.mapping) {
+            let mapped = readback.mapped.clone();
+            readback
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| match result {
+                    Ok(()) => mapped.store(true, Ordering::Release),
+                    Err(e) => eprintln!("Failed to read a frame back: {}", e),
+                });
+            readback.mapping = true;
+        }
+    }
+}
+
+impl Readback {
+    /// Drops the row padding the copy needed, and the alpha, which the window ignores too.
+    fn image(&self) -> image::RgbaImage {
+        let data = self.buffer.slice(..).get_mapped_range();
+        let row = self.size.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row * self.size.height as usize);
+        for padded in data.chunks(padded_row(self.size.width) as usize) {
+            pixels.extend_from_slice(&padded[..row]);
+        }
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+        image::RgbaImage::from_raw(self.size.width, self.size.height, pixels)
+            .expect("The buffer to hold the whole frame")
+    }
+}
+
+/// Buffer copies need rows aligned to 256 bytes.
+fn padded_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4).div_ceil(align) * align
+}
+
+/// Milliseconds since the epoch, which sorts and doesn't collide.
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn save_png(path: PathBuf, image: image::RgbaImage) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(image::ImageError::IoError)
+            .and_then(|_| image.save(&path));
+        match saved {
+            Ok(()) => println!("Saved {}", path.display()),
+            Err(e) => eprintln!("Failed to save {}: {}", path.display(), e),
+        }
+    })
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Color32, FontId, Key, Modifiers};
+
+use crate::renderer::Renderer;
+use crate::shaders;
+
+const KEYWORDS: &[&str] = &[
+    "alias", "break", "case", "const", "continue", "default", "discard", "else", "enable", "false",
+    "fn", "for", "if", "let", "loop", "override", "return", "struct", "switch", "true", "var",
+    "while",
+];
+
+const TYPES: &[&str] = &[
+    "array",
+    "atomic",
+    "bool",
+    "f16",
+    "f32",
+    "i32",
+    "mat2x2",
+    "mat2x3",
+    "mat2x4",
+    "mat3x2",
+    "mat3x3",
+    "mat3x4",
+    "mat4x2",
+    "mat4x3",
+    "mat4x4",
+    "ptr",
+    "sampler",
+    "texture_2d",
+    "texture_3d",
+    "texture_storage_2d",
+    "u32",
+    "vec2",
+    "vec3",
+    "vec4",
+];
+
+#[derive(Clone, Copy)]
+enum Token {
+    Plain,
+    Keyword,
+    Type,
+    Function,
+    Number,
+    Attribute,
+    Comment,
+    /// `//!` directives and `#include`.
+    Directive,
+}
+
+impl Token {
+    fn color(&self) -> Color32 {
+        match self {
+            Token::Plain => Color32::LIGHT_GRAY,
+            Token::Keyword => Color32::from_rgb(198, 120, 221),
+            Token::Type => Color32::from_rgb(86, 182, 194),
+            Token::Function => Color32::from_rgb(220, 220, 170),
+            Token::Number => Color32::from_rgb(209, 154, 102),
+            Token::Attribute => Color32::from_rgb(97, 175, 239),
+            Token::Comment => Color32::from_rgb(106, 153, 85),
+            Token::Directive => Color32::from_rgb(229, 192, 123),
+        }
+    }
+}
+
+const ERROR_BACKGROUND: Color32 = Color32::from_rgb(90, 20, 20);
+
+/// Edits the shader of the selected layer in a window. Ctrl+Enter runs the edit
+/// without saving it, Ctrl+S saves it.
+#[derive(Default)]
+pub struct ShaderEditor {
+    pub open: bool,
+    path: PathBuf,
+    text: String,
+    /// What's in the file, to tell whether there's anything to save.
+    saved: String,
+    /// From checking the text as it's typed, or from running it.
+    error: Option<String>,
+    error_lines: Vec<usize>,
+    /// The layout of the text the last time it was drawn, laying it out every frame is slow.
+    highlighted: Option<(String, LayoutJob)>,
+    /// For the includes when checking.
+    shader_dir: PathBuf,
+}
+
+impl ShaderEditor {
+    pub fn new(shader_dir: &Path) -> Self {
+        Self {
+            shader_dir: shader_dir.to_path_buf(),
+            ..Self::default()
+        }
+    }
+
+    pub fn modified(&self) -> bool {
+        self.text != self.saved
+    }
+
+    fn load(&mut self, path: &Path) {
+        self.path = path.to_path_buf();
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.text = text.clone();
+                self.saved = text;
+                self.check();
+            }
+            Err(e) => {
+                self.text.clear();
+                self.saved.clear();
+                self.set_error(Some(format!("Can't read {}: {}", path.display(), e)));
+            }
+        }
+    }
+
+    fn set_error(&mut self, error: Option<String>) {
+        self.error_lines = error
+            .as_deref()
+            .map(|e| shaders::error_lines(&self.path, e))
+            .unwrap_or_default();
+        self.error = error;
+        self.highlighted = None;
+    }
+
+    /// Validates the text like loading it would, without a device.
+    fn check(&mut self) {
+        let error = shaders::check_source(&self.path, &self.text, &self.shader_dir)
+            .err()
+            .map(|e| format!("{:#}", e));
+        self.set_error(error);
+    }
+
+    /// Runs the text in the selected layer, the file stays as it is.
+    fn apply(&mut self, renderer: &mut Renderer) {
+        let error = renderer
+            .apply_source(&self.path, &self.text)
+            .err()
+            .map(|e| format!("{:#}", e));
+        self.set_error(error);
+    }
+
+    fn save(&mut self) -> bool {
+        match fs::write(&self.path, &self.text) {
+            Ok(()) => {
+                self.saved = self.text.clone();
+                true
+            }
+            Err(e) => {
+                self.set_error(Some(format!("Can't save {}: {}", self.path.display(), e)));
+                false
+            }
+        }
+    }
+
+    /// Follows the shader of the selected layer unless there are changes to it that aren't saved.
+    /// Returns whether the shader was saved.
+    pub fn show(&mut self, ctx: &egui::Context, renderer: &mut Renderer) -> bool {
+        let current = &renderer.layers[renderer.selected_layer].path;
+        if *current != self.path && !self.modified() {
+            let current = current.clone();
+            self.load(&current);
+        }
+
+        let mut open = self.open;
+        let mut saved = false;
+        egui::Window::new("Shader editor")
+            .open(&mut open)
+            .default_size([600.0, 500.0])
+            .show(ctx, |ui| saved = self.ui(ui, renderer));
+        self.open = open;
+        saved
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, renderer: &mut Renderer) -> bool {
+        let id = egui::Id::new("shader_editor");
+        let focused = ui.memory(|m| m.has_focus(id));
+        // Taken before the text field sees them, it would put a new line in for Ctrl+Enter.
+        let (mut apply, mut save) = ui.input_mut(|i| {
+            (
+                focused && i.consume_key(Modifiers::COMMAND, Key::Enter),
+                focused && i.consume_key(Modifiers::COMMAND, Key::S),
+            )
+        });
+
+        ui.horizontal(|ui| {
+            let name = self.path.display().to_string();
+            if self.modified() {
+                ui.strong(format!("{} *", name));
+            } else {
+                ui.strong(name);
+            }
+            apply |= ui.button("Run").on_hover_text("Ctrl+Enter").clicked();
+            save |= ui
+                .add_enabled(self.modified(), egui::Button::new("Save"))
+                .on_hover_text("Ctrl+S")
+                .clicked();
+            if ui
+                .add_enabled(self.modified(), egui::Button::new("Revert"))
+                .clicked()
+            {
+                let path = self.path.clone();
+                self.load(&path);
+            }
+        });
+        if let Some(error) = &self.error {
+            egui::ScrollArea::vertical()
+                .id_source("shader_editor_error")
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    ui.add(egui::Label::new(
+                        egui::RichText::new(error)
+                            .monospace()
+                            .color(Color32::LIGHT_RED),
+                    ));
+                });
+        }
+        ui.separator();
+
+        let font = egui::TextStyle::Monospace.resolve(ui.style());
+        let line_count = self.text.split('\n').count();
+        let gutter = gutter(line_count, &self.error_lines, font.clone());
+        let error_lines = &self.error_lines;
+        let highlighted = &mut self.highlighted;
+        let mut layouter = |ui: &egui::Ui, text: &str, _wrap_width: f32| {
+            let job = match highlighted {
+                Some((cached, job)) if cached == text => job.clone(),
+                _ => {
+                    let job = highlight(text, error_lines, font.clone());
+                    *highlighted = Some((text.to_string(), job.clone()));
+                    job
+                }
+            };
+            ui.fonts(|f| f.layout_job(job))
+        };
+        let mut changed = false;
+        egui::ScrollArea::both()
+            .id_source("shader_editor_text")
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.horizontal_top(|ui| {
+                    ui.add(egui::Label::new(gutter));
+                    changed = ui
+                        .add(
+                            egui::TextEdit::multiline(&mut self.text)
+                                .id(id)
+                                .code_editor()
+                                .desired_width(f32::INFINITY)
+                                .layouter(&mut layouter),
+                        )
+                        .changed();
+                });
+            });
+
+        if changed {
+            self.check();
+        }
+        if apply {
+            self.apply(renderer);
+        }
+        save && self.modified() && self.save()
+    }
+}
+
+/// The line numbers, the ones with errors in red.
+fn gutter(line_count: usize, error_lines: &[usize], font: FontId) -> LayoutJob {
+    let width = line_count.to_string().len();
+    let mut job = LayoutJob::default();
+    for line in 1..=line_count {
+        let color = if error_lines.contains(&line) {
+            Color32::RED
+        } else {
+            Color32::GRAY
+        };
+        let newline = if line < line_count { "\n" } else { "" };
+        job.append(
+            &format!("{:>width$}{}", line, newline, width = width),
+            0.0,
+            TextFormat::simple(font.clone(), color),
+        );
+    }
+    job
+}
+
+/// Colors the WGSL by token, the lines with errors get a red background.
+fn highlight(text: &str, error_lines: &[usize], font: FontId) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    job.wrap.max_width = f32::INFINITY;
+    let mut in_comment = false;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let background = if error_lines.contains(&(index + 1)) {
+            ERROR_BACKGROUND
+        } else {
+            Color32::TRANSPARENT
+        };
+        let mut rest = line;
+        while !rest.is_empty() {
+            let (len, token) = next_token(rest, &mut in_comment);
+            let format = TextFormat {
+                font_id: font.clone(),
+                color: token.color(),
+                background,
+                ..Default::default()
+            };
+            job.append(&rest[..len], 0.0, format);
+            rest = &rest[len..];
+        }
+    }
+    job
+}
+
+/// The length of the token `rest` starts with and what it is,
+/// `in_comment` carries block comments over to the next line.
+fn next_token(rest: &str, in_comment: &mut bool) -> (usize, Token) {
+    if *in_comment || rest.starts_with("/*") {
+        *in_comment = true;
+        let start = if rest.starts_with("/*") { 2 } else { 0 };
+        let len = match rest[start..].find("*/") {
+            Some(end) => {
+                *in_comment = false;
+                start + end + 2
+            }
+            None => rest.len(),
+        };
+        return (len, Token::Comment);
+    }
+    if rest.starts_with("//!") || rest.starts_with("#include") {
+        return (rest.len(), Token::Directive);
+    }
+    if rest.starts_with("//") {
+        return (rest.len(), Token::Comment);
+    }
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let word_len = |s: &str| s.find(|c: char| !is_word(c)).unwrap_or(s.len());
+    let first = rest.chars().next().unwrap_or_default();
+    if first == '@' {
+        return (1 + word_len(&rest[1..]), Token::Attribute);
+    }
+    if first.is_ascii_digit() {
+        let len = rest
+            .find(|c: char| !(is_word(c) || c == '.'))
+            .unwrap_or(rest.len());
+        return (len, Token::Number);
+    }
+    if is_word(first) {
+        let len = word_len(rest);
+        let word = &rest[..len];
+        let token = if KEYWORDS.contains(&word) {
+            Token::Keyword
+        } else if TYPES.contains(&word) {
+            Token::Type
+        } else if rest[len..].trim_start().starts_with('(') {
+            Token::Function
+        } else {
+            Token::Plain
+        };
+        return (len, token);
+    }
+    // Anything else up to the next thing that could start a token.
+    let len = rest
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| is_word(*c) || matches!(c, '@' | '/' | '#'))
+        .map(|(i, _)| i)
+        .unwrap_or(rest.len());
+    (len, Token::Plain)
+}
@@ -93,16 +93,20 @@ struct SizedBuffer {
 /// RenderPass to render a egui based GUI.
 pub struct RenderPass {
     render_pipeline: wgpu::RenderPipeline,
+    /// For native textures that can't be filtered, like `R32Float`.
+    unfilterable_pipeline: wgpu::RenderPipeline,
     index_buffers: Vec<SizedBuffer>,
     vertex_buffers: Vec<SizedBuffer>,
     uniform_buffer: SizedBuffer,
     uniform_bind_group: wgpu::BindGroup,
     texture_bind_group_layout: wgpu::BindGroupLayout,
+    unfilterable_bind_group_layout: wgpu::BindGroupLayout,
 
     /// Map of egui texture IDs to textures and their associated bindgroups (texture view +
-    /// sampler). The texture may be None if the TextureId is just a handle to a user-provided
-    /// sampler.
-    textures: HashMap<egui::TextureId, (Option<wgpu::Texture>, wgpu::BindGroup)>,
+    /// sampler), and whether they're filterable. The texture may be None if the TextureId is
+    /// just a handle to a user-provided texture.
+    textures: HashMap<egui::TextureId, (Option<wgpu::Texture>, wgpu::BindGroup, bool)>,
+    next_user_texture_id: u64,
 }
 
 impl RenderPass {
@@ -160,16 +164,16 @@ impl RenderPass {
             }],
         });
 
-        let texture_bind_group_layout =
+        let texture_bind_group_layout = |label, filterable| {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("egui_texture_bind_group_layout"),
+                label: Some(label),
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            sample_type: wgpu::TextureSampleType::Float { filterable },
                             view_dimension: wgpu::TextureViewDimension::D2,
                         },
                         count: None,
@@ -177,24 +181,70 @@ impl RenderPass {
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
                         visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        ty: wgpu::BindingType::Sampler(if filterable {
+                            wgpu::SamplerBindingType::Filtering
+                        } else {
+                            wgpu::SamplerBindingType::NonFiltering
+                        }),
                         count: None,
                     },
                 ],
+            })
+        };
+        let unfilterable_bind_group_layout =
+            texture_bind_group_layout("egui_unfilterable_bind_group_layout", false);
+        let texture_bind_group_layout =
+            texture_bind_group_layout("egui_texture_bind_group_layout", true);
+
+        let render_pipeline = |label, texture_bind_group_layout| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("egui_pipeline_layout"),
+                bind_group_layouts: &[&uniform_bind_group_layout, texture_bind_group_layout],
+                push_constant_ranges: &[],
             });
+            Self::create_pipeline(
+                device,
+                &module,
+                &pipeline_layout,
+                output_format,
+                msaa_samples,
+                label,
+            )
+        };
+        let unfilterable_pipeline = render_pipeline(
+            "egui_unfilterable_pipeline",
+            &unfilterable_bind_group_layout,
+        );
+        let render_pipeline = render_pipeline("egui_pipeline", &texture_bind_group_layout);
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("egui_pipeline_layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout],
-            push_constant_ranges: &[],
-        });
+        Self {
+            render_pipeline,
+            unfilterable_pipeline,
+            vertex_buffers: Vec::with_capacity(64),
+            index_buffers: Vec::with_capacity(64),
+            uniform_buffer,
+            uniform_bind_group,
+            texture_bind_group_layout,
+            unfilterable_bind_group_layout,
+            textures: HashMap::new(),
+            next_user_texture_id: 0,
+        }
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("egui_pipeline"),
-            layout: Some(&pipeline_layout),
+    fn create_pipeline(
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+        layout: &wgpu::PipelineLayout,
+        output_format: wgpu::TextureFormat,
+        msaa_samples: u32,
+        label: &str,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
                 entry_point: "vs_main",
-                module: &module,
+                module,
                 buffers: &[wgpu::VertexBufferLayout {
                     array_stride: 5 * 4,
                     step_mode: wgpu::VertexStepMode::Vertex,
@@ -221,8 +271,13 @@ impl RenderPass {
             },
 
             fragment: Some(wgpu::FragmentState {
-                module: &module,
-                entry_point: "fs_main",
+                module,
+                // egui's colors are sRGB, a sRGB target would encode them a second time.
+                entry_point: if output_format.describe().srgb {
+                    "fs_main_linear_framebuffer"
+                } else {
+                    "fs_main"
+                },
                 targets: &[Some(wgpu::ColorTargetState {
                     format: output_format,
                     blend: Some(wgpu::BlendState {
@@ -241,17 +296,7 @@ impl RenderPass {
                 })],
             }),
             multiview: None,
-        });
-
-        Self {
-            render_pipeline,
-            vertex_buffers: Vec::with_capacity(64),
-            index_buffers: Vec::with_capacity(64),
-            uniform_buffer,
-            uniform_bind_group,
-            texture_bind_group_layout,
-            textures: HashMap::new(),
-        }
+        })
     }
 
     /// Executes the egui render pass. When `clear_color` is not None, the output target will get cleared with clear_color before writing to it.
@@ -298,6 +343,7 @@ impl RenderPass {
         screen_descriptor: &ScreenDescriptor,
     ) -> Result<(), BackendError> {
         rpass.set_pipeline(&self.render_pipeline);
+        let mut filtering = true;
 
         rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
 
@@ -355,7 +401,16 @@ impl RenderPass {
             }
 
             if let epaint::Primitive::Mesh(mesh) = primitive {
-                let bind_group = self.get_texture_bind_group(mesh.texture_id)?;
+                let (bind_group, filterable) = self.get_texture_bind_group(mesh.texture_id)?;
+                if filterable != filtering {
+                    filtering = filterable;
+                    rpass.set_pipeline(if filterable {
+                        &self.render_pipeline
+                    } else {
+                        &self.unfilterable_pipeline
+                    });
+                    rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                }
                 rpass.set_bind_group(1, bind_group, &[]);
 
                 rpass.set_index_buffer(index_buffer.buffer.slice(..), wgpu::IndexFormat::Uint32);
@@ -370,13 +425,13 @@ impl RenderPass {
     fn get_texture_bind_group(
         &self,
         texture_id: egui::TextureId,
-    ) -> Result<&wgpu::BindGroup, BackendError> {
+    ) -> Result<(&wgpu::BindGroup, bool), BackendError> {
         self.textures
             .get(&texture_id)
             .ok_or_else(|| {
                 BackendError::Internal(format!("Texture {:?} used but not live", texture_id))
             })
-            .map(|x| &x.1)
+            .map(|x| (&x.1, x.2))
     }
 
     /// Updates the texture used by egui for the fonts etc. Should be called before `execute()`.
@@ -448,7 +503,7 @@ impl RenderPass {
                             &self.texture_bind_group_layout,
                         );
 
-                        let (texture, _) = o.insert((Some(texture), bind_group));
+                        let (texture, ..) = o.insert((Some(texture), bind_group, true));
 
                         if let Some(texture) = texture {
                             texture.destroy();
@@ -487,7 +542,7 @@ impl RenderPass {
                         &self.texture_bind_group_layout,
                     );
 
-                    v.insert((Some(texture), bind_group));
+                    v.insert((Some(texture), bind_group, true));
                 }
             }
         }
@@ -498,7 +553,7 @@ impl RenderPass {
     /// Remove the textures egui no longer needs. Should be called after `execute()`
     pub fn remove_textures(&mut self, textures: egui::TexturesDelta) -> Result<(), BackendError> {
         for texture_id in textures.free {
-            let (texture, _binding) = self.textures.remove(&texture_id).ok_or_else(|| {
+            let (texture, ..) = self.textures.remove(&texture_id).ok_or_else(|| {
                 // This can happen due to a bug in egui, or if the user doesn't call `add_textures`
                 // when required.
                 BackendError::InvalidTextureId(format!(
@@ -515,6 +570,99 @@ impl RenderPass {
         Ok(())
     }
 
+    /// Registers a texture egui doesn't manage, like a render target, so the ui can show it.
+    /// The texture is read when the ui is drawn, so it shows whatever is in it at that point.
+    /// Float formats that can't be filtered are sampled with nearest filtering.
+    pub fn register_native_texture(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        view: &wgpu::TextureView,
+    ) -> egui::TextureId {
+        let id = egui::TextureId::User(self.next_user_texture_id);
+        self.next_user_texture_id += 1;
+        self.textures
+            .insert(id, self.native_bind_group(device, id, texture, view));
+        id
+    }
+
+    /// Points a registered texture at another one, like after a render target was recreated.
+    pub fn update_native_texture(
+        &mut self,
+        device: &wgpu::Device,
+        id: egui::TextureId,
+        texture: &wgpu::Texture,
+        view: &wgpu::TextureView,
+    ) -> Result<(), BackendError> {
+        let entry = self.native_bind_group(device, id, texture, view);
+        match self.textures.get_mut(&id) {
+            Some(existing) if existing.0.is_none() => {
+                *existing = entry;
+                Ok(())
+            }
+            Some(_) => Err(BackendError::InvalidTextureId(format!(
+                "Update of managed texture {:?} as a native one",
+                id
+            ))),
+            None => Err(BackendError::InvalidTextureId(format!(
+                "Update of an unknown texture {:?}",
+                id
+            ))),
+        }
+    }
+
+    /// Forgets a registered texture, the texture itself is left alone.
+    pub fn free_native_texture(&mut self, id: egui::TextureId) {
+        if matches!(self.textures.get(&id), Some((None, ..))) {
+            self.textures.remove(&id);
+        }
+    }
+
+    fn native_bind_group(
+        &self,
+        device: &wgpu::Device,
+        id: egui::TextureId,
+        texture: &wgpu::Texture,
+        view: &wgpu::TextureView,
+    ) -> (Option<wgpu::Texture>, wgpu::BindGroup, bool) {
+        let filterable = matches!(
+            texture.format().describe().sample_type,
+            wgpu::TextureSampleType::Float { filterable: true }
+        );
+        let filter = if filterable {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
+        let label_base = format!("egui_native_image_{:?}", id);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(format!("{}_sampler", label_base).as_str()),
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        let layout = if filterable {
+            &self.texture_bind_group_layout
+        } else {
+            &self.unfilterable_bind_group_layout
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(format!("{}_texture_bind_group", label_base).as_str()),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        (None, bind_group, filterable)
+    }
+
     /// Uploads the uniform, vertex and index data used by the render pass.
     /// Should be called before `execute()`.
     pub fn update_buffers(
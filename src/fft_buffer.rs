@@ -10,20 +10,18 @@ pub fn to_byte_slice<'a>(floats: &'a [f32]) -> &'a [u8] {
 pub struct FFTDimensions {
     pub fft_size: usize,
     time_slices: usize,
-    pub smoothing: f32,
     ring_factor: usize,
     // TODO: make dependent on the sample rate.
 }
 
 impl FFTDimensions {
-    pub fn new(fft_size: usize, time_slices: usize, smoothing: f32, ring_factor: usize) -> Self {
+    pub fn new(fft_size: usize, time_slices: usize, ring_factor: usize) -> Self {
         if f32::log2(fft_size as f32).fract() != 0.0 {
             eprintln!("FFT Size should be power of two, but it was {}", fft_size);
         }
         Self {
             fft_size,
             time_slices,
-            smoothing,
             ring_factor,
         }
     }
@@ -45,7 +43,7 @@ impl FFTDimensions {
 
 impl Default for FFTDimensions {
     fn default() -> Self {
-        Self::new(1024, 100, 0.8, 4)
+        Self::new(1024, 100, 4)
     }
 }
 
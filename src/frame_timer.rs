@@ -0,0 +1,231 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Enough for the particles, all five passes, the post effects, the blit and the ui,
+/// plus the end of the frame.
+const MAX_QUERIES: u32 = 16;
+
+/// How long one pass of a frame took.
+#[derive(Clone, Debug)]
+pub struct PassTiming {
+    pub label: &'static str,
+    /// Recording the commands.
+    pub cpu: Duration,
+    /// Running them, `None` without timestamp queries.
+    pub gpu: Option<Duration>,
+}
+
+/// Times the passes of a frame with timestamp queries written between them.
+/// The timestamps are read back without stalling, so the timings are a frame or two behind.
+pub struct FrameTimer {
+    gpu: Option<GpuQueries>,
+    // This frames passes, each one lasts until the next mark or the end of the frame.
+    labels: Vec<&'static str>,
+    cpu_marks: Vec<Instant>,
+    timings: Vec<PassTiming>,
+}
+
+impl FrameTimer {
+    /// Only times the gpu if the device was created with `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuQueries::new(device, queue));
+        Self {
+            gpu,
+            labels: vec![],
+            cpu_marks: vec![],
+            timings: vec![],
+        }
+    }
+
+    pub fn has_gpu_timing(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// The passes of the latest frame that was read back.
+    pub fn timings(&self) -> &[PassTiming] {
+        &self.timings
+    }
+
+    /// The gpu time of the whole frame, `None` without timestamp queries.
+    pub fn gpu_total(&self) -> Option<Duration> {
+        if self.timings.is_empty() {
+            return None;
+        }
+        self.timings.iter().map(|t| t.gpu).sum()
+    }
+
+    /// Picks up the timestamps of an earlier frame if they're ready.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        self.labels.clear();
+        self.cpu_marks.clear();
+        if let Some(gpu) = &mut self.gpu {
+            device.poll(wgpu::Maintain::Poll);
+            if let Some(timings) = gpu.read() {
+                self.timings = timings;
+            }
+        }
+    }
+
+    /// Starts timing the pass `label`, which ends with the next mark.
+    pub fn mark(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        // The last query is kept for the end of the frame.
+        if self.labels.len() as u32 + 1 >= MAX_QUERIES {
+            return;
+        }
+        if let Some(gpu) = &self.gpu {
+            encoder.write_timestamp(&gpu.query_set, self.labels.len() as u32);
+        }
+        self.labels.push(label);
+        self.cpu_marks.push(Instant::now());
+    }
+
+    /// Ends the last pass and copies the timestamps out, unless the previous ones are still being read.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let now = Instant::now();
+        let cpu: Vec<Duration> = self
+            .cpu_marks
+            .iter()
+            .enumerate()
+            .map(|(i, start)| {
+                self.cpu_marks
+                    .get(i + 1)
+                    .unwrap_or(&now)
+                    .duration_since(*start)
+            })
+            .collect();
+
+        match &mut self.gpu {
+            Some(gpu) => gpu.resolve(encoder, &self.labels, cpu),
+            None => {
+                self.timings = self
+                    .labels
+                    .iter()
+                    .zip(cpu)
+                    .map(|(&label, cpu)| PassTiming {
+                        label,
+                        cpu,
+                        gpu: None,
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    /// Starts reading the timestamps, the frame has to be submitted by now.
+    pub fn after_submit(&mut self) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.map();
+        }
+    }
+}
+
+struct GpuQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    read_buffer: wgpu::Buffer,
+    /// Nanoseconds per tick.
+    period: f32,
+    // The frame being read back, with its labels and cpu times.
+    in_flight: Option<(Vec<&'static str>, Vec<Duration>)>,
+    mapping: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+impl GpuQueries {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Frame Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_QUERIES,
+        });
+        let size = MAX_QUERIES as u64 * std::mem::size_of::<u64>() as u64;
+        // Queries resolve into copy destinations, mapped buffers can't be one directly.
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Timer Read Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            query_set,
+            resolve_buffer,
+            read_buffer,
+            period: queue.get_timestamp_period(),
+            in_flight: None,
+            mapping: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn resolve(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        labels: &[&'static str],
+        cpu: Vec<Duration>,
+    ) {
+        if self.in_flight.is_some() || labels.is_empty() {
+            return;
+        }
+        let count = labels.len() as u32 + 1;
+        encoder.write_timestamp(&self.query_set, count - 1);
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.read_buffer,
+            0,
+            count as u64 * std::mem::size_of::<u64>() as u64,
+        );
+        self.in_flight = Some((labels.to_vec(), cpu));
+    }
+
+    fn map(&mut self) {
+        if self.in_flight.is_none() || self.mapping {
+            return;
+        }
+        self.mapping = true;
+        let mapped = self.mapped.clone();
+        self.read_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    fn read(&mut self) -> Option<Vec<PassTiming>> {
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return None;
+        }
+        let (labels, cpu) = self.in_flight.take()?;
+        let timestamps: Vec<u64> =
+            bytemuck::cast_slice(&self.read_buffer.slice(..).get_mapped_range()).to_vec();
+        self.read_buffer.unmap();
+        self.mapping = false;
+
+        let timings = labels
+            .into_iter()
+            .zip(cpu)
+            .zip(timestamps.windows(2))
+            .map(|((label, cpu), ticks)| PassTiming {
+                label,
+                cpu,
+                gpu: Some(Duration::from_nanos(
+                    (ticks[1].saturating_sub(ticks[0]) as f64 * self.period as f64) as u64,
+                )),
+            })
+            .collect();
+        Some(timings)
+    }
+}
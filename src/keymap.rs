@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+/// What a key can do, bound in the `[keys]` table of the settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Help,
+    ToggleUi,
+    Fullscreen,
+    Screenshot,
+    DumpRecording,
+    NextShader,
+    PreviousShader,
+    RandomShader,
+    NextPreset,
+    PreviousPreset,
+    /// Keeps the preset from being switched, by hand or from MIDI and OSC.
+    LockPreset,
+    PauseAudio,
+    MoreSensitive,
+    LessSensitive,
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::Help,
+        Action::ToggleUi,
+        Action::Fullscreen,
+        Action::Screenshot,
+        Action::DumpRecording,
+        Action::NextShader,
+        Action::PreviousShader,
+        Action::RandomShader,
+        Action::NextPreset,
+        Action::PreviousPreset,
+        Action::LockPreset,
+        Action::PauseAudio,
+        Action::MoreSensitive,
+        Action::LessSensitive,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Help => "Show keys",
+            Action::ToggleUi => "Show UI",
+            Action::Fullscreen => "Fullscreen",
+            Action::Screenshot => "Screenshot",
+            Action::DumpRecording => "Save recording",
+            Action::NextShader => "Next shader",
+            Action::PreviousShader => "Previous shader",
+            Action::RandomShader => "Random shader",
+            Action::NextPreset => "Next preset",
+            Action::PreviousPreset => "Previous preset",
+            Action::LockPreset => "Lock preset",
+            Action::PauseAudio => "Pause audio",
+            Action::MoreSensitive => "More sensitive",
+            Action::LessSensitive => "Less sensitive",
+        }
+    }
+
+    fn default_keys(&self) -> Vec<VirtualKeyCode> {
+        use VirtualKeyCode::*;
+        match self {
+            Action::Help => vec![F1],
+            Action::ToggleUi => vec![F2],
+            Action::Fullscreen => vec![F11],
+            Action::Screenshot => vec![F12],
+            Action::DumpRecording => vec![F9],
+            Action::NextShader => vec![RBracket],
+            Action::PreviousShader => vec![LBracket],
+            Action::RandomShader => vec![R],
+            Action::NextPreset => vec![PageDown],
+            Action::PreviousPreset => vec![PageUp],
+            Action::LockPreset => vec![L],
+            Action::PauseAudio => vec![P],
+            Action::MoreSensitive => vec![Equals],
+            Action::LessSensitive => vec![Minus],
+        }
+    }
+}
+
+pub type Bindings = BTreeMap<Action, Vec<VirtualKeyCode>>;
+
+/// Which keys do what, and which of them are held down so holding one doesn't repeat it.
+pub struct Keymap {
+    pub bindings: Bindings,
+    held: HashSet<VirtualKeyCode>,
+    /// The next key that goes down is bound to this instead.
+    pub rebinding: Option<Action>,
+}
+
+impl Keymap {
+    /// The defaults with the bindings from the settings over them.
+    pub fn new(bindings: &Bindings) -> Self {
+        let mut all: Bindings = Action::ALL
+            .into_iter()
+            .map(|action| (action, action.default_keys()))
+            .collect();
+        all.extend(bindings.iter().map(|(a, keys)| (*a, keys.clone())));
+        Self {
+            bindings: all,
+            held: HashSet::new(),
+            rebinding: None,
+        }
+    }
+
+    /// The action bound to the key, the first one if there are more.
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+
+    /// Whether the key just went down, every key on its own.
+    pub fn pressed(&mut self, key: VirtualKeyCode, down: bool) -> bool {
+        if down {
+            self.held.insert(key)
+        } else {
+            self.held.remove(&key);
+            false
+        }
+    }
+
+    /// Forgets the keys that are down, their releases go elsewhere while the window isn't focused.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /// Binds the key while rebinding, Escape leaves the binding as it was.
+    /// Returns whether the key was used for that.
+    pub fn rebind(&mut self, key: VirtualKeyCode, down: bool) -> bool {
+        let Some(action) = self.rebinding else {
+            return false;
+        };
+        if self.pressed(key, down) {
+            if key != VirtualKeyCode::Escape {
+                for keys in self.bindings.values_mut() {
+                    keys.retain(|k| *k != key);
+                }
+                self.bindings.insert(action, vec![key]);
+            }
+            self.rebinding = None;
+        }
+        true
+    }
+
+    /// Like `F1` or `PageDown`, `-` without one.
+    pub fn keys(&self, action: Action) -> String {
+        let keys = &self.bindings[&action];
+        if keys.is_empty() {
+            return "-".to_string();
+        }
+        keys.iter()
+            .map(|key| format!("{:?}", key))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn is_bound(&self, action: Action) -> bool {
+        !self.bindings[&action].is_empty()
+    }
+
+    /// `<text> (<keys>)` for buttons and hints, just the text when nothing is bound.
+    pub fn label(&self, text: &str, action: Action) -> String {
+        if self.is_bound(action) {
+            format!("{} ({})", text, self.keys(action))
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::program::{PassResources, ShaderProgram};
+use crate::render_target::RenderTarget;
+use crate::shaders::Vertex;
+
+/// How a layer is put on top of the ones below it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    #[default]
+    Alpha,
+    Add,
+    Multiply,
+    Screen,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Alpha,
+        BlendMode::Add,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Alpha => "Alpha",
+            BlendMode::Add => "Add",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+        }
+    }
+
+    /// The layer shader outputs premultiplied color, except for multiply.
+    fn blend_state(&self) -> wgpu::BlendState {
+        let color = |src_factor, dst_factor| wgpu::BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: wgpu::BlendOperation::Add,
+        };
+        use wgpu::BlendFactor::*;
+        wgpu::BlendState {
+            color: match self {
+                BlendMode::Alpha => color(One, OneMinusSrcAlpha),
+                BlendMode::Add => color(One, One),
+                BlendMode::Multiply => color(Dst, Zero),
+                BlendMode::Screen => color(OneMinusDst, One),
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        }
+    }
+
+    fn entry_point(&self) -> &'static str {
+        match self {
+            BlendMode::Multiply => "fs_multiply",
+            _ => "fs_main",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerUniform {
+    opacity: f32,
+    // Uniforms are padded to 16 bytes.
+    _padding: [f32; 3],
+}
+
+/// A shader in the layer list, rendered to its own targets.
+pub struct Layer {
+    pub program: ShaderProgram,
+    pub path: PathBuf,
+    pub blend: BlendMode,
+    pub opacity: f32,
+    /// Added to the opacity on a beat, negative fades the layer out instead.
+    pub audio: f32,
+    pub visible: bool,
+    /// Scales the opacity while presets fade into each other.
+    pub fade: f32,
+    /// On its way out in a fade, removed when it's over.
+    pub leaving: bool,
+    // The opacity of this frame, with the audio.
+    current_opacity: f32,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl Layer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        resources: &PassResources,
+        shader: &Path,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> Result<Self> {
+        let program = ShaderProgram::new(device, queue, resources, shader, size)?;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Layer Buffer"),
+            contents: bytemuck::cast_slice(&[LayerUniform {
+                opacity: 1.0,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Ok(Self {
+            program,
+            path: shader.to_path_buf(),
+            blend: BlendMode::default(),
+            opacity: 1.0,
+            audio: 0.0,
+            visible: true,
+            fade: 1.0,
+            leaving: false,
+            current_opacity: 1.0,
+            uniform_buffer,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the shader, keeping how the layer is blended.
+    /// The params keep their values when it's the same shader again.
+    pub fn set_program(&mut self, mut program: ShaderProgram, shader: &Path) {
+        if self.path == shader {
+            program.keep_params(&self.program);
+        }
+        self.program = program;
+        self.path = shader.to_path_buf();
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, time: f32, beat: f32) {
+        self.program.update(queue, time);
+        self.current_opacity = (self.opacity + self.audio * beat).clamp(0.0, 1.0) * self.fade;
+        let uniform = LayerUniform {
+            opacity: self.current_opacity,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+/// Blends the visible layers bottom to top.
+pub struct Compositor {
+    /// In the order of `BlendMode::ALL`.
+    pipelines: Vec<wgpu::RenderPipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    target: RenderTarget,
+}
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+impl Compositor {
+    pub fn new(device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("layers_shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("layers.wgsl"))),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("layers_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("layers_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = BlendMode::ALL
+            .iter()
+            .map(|mode| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(mode.name()),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &module,
+                        entry_point: "vs_main",
+                        buffers: &[Vertex::desc()],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &module,
+                        entry_point: mode.entry_point(),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: FORMAT,
+                            blend: Some(mode.blend_state()),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        cull_mode: Some(wgpu::Face::Back),
+                        ..Default::default()
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                })
+            })
+            .collect();
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("layers_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipelines,
+            bind_group_layout,
+            sampler,
+            target: Self::make_target(device, size),
+        }
+    }
+
+    fn make_target(device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) -> RenderTarget {
+        RenderTarget::new(device, "layers_target", size.width, size.height, FORMAT)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) {
+        self.target = Self::make_target(device, size);
+    }
+
+    /// The blended layers, stale while a single layer is passed through.
+    pub fn target(&self) -> &RenderTarget {
+        &self.target
+    }
+
+    /// Blends the rendered layers, a single opaque layer is passed through as is.
+    pub fn render<'a>(
+        &'a self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        layers: &'a [Layer],
+    ) -> &'a RenderTarget {
+        let visible: Vec<&Layer> = layers.iter().filter(|l| l.visible).collect();
+        if let [layer] = visible[..] {
+            if layer.blend == BlendMode::Alpha && layer.current_opacity >= 1.0 {
+                return layer.program.output();
+            }
+        }
+
+        let bind_groups: Vec<wgpu::BindGroup> = visible
+            .iter()
+            .map(|layer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: layer.uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(
+                                &layer.program.output().view,
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                    label: Some("layers_bind_group"),
+                })
+            })
+            .collect();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Layers Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.target.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        for (layer, bind_group) in visible.iter().zip(&bind_groups) {
+            render_pass.set_pipeline(&self.pipelines[layer.blend as usize]);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            resources.draw_quad(&mut render_pass);
+        }
+        drop(render_pass);
+        &self.target
+    }
+}
@@ -0,0 +1,45 @@
+// Blends the output of a layer onto the layers below it, the blend mode is in the pipeline.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+	@location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+	@location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+struct LayerUniform {
+	// Including the audio.
+	opacity: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> layer: LayerUniform;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var source_sampler: sampler;
+
+// Premultiplied, for alpha, add and screen.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+	let color = textureSample(source, source_sampler, in.tex_coords).rgb;
+	return vec4<f32>(color * layer.opacity, layer.opacity);
+}
+
+// Fades towards white, which multiplies to no change.
+@fragment
+fn fs_multiply(in: VertexOutput) -> @location(0) vec4<f32> {
+	let color = textureSample(source, source_sampler, in.tex_coords).rgb;
+	return vec4<f32>(mix(vec3<f32>(1.0), color, layer.opacity), 1.0);
+}
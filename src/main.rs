@@ -1,33 +1,104 @@
+use clap::Parser;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
 };
 
 mod audio_processor;
+mod blit;
+mod browser;
+mod camera;
+mod capture;
+mod editor;
 mod egui_integration;
 mod fft_buffer;
+mod frame_timer;
+mod keymap;
+mod layers;
+mod midi;
+mod osc;
+mod output;
+mod particles;
+mod plots;
+mod post;
+mod preset;
+mod previews;
+mod program;
+mod quality;
+mod render_target;
 mod renderer;
+mod settings;
+mod shader_meta;
 mod shaders;
+mod shadertoy;
 mod state;
+mod texture;
+mod thumbnails;
 mod ui;
 
 #[tokio::main]
 async fn main() {
+    let args = settings::Args::parse();
+    let settings_path = args.settings_path();
+    let loaded = settings::Settings::load(&settings_path);
+    let mut settings = loaded.clone();
+    settings.apply_args(&args);
+    // The command line is only for this run, it's taken back out before saving.
+    let started = settings.clone();
+
+    if args.prelude_docs {
+        print!("{}", shaders::prelude_docs());
+        return;
+    }
+    if args.list_devices {
+        for name in audio_processor::input_device_names() {
+            println!("{}", name);
+        }
+        return;
+    }
+    if args.list_midi_ports {
+        for name in midi::Midi::ports() {
+            println!("{}", name);
+        }
+        return;
+    }
+    if args.list_shaders {
+        for shader in shaders::list_shaders(&settings.ui.shader_dir).unwrap_or_default() {
+            println!("{}", shader.display());
+        }
+        return;
+    }
     env_logger::init();
+    if args.thumbnails {
+        if let Err(e) = thumbnails::generate(&settings, args.animated).await {
+            eprintln!("{:#}", e);
+        }
+        return;
+    }
     let event_loop = EventLoop::new();
 
-    let mut state = state::State::new(&event_loop);
-    let mut audio_processor = audio_processor::AudioProcessor::new(&state);
-    let mut renderer = renderer::Renderer::new(&state).await;
-    let mut ui = ui::Ui::new(&state, &renderer);
+    let mut state = state::State::new(&event_loop, &settings);
+    let mut audio_processor =
+        audio_processor::AudioProcessor::new(&state, &settings.audio, settings.analysis.analysis());
+    let mut renderer = renderer::Renderer::new(&state, &settings).await;
+    let mut ui = ui::Ui::new(&state, &renderer, &settings);
+    if let Some(path) = &args.preset {
+        match preset::Preset::load(path) {
+            Ok(preset) => preset.apply(&mut renderer, &audio_processor),
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
 
     // END FFT.
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, target, control_flow| {
         ui.handle_event(&event);
         *control_flow = ControlFlow::Poll;
         match event {
             Event::MainEventsCleared => {
-                ui.update(&state, &mut renderer);
+                ui.update(&mut state, &audio_processor, &mut renderer);
+                for window in state.build_outputs(target) {
+                    renderer.add_output(window);
+                }
                 renderer.update(&audio_processor, &mut state);
                 //audio_processor.update().... needs to update thread.
                 state.update();
@@ -47,10 +118,20 @@ async fn main() {
                 window_id,
             } if window_id == state.window.id() => {
                 audio_processor.new_analysis_thread();
+                state.input(event);
                 // If input didnt capture the keybind, do this.
-                if !ui.input(event, &mut state) {
+                if !ui.input(event, &mut state, &audio_processor, &mut renderer) {
                     match event {
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                        WindowEvent::CloseRequested => {
+                            if !args.no_save {
+                                settings.update(&state, &audio_processor, &renderer, &ui);
+                                let file = settings.for_file(&started, &loaded);
+                                if let Err(e) = file.save(&settings_path) {
+                                    eprintln!("{:#}", e);
+                                }
+                            }
+                            *control_flow = ControlFlow::Exit;
+                        }
                         WindowEvent::Resized(physical_size) => {
                             renderer.resize(*physical_size);
                         }
@@ -58,10 +139,18 @@ async fn main() {
                             // new_inner_size is &mut so w have to dereference it twice
                             renderer.resize(**new_inner_size);
                         }
-                        _ => {}
+                        _ => {
+                            renderer.input(event);
+                        }
                     }
                 }
             }
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } => {
+                renderer.output_input(window_id, event, &ui.keymap);
+            }
             Event::Suspended => {
                 println!("SUSPENDED");
             }
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use anyhow::*;
+use midir::{MidiInput, MidiInputConnection};
+use serde::{Deserialize, Serialize};
+
+use crate::audio_processor::AudioProcessor;
+use crate::post::Effect;
+use crate::preset::Presets;
+use crate::renderer::Renderer;
+
+/// What we show up as, and the name of the virtual port other programs can send to.
+const CLIENT_NAME: &str = "audio_visualizer";
+
+/// A knob, fader or pad on a controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Control {
+    Cc { channel: u8, number: u8 },
+    Note { channel: u8, note: u8 },
+}
+
+impl Control {
+    pub fn name(&self) -> String {
+        match self {
+            Control::Cc { channel, number } => format!("CC {} ch {}", number, channel + 1),
+            Control::Note { channel, note } => format!("Note {} ch {}", note, channel + 1),
+        }
+    }
+}
+
+/// What a control changes. Values go from 0 to 1, the triggers go off above 0.
+// Adjacently tagged, toml can't do enums with data otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "which", rename_all = "snake_case")]
+pub enum Target {
+    EffectStrength(Effect),
+    EffectAudio(Effect),
+    /// Layers are counted from the bottom.
+    LayerOpacity(usize),
+    LayerAudio(usize),
+    /// A `//! param` of the shader in a layer, by layer and index.
+    ShaderParam(usize, usize),
+    NextPreset,
+    PreviousPreset,
+    Preset(usize),
+}
+
+impl Target {
+    /// Everything there is to map right now.
+    pub fn all(renderer: &Renderer, presets: usize) -> Vec<Target> {
+        let mut targets = vec![];
+        for effect in Effect::ALL {
+            targets.push(Target::EffectStrength(effect));
+            targets.push(Target::EffectAudio(effect));
+        }
+        for (index, layer) in renderer.layers.iter().enumerate() {
+            targets.push(Target::LayerOpacity(index));
+            targets.push(Target::LayerAudio(index));
+            targets.extend(
+                (0..layer.program.params.len()).map(|param| Target::ShaderParam(index, param)),
+            );
+        }
+        targets.push(Target::NextPreset);
+        targets.push(Target::PreviousPreset);
+        targets.extend((0..presets).map(Target::Preset));
+        targets
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Target::EffectStrength(effect) => format!("{} strength", effect.name()),
+            Target::EffectAudio(effect) => format!("{} beat", effect.name()),
+            Target::LayerOpacity(layer) => format!("Layer {} opacity", layer + 1),
+            Target::LayerAudio(layer) => format!("Layer {} beat opacity", layer + 1),
+            Target::ShaderParam(layer, param) => {
+                format!("Layer {} param {}", layer + 1, param + 1)
+            }
+            Target::NextPreset => "Next preset".to_string(),
+            Target::PreviousPreset => "Previous preset".to_string(),
+            Target::Preset(index) => format!("Preset {}", index + 1),
+        }
+    }
+
+    /// Goes off once instead of following the value.
+    pub fn is_trigger(&self) -> bool {
+        matches!(
+            self,
+            Target::NextPreset | Target::PreviousPreset | Target::Preset(_)
+        )
+    }
+
+    /// Targets that are gone, like a removed layer, are left alone.
+    pub fn apply(
+        &self,
+        value: f32,
+        renderer: &mut Renderer,
+        presets: &mut Presets,
+        audio: &AudioProcessor,
+    ) {
+        let pressed = value > 0.0;
+        match *self {
+            Target::EffectStrength(effect) => {
+                renderer.post.settings.get_mut(effect).strength = value * effect.max_strength()
+            }
+            Target::EffectAudio(effect) => {
+                renderer.post.settings.get_mut(effect).audio = value * effect.max_strength()
+            }
+            Target::LayerOpacity(layer) => {
+                if let Some(layer) = renderer.layers.get_mut(layer) {
+                    layer.opacity = value;
+                }
+            }
+            Target::LayerAudio(layer) => {
+                if let Some(layer) = renderer.layers.get_mut(layer) {
+                    layer.audio = value * 2.0 - 1.0;
+                }
+            }
+            Target::ShaderParam(layer, param) => {
+                let param = renderer
+                    .layers
+                    .get_mut(layer)
+                    .and_then(|layer| layer.program.params.get_mut(param));
+                if let Some(param) = param {
+                    param.set_normalized(value);
+                }
+            }
+            Target::NextPreset if pressed => presets.next(renderer, audio),
+            Target::PreviousPreset if pressed => presets.previous(renderer, audio),
+            Target::Preset(index) if pressed => presets.load(index, renderer, audio),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub control: Control,
+    pub target: Target,
+}
+
+/// Listens to a MIDI port and turns the controls that are mapped into targets.
+pub struct Midi {
+    connection: Option<MidiInputConnection<()>>,
+    /// The port to listen to, None for the virtual port. Kept when it's not there,
+    /// so the settings still have it next time.
+    pub port: Option<String>,
+    sender: Sender<(Control, f32)>,
+    receiver: Receiver<(Control, f32)>,
+    pub mappings: Vec<Mapping>,
+    /// The next control that moves gets mapped to this.
+    pub learning: Option<Target>,
+    /// The last control that moved, and where it went.
+    pub last: Option<(Control, f32)>,
+    /// Where every control that moved is, so triggers only go off when one comes up from 0.
+    values: HashMap<Control, f32>,
+}
+
+impl Midi {
+    /// Connects to the named port, or opens a virtual port without one.
+    pub fn new(port: Option<&str>, mappings: Vec<Mapping>) -> Self {
+        let mut midi = Self::unconnected(mappings);
+        midi.connect(port);
+        midi
+    }
+
+    fn unconnected(mappings: Vec<Mapping>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            connection: None,
+            port: None,
+            sender,
+            receiver,
+            mappings,
+            learning: None,
+            last: None,
+            values: HashMap::new(),
+        }
+    }
+
+    /// The names of the ports there are to connect to.
+    pub fn ports() -> Vec<String> {
+        let Some(input) = MidiInput::new(CLIENT_NAME).ok() else {
+            return vec![];
+        };
+        input
+            .ports()
+            .iter()
+            .filter_map(|port| input.port_name(port).ok())
+            .collect()
+    }
+
+    /// Drops the current connection first, failing leaves it without one.
+    pub fn connect(&mut self, port: Option<&str>) {
+        self.connection = None;
+        self.port = port.map(str::to_string);
+        let sender = self.sender.clone();
+        let callback = move |_: u64, message: &[u8], _: &mut ()| {
+            if let Some(control) = parse(message) {
+                let _ = sender.send(control);
+            }
+        };
+        let connected = match port {
+            Some(name) => open_port(name, callback),
+            None => open_virtual(callback),
+        };
+        match connected {
+            std::result::Result::Ok(connection) => self.connection = Some(connection),
+            Err(e) => eprintln!("MIDI: {:#}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// The mapped controls that moved since the last call, in order.
+    /// While learning, the first control that moves is mapped instead.
+    /// Triggers only come through when the control goes above 0 from 0, so turning a knob
+    /// mapped to the next preset doesn't skip through all of them.
+    pub fn poll(&mut self) -> Vec<(Target, f32)> {
+        let mut moved = vec![];
+        while let std::result::Result::Ok((control, value)) = self.receiver.try_recv() {
+            self.last = Some((control, value));
+            let previous = self.values.insert(control, value).unwrap_or(0.0);
+            let rising = previous <= 0.0 && value > 0.0;
+            // Letting go of a pad shouldn't be learned on its own.
+            if value > 0.0 {
+                if let Some(target) = self.learning.take() {
+                    self.mappings.retain(|m| m.control != control);
+                    self.mappings.push(Mapping { control, target });
+                    continue;
+                }
+            }
+            moved.extend(
+                self.mappings
+                    .iter()
+                    .filter(|m| m.control == control && (rising || !m.target.is_trigger()))
+                    .map(|m| (m.target, value)),
+            );
+        }
+        moved
+    }
+}
+
+/// Control changes and notes, the rest is ignored.
+fn parse(message: &[u8]) -> Option<(Control, f32)> {
+    let [status, data, value, ..] = *message else {
+        return None;
+    };
+    let channel = status & 0x0F;
+    let value = value as f32 / 127.0;
+    match status & 0xF0 {
+        0xB0 => Some((
+            Control::Cc {
+                channel,
+                number: data,
+            },
+            value,
+        )),
+        0x90 => Some((
+            Control::Note {
+                channel,
+                note: data,
+            },
+            value,
+        )),
+        0x80 => Some((
+            Control::Note {
+                channel,
+                note: data,
+            },
+            0.0,
+        )),
+        _ => None,
+    }
+}
+
+fn open_port<F>(name: &str, callback: F) -> Result<MidiInputConnection<()>>
+where
+    F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+{
+    let input = MidiInput::new(CLIENT_NAME).map_err(|e| anyhow!("{}", e))?;
+    let port = input
+        .ports()
+        .into_iter()
+        .find(|port| input.port_name(port).is_ok_and(|n| n == name))
+        .with_context(|| format!("No port called `{}`", name))?;
+    input
+        .connect(&port, CLIENT_NAME, callback, ())
+        .map_err(|e| anyhow!("Failed to connect to `{}`: {}", name, e))
+}
+
+/// Other programs can connect to this, e.g. `aplaymidi -p audio_visualizer file.mid`.
+#[cfg(unix)]
+fn open_virtual<F>(callback: F) -> Result<MidiInputConnection<()>>
+where
+    F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+{
+    use midir::os::unix::VirtualInput;
+
+    let input = MidiInput::new(CLIENT_NAME).map_err(|e| anyhow!("{}", e))?;
+    input
+        .create_virtual(CLIENT_NAME, callback, ())
+        .map_err(|e| anyhow!("Failed to open a virtual port: {}", e))
+}
+
+/// There are no virtual ports on windows, the first port is used instead.
+#[cfg(not(unix))]
+fn open_virtual<F>(callback: F) -> Result<MidiInputConnection<()>>
+where
+    F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+{
+    let first = Midi::ports().into_iter().next().context("No MIDI ports")?;
+    open_port(&first, callback)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moved(midi: &mut Midi, control: Control, values: &[f32]) -> Vec<(Target, f32)> {
+        for value in values {
+            midi.sender.send((control, *value)).unwrap();
+        }
+        midi.poll()
+    }
+
+    #[test]
+    fn triggers_on_rising_edge() {
+        let knob = Control::Cc {
+            channel: 0,
+            number: 1,
+        };
+        let pad = Control::Note {
+            channel: 0,
+            note: 36,
+        };
+        let mut midi = Midi::unconnected(vec![
+            Mapping {
+                control: knob,
+                target: Target::NextPreset,
+            },
+            Mapping {
+                control: knob,
+                target: Target::LayerOpacity(0),
+            },
+            Mapping {
+                control: pad,
+                target: Target::Preset(2),
+            },
+        ]);
+
+        // Turning the knob up goes to the next preset once, the opacity follows every step.
+        let turned = moved(&mut midi, knob, &[0.1, 0.2, 0.3, 0.4]);
+        let triggers: Vec<_> = turned.iter().filter(|(t, _)| t.is_trigger()).collect();
+        assert_eq!(triggers, vec![&(Target::NextPreset, 0.1)]);
+        assert_eq!(turned.len(), 5);
+        // Down to 0 and up again is another one.
+        let turned = moved(&mut midi, knob, &[0.0, 0.5]);
+        assert!(turned.contains(&(Target::NextPreset, 0.5)));
+
+        // Pads go off on every press, not on the release.
+        let pressed = moved(&mut midi, pad, &[1.0, 0.0, 0.8, 0.0]);
+        assert_eq!(
+            pressed,
+            vec![(Target::Preset(2), 1.0), (Target::Preset(2), 0.8)]
+        );
+    }
+}
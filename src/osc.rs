@@ -0,0 +1,598 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::*;
+
+use crate::audio_processor::AudioProcessor;
+use crate::fft_buffer::FFTDimensions;
+use crate::midi::Target;
+use crate::post::Effect;
+use crate::preset::{Presets, Transition};
+use crate::renderer::Renderer;
+use crate::shaders;
+
+/// Sized for what fits in one datagram on a local network.
+const MAX_PACKET: usize = 1536;
+/// What `/tempo` is clamped to, the beat period can't get to 0 or overflow.
+const MIN_BPM: f32 = 1.0;
+const MAX_BPM: f32 = 999.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+}
+
+impl Arg {
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Arg::Int(i) => Some(i as f32),
+            Arg::Float(f) => Some(f),
+            Arg::Bool(b) => Some(b as i32 as f32),
+            Arg::Str(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub address: String,
+    pub args: Vec<Arg>,
+}
+
+impl Message {
+    pub fn new(address: &str, args: Vec<Arg>) -> Self {
+        Self {
+            address: address.to_string(),
+            args,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_str(&mut bytes, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(|arg| match arg {
+                Arg::Int(_) => 'i',
+                Arg::Float(_) => 'f',
+                Arg::Str(_) => 's',
+                Arg::Bool(true) => 'T',
+                Arg::Bool(false) => 'F',
+            }))
+            .collect();
+        write_str(&mut bytes, &tags);
+        for arg in &self.args {
+            match arg {
+                Arg::Int(i) => bytes.extend(i.to_be_bytes()),
+                Arg::Float(f) => bytes.extend(f.to_be_bytes()),
+                Arg::Str(s) => write_str(&mut bytes, s),
+                Arg::Bool(_) => {}
+            }
+        }
+        bytes
+    }
+
+    fn arg(&self, index: usize) -> Option<&Arg> {
+        self.args.get(index)
+    }
+
+    fn f32(&self, index: usize) -> Option<f32> {
+        self.arg(index).and_then(Arg::as_f32)
+    }
+}
+
+/// The messages in a packet, bundles are flattened and their time tags ignored.
+pub fn decode(packet: &[u8]) -> Result<Vec<Message>> {
+    let mut reader = Reader { bytes: packet };
+    if packet.starts_with(b"#bundle\0") {
+        reader.str()?;
+        reader.take(8).context("Bundle without a time tag")?;
+        let mut messages = vec![];
+        while !reader.bytes.is_empty() {
+            let size = reader.i32()?;
+            ensure!(size >= 0, "Negative bundle element size");
+            let element = reader
+                .take(size as usize)
+                .context("Bundle element is cut short")?;
+            messages.extend(decode(element)?);
+        }
+        return Ok(messages);
+    }
+
+    let address = reader.str()?;
+    ensure!(address.starts_with('/'), "`{}` isn't an address", address);
+    // Some old senders leave the type tags out.
+    let tags = if reader.bytes.is_empty() {
+        ",".to_string()
+    } else {
+        reader.str()?
+    };
+    let mut args = vec![];
+    for tag in tags.chars().skip(1) {
+        args.push(match tag {
+            'i' => Arg::Int(reader.i32()?),
+            'f' => Arg::Float(f32::from_bits(reader.i32()? as u32)),
+            's' | 'S' => Arg::Str(reader.str()?),
+            'h' => Arg::Int(reader.i64()? as i32),
+            'd' => Arg::Float(f64::from_bits(reader.i64()? as u64) as f32),
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            'N' | 'I' => continue,
+            _ => bail!("Unsupported type tag `{}` in {}", tag, address),
+        });
+    }
+    Ok(vec![Message { address, args }])
+}
+
+/// Layers and params are counted from 1 in addresses, from 0 here.
+fn counted_from_one(part: &str, what: &str) -> Result<usize> {
+    part.parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .with_context(|| format!("`{}` isn't a {}, they start at 1", part, what))
+}
+
+/// Strings are null terminated and padded to four bytes.
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    bytes.extend(std::iter::repeat_n(0, padding));
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if count > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        let bytes = self.take(4).context("Message is cut short")?;
+        Ok(i32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        let bytes = self.take(8).context("Message is cut short")?;
+        Ok(i64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self
+            .bytes
+            .iter()
+            .position(|b| *b == 0)
+            .context("String without an end")?;
+        let s = std::str::from_utf8(&self.bytes[..len])?.to_string();
+        self.take((len / 4 + 1) * 4)
+            .context("String is cut short")?;
+        Ok(s)
+    }
+}
+
+/// What an address asks for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Shader(String),
+    NextShader,
+    PreviousShader,
+    /// A name or an index, with the seconds to fade in over.
+    Preset(Arg, Option<f32>),
+    Set(Target, f32),
+    Beat,
+    /// 0 or less goes back to detecting beats.
+    Tempo(f32),
+}
+
+impl Command {
+    pub fn parse(message: &Message) -> Result<Self> {
+        let parts: Vec<&str> = message.address[1..].split('/').collect();
+        let value = || {
+            message
+                .f32(0)
+                .with_context(|| format!("{} needs a number", message.address))
+        };
+        let command = match parts[..] {
+            ["shader"] => match message.arg(0) {
+                Some(Arg::Str(name)) => Command::Shader(name.clone()),
+                _ => bail!("/shader needs a name"),
+            },
+            ["shader", "next"] => Command::NextShader,
+            ["shader", "previous"] => Command::PreviousShader,
+            ["preset"] => Command::Preset(
+                message
+                    .arg(0)
+                    .context("/preset needs a name or index")?
+                    .clone(),
+                message.f32(1),
+            ),
+            ["preset", "next"] => Command::Set(Target::NextPreset, 1.0),
+            ["preset", "previous"] => Command::Set(Target::PreviousPreset, 1.0),
+            ["effect", effect, what] => {
+                let effect = Effect::from_name(effect)
+                    .with_context(|| format!("No effect called `{}`", effect))?;
+                match what {
+                    "strength" => Command::Set(Target::EffectStrength(effect), value()?),
+                    "beat" => Command::Set(Target::EffectAudio(effect), value()?),
+                    _ => bail!("Unknown address {}", message.address),
+                }
+            }
+            ["layer", layer, what] => {
+                let layer = counted_from_one(layer, "layer")?;
+                match what {
+                    "opacity" => Command::Set(Target::LayerOpacity(layer), value()?),
+                    "beat" => Command::Set(Target::LayerAudio(layer), value()?),
+                    _ => bail!("Unknown address {}", message.address),
+                }
+            }
+            ["layer", layer, "param", param] => {
+                let layer = counted_from_one(layer, "layer")?;
+                let param = counted_from_one(param, "param")?;
+                Command::Set(Target::ShaderParam(layer, param), value()?)
+            }
+            ["beat"] => Command::Beat,
+            ["tempo"] => Command::Tempo(value()?),
+            _ => bail!("Unknown address {}", message.address),
+        };
+        Ok(command)
+    }
+
+    /// Shaders are looked up by file name in the list, or taken as a path.
+    pub fn apply(
+        self,
+        renderer: &mut Renderer,
+        shaders: &[PathBuf],
+        presets: &mut Presets,
+        audio: &AudioProcessor,
+    ) {
+        let current = renderer.layers[renderer.selected_layer].path.clone();
+        match self {
+            Command::Shader(name) => {
+                let path = shaders
+                    .iter()
+                    .find(|p| {
+                        p.file_name().is_some_and(|n| *n == *name)
+                            || p.file_stem().is_some_and(|n| *n == *name)
+                    })
+                    .cloned()
+                    .unwrap_or_else(|| PathBuf::from(name));
+                renderer.change_shader(&path);
+            }
+            Command::NextShader => {
+                if let Some(shader) = shaders::step(shaders, &current, 1) {
+                    renderer.change_shader(shader);
+                }
+            }
+            Command::PreviousShader => {
+                if let Some(shader) = shaders::step(shaders, &current, -1) {
+                    renderer.change_shader(shader);
+                }
+            }
+            Command::Preset(which, seconds) => {
+                let index = match which {
+                    Arg::Str(name) => presets.list.iter().position(|p| Presets::name(p) == name),
+                    // Counted from 1 like the layers.
+                    arg => arg
+                        .as_f32()
+                        .and_then(|i| (i as usize).checked_sub(1))
+                        .filter(|i| *i < presets.list.len()),
+                };
+                let transition = seconds.map(|s| {
+                    let transition = if s > 0.0 {
+                        Transition::Fade
+                    } else {
+                        Transition::Cut
+                    };
+                    (transition, s)
+                });
+                match index {
+                    Some(index) => presets.load_with(index, transition, renderer, audio),
+                    None => eprintln!("OSC: No such preset"),
+                }
+            }
+            Command::Set(target, value) => target.apply(value, renderer, presets, audio),
+            // Already handled when polling.
+            Command::Beat | Command::Tempo(_) => {}
+        }
+    }
+}
+
+/// A small OSC server over UDP, for show control software to drive the visuals.
+///
+/// Addresses, layers are counted from 1 and values go from 0 to 1 like with MIDI:
+/// - `/shader <name or path>`, `/shader/next`, `/shader/previous`
+/// - `/preset <name or index> [seconds]`, `/preset/next`, `/preset/previous`,
+///   with seconds the preset fades in for that long instead of its own transition
+/// - `/effect/<effect>/strength <value>`, `/effect/<effect>/beat <value>`
+/// - `/layer/<n>/opacity <value>`, `/layer/<n>/beat <value>`
+/// - `/layer/<n>/param/<i> <value>` for the `i`th `//! param` of the layer's shader
+/// - `/beat` for a beat right now, `/tempo <bpm>` for beats on their own, `/tempo 0` to
+///   detect them from the audio again.
+///
+/// With a broadcast address, every frame sends `/audio/level`, `/audio/bands <low> <mid> <high>`
+/// and `/audio/pulse`, and `/audio/beat <count>` on every beat.
+pub struct Osc {
+    socket: Option<UdpSocket>,
+    pub port: u16,
+    /// Where the features go, like `127.0.0.1:9001`, nowhere when empty.
+    pub broadcast: String,
+    /// Sends from a port of its own, so broadcasting works without listening.
+    broadcast_to: Option<(UdpSocket, SocketAddr)>,
+    /// The beat period and when the next one is due, while a tempo is set.
+    tempo: Option<(Duration, Instant)>,
+    last_beat: u32,
+    /// The last message that came in, or what was wrong with it.
+    pub last: Option<String>,
+}
+
+impl Osc {
+    /// Without a port it's off until `listen`.
+    pub fn new(port: Option<u16>, broadcast: Option<&str>) -> Self {
+        let mut osc = Self {
+            socket: None,
+            port: port.unwrap_or(9000),
+            broadcast: String::new(),
+            broadcast_to: None,
+            tempo: None,
+            last_beat: 0,
+            last: None,
+        };
+        if let Some(broadcast) = broadcast {
+            osc.set_broadcast(broadcast);
+            if !osc.is_broadcasting() {
+                eprintln!("OSC: `{}` isn't an address like 127.0.0.1:9001", broadcast);
+            }
+        }
+        if port.is_some() {
+            osc.listen();
+        }
+        osc
+    }
+
+    /// Binds to the port on every interface, failing leaves it off.
+    pub fn listen(&mut self) {
+        self.socket = None;
+        let socket = UdpSocket::bind(("0.0.0.0", self.port)).and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            std::io::Result::Ok(socket)
+        });
+        match socket {
+            std::result::Result::Ok(socket) => self.socket = Some(socket),
+            Err(e) => eprintln!("OSC: Failed to listen on port {}: {}", self.port, e),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.socket = None;
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// The port when listening, for the settings.
+    pub fn listening_port(&self) -> Option<u16> {
+        self.socket.as_ref().map(|_| self.port)
+    }
+
+    /// An address that doesn't parse turns broadcasting off.
+    pub fn set_broadcast(&mut self, addr: &str) {
+        self.broadcast = addr.to_string();
+        self.broadcast_to = None;
+        let Some(addr) = addr.parse::<SocketAddr>().ok() else {
+            return;
+        };
+        let any: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        match UdpSocket::bind(any) {
+            std::result::Result::Ok(socket) => self.broadcast_to = Some((socket, addr)),
+            Err(e) => eprintln!("OSC: Failed to open a socket to send to {}: {}", addr, e),
+        }
+    }
+
+    pub fn is_broadcasting(&self) -> bool {
+        self.broadcast_to.is_some()
+    }
+
+    /// The commands that came in since the last call. Keeps the tempo going too.
+    pub fn poll(&mut self, audio: &AudioProcessor) -> Vec<Command> {
+        let mut commands = vec![];
+        if let Some(socket) = &self.socket {
+            let mut buf = [0; MAX_PACKET];
+            while let std::result::Result::Ok((len, _)) = socket.recv_from(&mut buf) {
+                match decode(&buf[..len]) {
+                    std::result::Result::Ok(messages) => {
+                        for message in messages {
+                            self.last = Some(format!("{} {:?}", message.address, message.args));
+                            match Command::parse(&message) {
+                                std::result::Result::Ok(command) => commands.push(command),
+                                Err(e) => self.last = Some(format!("{:#}", e)),
+                            }
+                        }
+                    }
+                    Err(e) => self.last = Some(format!("{:#}", e)),
+                }
+            }
+        }
+        // Beats and tempo are handled here so they don't wait for the ui.
+        commands.retain(|command| match *command {
+            Command::Beat => {
+                audio.external_beat();
+                if let Some((period, next)) = &mut self.tempo {
+                    *next = Instant::now() + *period;
+                }
+                false
+            }
+            Command::Tempo(bpm) if !bpm.is_finite() => {
+                self.last = Some(format!("/tempo {} isn't a tempo", bpm));
+                false
+            }
+            Command::Tempo(bpm) => {
+                if bpm > 0.0 {
+                    let bpm = bpm.clamp(MIN_BPM, MAX_BPM);
+                    let period = Duration::from_secs_f32(60.0 / bpm);
+                    let next = self.tempo.map_or_else(Instant::now, |(_, next)| next);
+                    self.tempo = Some((period, next));
+                } else {
+                    self.tempo = None;
+                    audio.detect_beats();
+                }
+                false
+            }
+            _ => true,
+        });
+        if let Some((period, next)) = &mut self.tempo {
+            let now = Instant::now();
+            if now >= *next {
+                audio.external_beat();
+                // Skips the beats that were missed instead of catching up.
+                while *next <= now && !period.is_zero() {
+                    *next += *period;
+                }
+            }
+        }
+        commands
+    }
+
+    /// Sends the features of the newest fft run to the broadcast address.
+    pub fn broadcast(&mut self, audio: &AudioProcessor, dimensions: FFTDimensions) {
+        let Some((socket, addr)) = &self.broadcast_to else {
+            return;
+        };
+        let width = dimensions.texture_width() as usize;
+        let bin_freq = audio.sample_rate() as f32 / dimensions.fft_size as f32;
+        let bands = {
+            let fft = audio.fft_texture();
+            let fft = fft.lock().unwrap();
+            let newest = &fft[..width];
+            let band = |low: f32, high: f32| {
+                let bins = (low / bin_freq) as usize..((high / bin_freq) as usize).min(width);
+                let count = bins.len().max(1);
+                newest.get(bins).map_or(0.0, |b| b.iter().sum::<f32>()) / count as f32
+            };
+            [
+                band(20.0, 250.0),
+                band(250.0, 4000.0),
+                band(4000.0, 20000.0),
+            ]
+        };
+        let level = {
+            let wave = audio.wave_texture();
+            let wave = wave.lock().unwrap();
+            (wave.iter().map(|s| s * s).sum::<f32>() / wave.len().max(1) as f32).sqrt()
+        };
+        let beat = audio.beat();
+
+        let mut messages = vec![
+            Message::new("/audio/level", vec![Arg::Float(level)]),
+            Message::new("/audio/bands", bands.into_iter().map(Arg::Float).collect()),
+            Message::new("/audio/pulse", vec![Arg::Float(beat.pulse())]),
+        ];
+        if beat.count != self.last_beat {
+            self.last_beat = beat.count;
+            messages.push(Message::new(
+                "/audio/beat",
+                vec![Arg::Int(beat.count as i32)],
+            ));
+        }
+        for message in messages {
+            if let Err(e) = socket.send_to(&message.encode(), addr) {
+                eprintln!("OSC: Failed to send to {}: {}", addr, e);
+                self.broadcast_to = None;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let message = Message::new(
+            "/layer/1/opacity",
+            vec![
+                Arg::Float(0.5),
+                Arg::Int(-3),
+                Arg::Str("abcd".to_string()),
+                Arg::Bool(true),
+                Arg::Bool(false),
+            ],
+        );
+        let bytes = message.encode();
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(decode(&bytes).unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn decode_bundle() {
+        let a = Message::new("/beat", vec![]);
+        let b = Message::new("/tempo", vec![Arg::Float(120.0)]);
+        let mut packet = vec![];
+        write_str(&mut packet, "#bundle");
+        packet.extend([0; 8]);
+        for message in [&a, &b] {
+            let bytes = message.encode();
+            packet.extend((bytes.len() as i32).to_be_bytes());
+            packet.extend(bytes);
+        }
+        assert_eq!(decode(&packet).unwrap(), vec![a, b]);
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let bytes = Message::new("/tempo", vec![Arg::Float(120.0)]).encode();
+        // Just the address is a message without type tags.
+        for len in (0..bytes.len()).filter(|len| *len != 8) {
+            assert!(decode(&bytes[..len]).is_err(), "{} bytes decoded", len);
+        }
+
+        let mut packet = vec![];
+        write_str(&mut packet, "#bundle");
+        packet.extend([0; 8]);
+        packet.extend(100i32.to_be_bytes());
+        packet.extend(&bytes);
+        assert!(decode(&packet).is_err());
+        packet[16..20].copy_from_slice(&(-1i32).to_be_bytes());
+        assert!(decode(&packet).is_err());
+    }
+
+    #[test]
+    fn parse_commands() {
+        let parse = |address: &str, args| Command::parse(&Message::new(address, args));
+        assert_eq!(
+            parse("/layer/2/param/1", vec![Arg::Float(0.25)]).unwrap(),
+            Command::Set(Target::ShaderParam(1, 0), 0.25)
+        );
+        assert_eq!(
+            parse("/tempo", vec![Arg::Int(128)]).unwrap(),
+            Command::Tempo(128.0)
+        );
+        assert!(parse("/layer/0/opacity", vec![Arg::Float(1.0)]).is_err());
+        assert!(parse("/layer/1/opacity", vec![]).is_err());
+        assert!(parse("/nope", vec![]).is_err());
+    }
+
+    #[test]
+    fn broadcasts_without_listening() {
+        let mut osc = Osc::new(None, Some("127.0.0.1:9001"));
+        assert!(!osc.is_listening());
+        assert!(osc.is_broadcasting());
+        osc.set_broadcast("not an address");
+        assert!(!osc.is_broadcasting());
+    }
+}
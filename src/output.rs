@@ -0,0 +1,107 @@
+use anyhow::*;
+use winit::window::{Fullscreen, Window, WindowId};
+
+use crate::blit::{Blitter, Tonemap, UpscaleFilter};
+use crate::program::PassResources;
+
+/// What an output window shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputSource {
+    /// The same as the main window, all layers with the post effects.
+    #[default]
+    Frame,
+    /// A single layer by index, without post effects. Falls back to the frame if it's gone.
+    Layer(usize),
+}
+
+/// An extra window without the ui, e.g. for a projector, drawn from the same frame as the main window.
+pub struct OutputWindow {
+    pub window: Window,
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+    blitter: Blitter,
+    pub source: OutputSource,
+}
+
+impl OutputWindow {
+    pub fn new(
+        window: Window,
+        instance: &wgpu::Instance,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+    ) -> Result<Self> {
+        let surface = unsafe { instance.create_surface(&window) }?;
+        let caps = surface.get_capabilities(adapter);
+        ensure!(
+            !caps.formats.is_empty(),
+            "The adapter can't draw to the window"
+        );
+        let format = caps.formats[0];
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        surface.configure(device, &config);
+
+        Ok(Self {
+            window,
+            surface,
+            config,
+            blitter: Blitter::new(device, format, false),
+            source: OutputSource::default(),
+        })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            self.config.width = size.width;
+            self.config.height = size.height;
+            self.surface.configure(device, &self.config);
+        }
+    }
+
+    /// Borderless on the monitor the window is on, or back to a window.
+    pub fn toggle_fullscreen(&self) {
+        let fullscreen = match self.window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(self.window.current_monitor())),
+        };
+        self.window.set_fullscreen(fullscreen);
+    }
+
+    /// Draws `source` stretched over the window, the texture has to be presented after the submit.
+    /// A lost surface is set up again once.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        source: &wgpu::TextureView,
+        filter: UpscaleFilter,
+        tonemap: Tonemap,
+    ) -> Result<wgpu::SurfaceTexture, wgpu::SurfaceError> {
+        let output = match self.surface.get_current_texture() {
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(device, &self.config);
+                self.surface.get_current_texture()?
+            }
+            result => result?,
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.blitter
+            .blit(device, encoder, resources, source, &view, filter, tonemap);
+        std::result::Result::Ok(output)
+    }
+}
@@ -0,0 +1,156 @@
+use wgpu::util::DeviceExt;
+
+use crate::program::PassResources;
+use crate::shader_meta::ParticlesDesc;
+use crate::shaders::Vertex;
+
+pub const WORKGROUP_SIZE: u32 = 64;
+/// Generated by `shaders::build_source`, calls the users `cs_particle`.
+pub const COMPUTE_ENTRY_POINT: &str = "cs_main_particles";
+
+/// Matches `Particle` in the prelude.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Particle {
+    position: glam::Vec4,
+    velocity: glam::Vec4,
+    color: glam::Vec4,
+}
+
+impl Particle {
+    /// The particles are read per instance when drawing, after the quad's vertex attributes.
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![2 => Float32x4, 3 => Float32x4, 4 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+
+    /// Spread evenly over the screen so there is something to see before the shader moves them.
+    fn seed(index: u32) -> Self {
+        // The R2 sequence, http://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+        const A1: f32 = 0.754_877_7;
+        const A2: f32 = 0.569_840_3;
+        let i = index as f32;
+        Self {
+            position: glam::vec4((0.5 + A1 * i).fract(), (0.5 + A2 * i).fract(), 0.0, 0.003),
+            velocity: glam::Vec4::ZERO,
+            color: glam::Vec4::ONE,
+        }
+    }
+}
+
+/// The particle buffer of a shader and the pipelines that update and draw it.
+pub struct Particles {
+    count: u32,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    compute_pipeline: wgpu::ComputePipeline,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl Particles {
+    pub fn new(
+        device: &wgpu::Device,
+        resources: &PassResources,
+        module: &wgpu::ShaderModule,
+        desc: &ParticlesDesc,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let particles: Vec<Particle> = (0..desc.count).map(Particle::seed).collect();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Buffer"),
+            contents: bytemuck::cast_slice(&particles),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &resources.particle_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("particle_bind_group"),
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Compute Pipeline"),
+            layout: Some(&resources.compute_pipeline_layout),
+            module,
+            entry_point: COMPUTE_ENTRY_POINT,
+        });
+
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Render Pipeline"),
+            layout: Some(&resources.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: "vs_main_particle",
+                buffers: &[Vertex::desc(), Particle::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: "fs_main_particle",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // Additive, so overlapping particles glow.
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            count: desc.count,
+            buffer,
+            bind_group,
+            compute_pipeline,
+            render_pipeline,
+        }
+    }
+
+    /// Runs the users `cs_particle` on every particle,
+    /// `bind_groups` are the same as for the passes, including the feedback bind group.
+    pub fn update(&self, encoder: &mut wgpu::CommandEncoder, bind_groups: &[&wgpu::BindGroup]) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Pass"),
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            compute_pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        compute_pass.set_bind_group(bind_groups.len() as u32, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// Draws the particles into a pass that already has the pass bind groups set.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        resources: &'a PassResources,
+    ) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, resources.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.buffer.slice(..));
+        render_pass.set_index_buffer(resources.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..resources.num_indices, 0, 0..self.count);
+    }
+}
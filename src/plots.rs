@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use egui::plot::{Line, Plot, PlotPoints, VLine};
+use egui::{Color32, ColorImage, TextureHandle, TextureOptions};
+
+use crate::audio_processor::AudioProcessor;
+use crate::fft_buffer::FFTDimensions;
+use crate::renderer::Renderer;
+
+/// How far back the beat plot goes.
+const HISTORY: Duration = Duration::from_secs(5);
+
+/// Plots of the audio data the shaders get, for tuning the analysis without writing a shader.
+#[derive(Default)]
+pub struct AudioPlots {
+    pub open: bool,
+    log_frequency: bool,
+    decibels: bool,
+    spectrogram: Option<TextureHandle>,
+    // The pulse and beats since the plots were opened, up to `HISTORY` back.
+    pulse: VecDeque<(Instant, f32)>,
+    beats: VecDeque<Instant>,
+    last_beat: u32,
+}
+
+impl AudioPlots {
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        renderer: &Renderer,
+        audio: &AudioProcessor,
+        dimensions: FFTDimensions,
+    ) {
+        let now = Instant::now();
+        let beat = audio.beat();
+        if beat.count != self.last_beat {
+            self.last_beat = beat.count;
+            self.beats.push_back(beat.last.unwrap_or(now));
+        }
+        self.pulse.push_back((now, beat.pulse_at(now)));
+        let start = now.checked_sub(HISTORY).unwrap_or(now);
+        self.beats.retain(|t| *t >= start);
+        while self.pulse.front().is_some_and(|(t, _)| *t < start) {
+            self.pulse.pop_front();
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Audio plots")
+            .open(&mut open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.spectrum(ui, renderer, audio, dimensions);
+                    self.spectrogram(ui, renderer, dimensions);
+                    Self::waveform(ui, renderer, audio);
+                    self.beats(ui, now);
+                });
+            });
+        self.open = open;
+    }
+
+    /// The newest fft run by frequency.
+    fn spectrum(
+        &mut self,
+        ui: &mut egui::Ui,
+        renderer: &Renderer,
+        audio: &AudioProcessor,
+        dimensions: FFTDimensions,
+    ) {
+        ui.horizontal(|ui| {
+            ui.strong("Spectrum");
+            ui.checkbox(&mut self.log_frequency, "Log frequency");
+            ui.checkbox(&mut self.decibels, "dB");
+        });
+        let width = dimensions.texture_width() as usize;
+        let bin_freq = audio.sample_rate() as f64 / dimensions.fft_size as f64;
+        // The texture has the levels between the analysis range as 0 to 1.
+        let analysis = audio.analysis();
+        let (db_lo, db_hi) = (
+            analysis.db_min.min(analysis.db_max - 1.0) as f64,
+            analysis.db_max as f64,
+        );
+        let level = |value: f32| {
+            if self.decibels {
+                db_lo + value as f64 * (db_hi - db_lo)
+            } else {
+                value as f64
+            }
+        };
+        let points: PlotPoints = renderer.fft_data()[..width]
+            .iter()
+            .enumerate()
+            // There's no 0 on a log axis.
+            .skip(self.log_frequency as usize)
+            .map(|(i, value)| {
+                let freq = analysis.binning.bins(i, width).start as f64 * bin_freq;
+                let x = if self.log_frequency {
+                    freq.log10()
+                } else {
+                    freq
+                };
+                [x, level(*value)]
+            })
+            .collect();
+        let (y_min, y_max) = if self.decibels {
+            (db_lo, db_hi)
+        } else {
+            (0.0, 1.0)
+        };
+        let log_frequency = self.log_frequency;
+        Plot::new("spectrum")
+            .height(150.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .include_y(y_min)
+            .include_y(y_max)
+            .x_axis_formatter(move |x, _| {
+                let freq = if log_frequency { 10f64.powf(x) } else { x };
+                format!("{:.0} Hz", freq)
+            })
+            .show(ui, |plot| plot.line(Line::new(points)));
+    }
+
+    /// The whole fft texture, every run the shaders can look back at, the newest at the top.
+    fn spectrogram(&mut self, ui: &mut egui::Ui, renderer: &Renderer, dimensions: FFTDimensions) {
+        ui.strong("Spectrogram");
+        let size = [
+            dimensions.texture_width() as usize,
+            dimensions.texture_height() as usize,
+        ];
+        let pixels = renderer.fft_data().iter().map(|v| heat(*v)).collect();
+        let image = ColorImage { size, pixels };
+        let texture = match &mut self.spectrogram {
+            Some(texture) => {
+                texture.set(image, TextureOptions::LINEAR);
+                texture
+            }
+            None => self.spectrogram.insert(ui.ctx().load_texture(
+                "spectrogram",
+                image,
+                TextureOptions::LINEAR,
+            )),
+        };
+        ui.image(texture.id(), [ui.available_width(), 150.0]);
+    }
+
+    /// The samples that went into the newest fft run.
+    fn waveform(ui: &mut egui::Ui, renderer: &Renderer, audio: &AudioProcessor) {
+        ui.strong("Waveform");
+        let ms_per_sample = 1000.0 / audio.sample_rate() as f64;
+        let points: PlotPoints = renderer
+            .wave_data()
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| [i as f64 * ms_per_sample, *sample as f64])
+            .collect();
+        Plot::new("waveform")
+            .height(120.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .include_y(-1.0)
+            .include_y(1.0)
+            .x_axis_formatter(|x, _| format!("{:.0} ms", x))
+            .show(ui, |plot| plot.line(Line::new(points)));
+    }
+
+    /// The beat pulse over the last seconds with a line on every beat.
+    fn beats(&self, ui: &mut egui::Ui, now: Instant) {
+        ui.strong("Beats");
+        let seconds_ago = |t: Instant| -now.saturating_duration_since(t).as_secs_f64();
+        let points: PlotPoints = self
+            .pulse
+            .iter()
+            .map(|(t, pulse)| [seconds_ago(*t), *pulse as f64])
+            .collect();
+        Plot::new("beats")
+            .height(100.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .include_x(-HISTORY.as_secs_f64())
+            .include_x(0.0)
+            .include_y(0.0)
+            .include_y(1.0)
+            .x_axis_formatter(|x, _| format!("{:.1} s", x))
+            .show(ui, |plot| {
+                for beat in &self.beats {
+                    plot.vline(VLine::new(seconds_ago(*beat)).color(Color32::RED));
+                }
+                plot.line(Line::new(points));
+            });
+    }
+}
+
+/// Black through red and yellow to white.
+fn heat(value: f32) -> Color32 {
+    let channel = |offset: f32| ((value * 3.0 - offset).clamp(0.0, 1.0) * 255.0) as u8;
+    Color32::from_rgb(channel(0.0), channel(1.0), channel(2.0))
+}
@@ -0,0 +1,545 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::frame_timer::FrameTimer;
+use crate::program::PassResources;
+use crate::render_target::RenderTarget;
+use crate::shaders::Vertex;
+use crate::texture::{Texture, TEXTURE_DIR};
+
+/// The built-in effects, shaders turn them on with `//! post <effect>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Bloom,
+    Chromatic,
+    Grain,
+    Vignette,
+    /// Color grading with a lookup table image, see `//! post lut`.
+    Lut,
+    Kaleidoscope,
+}
+
+impl Effect {
+    pub const ALL: [Effect; 6] = [
+        Effect::Bloom,
+        Effect::Chromatic,
+        Effect::Grain,
+        Effect::Vignette,
+        Effect::Lut,
+        Effect::Kaleidoscope,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|effect| effect.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Bloom => "bloom",
+            Effect::Chromatic => "chromatic",
+            Effect::Grain => "grain",
+            Effect::Vignette => "vignette",
+            Effect::Lut => "lut",
+            Effect::Kaleidoscope => "kaleidoscope",
+        }
+    }
+
+    /// The lut and kaleidoscope blend between the plain and the processed frame, so 1 is all the way.
+    pub fn max_strength(&self) -> f32 {
+        match self {
+            Effect::Lut | Effect::Kaleidoscope => 1.0,
+            _ => 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EffectSettings {
+    pub strength: f32,
+    /// Added to the strength on a beat.
+    pub audio: f32,
+}
+
+impl EffectSettings {
+    pub fn at(&self, beat: f32) -> f32 {
+        self.strength + self.audio * beat
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostSettings {
+    /// In the order of `Effect::ALL`, all off by default.
+    #[serde(with = "effects_by_name")]
+    pub effects: [EffectSettings; 6],
+    /// How bright a pixel has to be to bloom.
+    pub bloom_threshold: f32,
+    pub kaleidoscope_segments: u32,
+    /// Relative to `texture::TEXTURE_DIR`, a strip of square slices, one for each blue level.
+    pub lut: Option<PathBuf>,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            effects: Default::default(),
+            bloom_threshold: 0.7,
+            kaleidoscope_segments: 6,
+            lut: None,
+        }
+    }
+}
+
+impl PostSettings {
+    pub fn get(&self, effect: Effect) -> &EffectSettings {
+        &self.effects[effect as usize]
+    }
+
+    pub fn get_mut(&mut self, effect: Effect) -> &mut EffectSettings {
+        &mut self.effects[effect as usize]
+    }
+}
+
+/// Effects are written as `bloom = { strength = 1.0, audio = 0.5 }`, ones left out are off.
+mod effects_by_name {
+    use std::collections::BTreeMap;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Effect, EffectSettings};
+
+    pub fn serialize<S: Serializer>(
+        effects: &[EffectSettings; 6],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let named: BTreeMap<&str, &EffectSettings> = Effect::ALL
+            .iter()
+            .zip(effects)
+            .filter(|(_, settings)| **settings != EffectSettings::default())
+            .map(|(effect, settings)| (effect.name(), settings))
+            .collect();
+        named.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[EffectSettings; 6], D::Error> {
+        let named = BTreeMap::<String, EffectSettings>::deserialize(deserializer)?;
+        let mut effects: [EffectSettings; 6] = Default::default();
+        for (name, settings) in named {
+            let effect = Effect::from_name(&name)
+                .ok_or_else(|| D::Error::custom(format!("unknown effect `{}`", name)))?;
+            effects[effect as usize] = settings;
+        }
+        std::result::Result::Ok(effects)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    bloom: f32,
+    bloom_threshold: f32,
+    chromatic: f32,
+    grain: f32,
+    vignette: f32,
+    lut: f32,
+    kaleidoscope: f32,
+    segments: f32,
+    time: f32,
+    lut_size: f32,
+    _padding: [f32; 2],
+}
+
+/// Runs the effects over the image pass, at the render size.
+pub struct PostProcessor {
+    pub settings: PostSettings,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_h_pipeline: wgpu::RenderPipeline,
+    blur_v_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    uniform: PostUniform,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    // Half size, the bloom ends up in the first one.
+    bloom_targets: [RenderTarget; 2],
+    output: RenderTarget,
+    lut: Texture,
+}
+
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BLOOM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+impl PostProcessor {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post_shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("post.wgsl"))),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                sampler_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                sampler_entry(5),
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let make_pipeline = |entry_point, format| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[Vertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        let uniform = PostUniform {
+            bloom: 0.0,
+            bloom_threshold: 0.0,
+            chromatic: 0.0,
+            grain: 0.0,
+            vignette: 0.0,
+            lut: 0.0,
+            kaleidoscope: 0.0,
+            segments: 2.0,
+            time: 0.0,
+            lut_size: 1.0,
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (bloom_targets, output) = Self::make_targets(device, size);
+        Self {
+            settings: PostSettings::default(),
+            bind_group_layout,
+            bright_pipeline: make_pipeline("fs_bright", BLOOM_FORMAT),
+            blur_h_pipeline: make_pipeline("fs_blur_h", BLOOM_FORMAT),
+            blur_v_pipeline: make_pipeline("fs_blur_v", BLOOM_FORMAT),
+            composite_pipeline: make_pipeline("fs_composite", OUTPUT_FORMAT),
+            uniform,
+            uniform_buffer,
+            sampler,
+            bloom_targets,
+            output,
+            lut: identity_lut(device, queue),
+        }
+    }
+
+    fn make_targets(
+        device: &wgpu::Device,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> ([RenderTarget; 2], RenderTarget) {
+        let (width, height) = (size.width / 2, size.height / 2);
+        let bloom_targets = [
+            RenderTarget::new(device, "bloom_0", width, height, BLOOM_FORMAT),
+            RenderTarget::new(device, "bloom_1", width, height, BLOOM_FORMAT),
+        ];
+        let output = RenderTarget::new(
+            device,
+            "post_output",
+            size.width,
+            size.height,
+            OUTPUT_FORMAT,
+        );
+        (bloom_targets, output)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) {
+        (self.bloom_targets, self.output) = Self::make_targets(device, size);
+    }
+
+    /// The bloom and the finished frame, stale while every effect is off.
+    pub fn targets(&self) -> [(&'static str, &RenderTarget); 2] {
+        [("bloom", &self.bloom_targets[0]), ("post", &self.output)]
+    }
+
+    /// Takes the settings of a new shader and loads its lut, without one the lut is left off.
+    pub fn set_settings(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: PostSettings,
+    ) {
+        self.lut = match &settings.lut {
+            Some(path) => load_lut(device, queue, path).unwrap_or_else(|e| {
+                eprintln!("Failed to load the lut: {:?}", e);
+                identity_lut(device, queue)
+            }),
+            None => identity_lut(device, queue),
+        };
+        self.settings = settings;
+    }
+
+    /// The name of the loaded lut.
+    pub fn lut_name(&self) -> Option<String> {
+        let path = self.settings.lut.as_ref()?;
+        (self.lut.size.height > 1).then(|| path.display().to_string())
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, beat: f32, time: f32) {
+        let strength = |effect| self.settings.get(effect).at(beat).max(0.0);
+        let has_lut = self.lut.size.height > 1;
+        self.uniform = PostUniform {
+            bloom: strength(Effect::Bloom),
+            bloom_threshold: self.settings.bloom_threshold,
+            chromatic: strength(Effect::Chromatic),
+            grain: strength(Effect::Grain),
+            vignette: strength(Effect::Vignette),
+            lut: if has_lut { strength(Effect::Lut) } else { 0.0 },
+            kaleidoscope: strength(Effect::Kaleidoscope),
+            segments: self.settings.kaleidoscope_segments.max(2) as f32,
+            time,
+            lut_size: self.lut.size.height as f32,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// Applies the effects to `source`, returns `None` when they're all off.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        source: &wgpu::TextureView,
+        timer: &mut FrameTimer,
+    ) -> Option<&RenderTarget> {
+        let u = &self.uniform;
+        let strengths = [
+            u.bloom,
+            u.chromatic,
+            u.grain,
+            u.vignette,
+            u.lut,
+            u.kaleidoscope,
+        ];
+        if strengths.iter().all(|s| *s <= 0.0) {
+            return None;
+        }
+
+        if u.bloom > 0.0 {
+            timer.mark(encoder, "bloom");
+            let [bloom, scratch] = &self.bloom_targets;
+            let passes = [
+                (&self.bright_pipeline, source, bloom),
+                (&self.blur_h_pipeline, &bloom.view, scratch),
+                (&self.blur_v_pipeline, &scratch.view, bloom),
+            ];
+            for (pipeline, source, target) in passes {
+                // The bloom isn't read yet, but something has to be bound that isn't the target.
+                self.pass(
+                    device,
+                    encoder,
+                    resources,
+                    pipeline,
+                    source,
+                    &self.output.view,
+                    target,
+                );
+            }
+        }
+        timer.mark(encoder, "post");
+        self.pass(
+            device,
+            encoder,
+            resources,
+            &self.composite_pipeline,
+            source,
+            &self.bloom_targets[0].view,
+            &self.output,
+        );
+        Some(&self.output)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::TextureView,
+        bloom: &wgpu::TextureView,
+        target: &RenderTarget,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(bloom),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&self.lut.sampler),
+                },
+            ],
+            label: Some("post_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        resources.draw_quad(&mut render_pass);
+    }
+}
+
+/// Bound while there's no lut, the effect is skipped then.
+fn identity_lut(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::new(1, 1));
+    Texture::from_image_with_format(
+        device,
+        queue,
+        &img,
+        Some("lut"),
+        wgpu::FilterMode::Linear,
+        wgpu::AddressMode::ClampToEdge,
+        wgpu::TextureFormat::Rgba8Unorm,
+    )
+    .expect("A 1x1 texture")
+}
+
+fn load_lut(device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<Texture> {
+    let path = Path::new(TEXTURE_DIR).join(path);
+    let img = image::open(&path).with_context(|| format!("Failed to load {}", path.display()))?;
+    let size = img.height();
+    ensure!(
+        size > 1 && img.width() == size * size,
+        "{} isn't a lut strip, it has to be n*n wide and n high",
+        path.display()
+    );
+    // The strip gets wide quickly, wgpu panics on textures over the limit.
+    let max = device.limits().max_texture_dimension_2d;
+    ensure!(
+        img.width() <= max,
+        "{} is a {}x{}x{} lut, this device only does strips up to {} wide",
+        path.display(),
+        size,
+        size,
+        size,
+        max
+    );
+    // The lut holds the colors as they are, not in sRGB.
+    Texture::from_image_with_format(
+        device,
+        queue,
+        &img,
+        Some("lut"),
+        wgpu::FilterMode::Linear,
+        wgpu::AddressMode::ClampToEdge,
+        wgpu::TextureFormat::Rgba8Unorm,
+    )
+}
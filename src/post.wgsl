@@ -0,0 +1,156 @@
+// The built-in effects that run between the image pass and the surface, see `post.rs`.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+	@location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+	@location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// The strengths already include the audio, 0 is off.
+struct PostUniform {
+	bloom: f32,
+	bloom_threshold: f32,
+	chromatic: f32,
+	grain: f32,
+	vignette: f32,
+	lut: f32,
+	kaleidoscope: f32,
+	segments: f32,
+	time: f32,
+	// The lut is `lut_size` slices of `lut_size` squared, side by side.
+	lut_size: f32,
+	_padding: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> post: PostUniform;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var source_sampler: sampler;
+@group(0) @binding(3)
+var bloom_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var lut_texture: texture_2d<f32>;
+@group(0) @binding(5)
+var lut_sampler: sampler;
+
+const TAU: f32 = 6.28318530718;
+
+// Bloom, the bright parts at half size, blurred one direction at a time.
+
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+	let color = textureSample(source, source_sampler, in.tex_coords).rgb;
+	let luma = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+	let bright = color * max(luma - post.bloom_threshold, 0.0) / max(luma, 0.0001);
+	return vec4<f32>(bright, 1.0);
+}
+
+// 9 taps in 5 samples, by sampling between texels.
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+	let texel = direction / vec2<f32>(textureDimensions(source));
+	var color = textureSample(source, source_sampler, uv) * 0.2270270270;
+	color += textureSample(source, source_sampler, uv + texel * 1.3846153846) * 0.3162162162;
+	color += textureSample(source, source_sampler, uv - texel * 1.3846153846) * 0.3162162162;
+	color += textureSample(source, source_sampler, uv + texel * 3.2307692308) * 0.0702702703;
+	color += textureSample(source, source_sampler, uv - texel * 3.2307692308) * 0.0702702703;
+	return color;
+}
+
+@fragment
+fn fs_blur_h(in: VertexOutput) -> @location(0) vec4<f32> {
+	return blur(in.tex_coords, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_v(in: VertexOutput) -> @location(0) vec4<f32> {
+	return blur(in.tex_coords, vec2<f32>(0.0, 1.0));
+}
+
+// Everything else in one go.
+
+fn kaleidoscope(uv: vec2<f32>) -> vec2<f32> {
+	let p = uv - 0.5;
+	let segment = TAU / post.segments;
+	var angle = atan2(p.y, p.x);
+	angle = angle - segment * floor(angle / segment);
+	// Every other segment is mirrored so the edges line up.
+	angle = min(angle, segment - angle);
+	return 0.5 + length(p) * vec2<f32>(cos(angle), sin(angle));
+}
+
+fn chromatic_sample(uv: vec2<f32>) -> vec3<f32> {
+	let offset = (uv - 0.5) * post.chromatic * 0.02;
+	return vec3<f32>(
+		textureSample(source, source_sampler, uv + offset).r,
+		textureSample(source, source_sampler, uv).g,
+		textureSample(source, source_sampler, uv - offset).b,
+	);
+}
+
+fn lut_slice(color: vec3<f32>, slice: f32) -> vec3<f32> {
+	let n = post.lut_size;
+	let uv = vec2<f32>(
+		(slice * n + color.r * (n - 1.0) + 0.5) / (n * n),
+		(color.g * (n - 1.0) + 0.5) / n,
+	);
+	return textureSample(lut_texture, lut_sampler, uv).rgb;
+}
+
+fn linear_to_srgb(x: vec3<f32>) -> vec3<f32> {
+	let low = x * 12.92;
+	let high = 1.055 * pow(x, vec3<f32>(1.0 / 2.4)) - 0.055;
+	return select(high, low, x <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(x: vec3<f32>) -> vec3<f32> {
+	let low = x / 12.92;
+	let high = pow((x + 0.055) / 1.055, vec3<f32>(2.4));
+	return select(high, low, x <= vec3<f32>(0.04045));
+}
+
+// Blue picks the slice, so it's blended by hand between the two closest.
+// Luts are made for colors as they're shown, so this works in sRGB.
+fn lut(linear: vec3<f32>) -> vec3<f32> {
+	let color = linear_to_srgb(clamp(linear, vec3<f32>(0.0), vec3<f32>(1.0)));
+	let blue = color.b * (post.lut_size - 1.0);
+	let slice = floor(blue);
+	let next = min(slice + 1.0, post.lut_size - 1.0);
+	return srgb_to_linear(mix(lut_slice(color, slice), lut_slice(color, next), blue - slice));
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+	return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+	let uv = in.tex_coords;
+	var color = mix(
+		chromatic_sample(uv),
+		chromatic_sample(kaleidoscope(uv)),
+		clamp(post.kaleidoscope, 0.0, 1.0),
+	);
+	color += textureSample(bloom_texture, source_sampler, uv).rgb * post.bloom;
+	color = mix(color, lut(color), clamp(post.lut, 0.0, 1.0));
+
+	let edge = length(uv - 0.5) * 1.4142;
+	color *= 1.0 - post.vignette * smoothstep(0.3, 1.0, edge);
+
+	let noise = hash(uv * vec2<f32>(textureDimensions(source)) + fract(post.time) * 100.0) - 0.5;
+	color += noise * post.grain * 0.2;
+	return vec4<f32>(color, 1.0);
+}
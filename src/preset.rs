@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::audio_processor::{Analysis, AudioProcessor};
+use crate::layers::BlendMode;
+use crate::post::PostSettings;
+use crate::renderer::Renderer;
+
+/// Where presets are saved, as toml files.
+pub const PRESET_DIR: &str = "presets";
+
+/// How the last preset makes way for the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    #[default]
+    Cut,
+    /// The old layers fade out while the new ones fade in.
+    Fade,
+}
+
+impl Transition {
+    pub const ALL: [Transition; 2] = [Transition::Cut, Transition::Fade];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transition::Cut => "Cut",
+            Transition::Fade => "Fade",
+        }
+    }
+}
+
+/// A saved look, the layers with their shaders, the post effects and how the audio is analysed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    /// Bottom to top.
+    pub layers: Vec<LayerPreset>,
+    pub post: PostSettings,
+    pub analysis: Analysis,
+    /// Used when switching to this preset.
+    pub transition: Transition,
+    pub transition_seconds: f32,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Self {
+            layers: vec![],
+            post: PostSettings::default(),
+            analysis: Analysis::default(),
+            transition: Transition::default(),
+            transition_seconds: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerPreset {
+    pub shader: PathBuf,
+    pub blend: BlendMode,
+    pub opacity: f32,
+    pub audio: f32,
+    pub visible: bool,
+    /// The values of the shader's `//! param`s by name, the rest keep their defaults.
+    pub params: BTreeMap<String, f32>,
+}
+
+impl Default for LayerPreset {
+    fn default() -> Self {
+        Self {
+            shader: PathBuf::new(),
+            blend: BlendMode::default(),
+            opacity: 1.0,
+            audio: 0.0,
+            visible: true,
+            params: BTreeMap::new(),
+        }
+    }
+}
+
+impl Preset {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut preset: Self =
+            toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        if let Err(e) = preset.analysis.check() {
+            eprintln!("In {}, {}, using the default analysis", path.display(), e);
+            preset.analysis = Analysis::default();
+        }
+        Ok(preset)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string_pretty(self)?;
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// What's running right now, layers that are fading out are left out.
+    pub fn current(renderer: &Renderer, audio: &AudioProcessor) -> Self {
+        Self {
+            layers: renderer
+                .layers
+                .iter()
+                .filter(|layer| !layer.leaving)
+                .map(|layer| LayerPreset {
+                    shader: layer.path.clone(),
+                    blend: layer.blend,
+                    opacity: layer.opacity,
+                    audio: layer.audio,
+                    visible: layer.visible,
+                    params: layer
+                        .program
+                        .params
+                        .iter()
+                        .map(|param| (param.desc.name.clone(), param.value))
+                        .collect(),
+                })
+                .collect(),
+            post: renderer.post.settings.clone(),
+            analysis: audio.analysis(),
+            ..Self::default()
+        }
+    }
+
+    /// Puts the preset on, shaders that fail to load are left out.
+    pub fn apply(&self, renderer: &mut Renderer, audio: &AudioProcessor) {
+        if renderer.load_preset(self) {
+            audio.set_analysis(self.analysis);
+        }
+    }
+}
+
+/// The presets in the preset directory, in the order hotkeys step through them.
+pub struct Presets {
+    pub dir: PathBuf,
+    pub list: Vec<PathBuf>,
+    /// The one that was loaded last.
+    pub current: Option<usize>,
+    /// Nothing else gets loaded while locked.
+    pub locked: bool,
+}
+
+impl Presets {
+    pub fn new(dir: &Path) -> Self {
+        let mut presets = Self {
+            dir: dir.to_path_buf(),
+            list: vec![],
+            current: None,
+            locked: false,
+        };
+        presets.refresh();
+        presets
+    }
+
+    /// Lists the directory again, a missing directory has no presets yet.
+    pub fn refresh(&mut self) {
+        let current = self.current.map(|index| self.list[index].clone());
+        self.list = list_presets(&self.dir).unwrap_or_default();
+        self.current = current.and_then(|path| self.list.iter().position(|p| *p == path));
+    }
+
+    pub fn name(path: &Path) -> String {
+        path.file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn load(&mut self, index: usize, renderer: &mut Renderer, audio: &AudioProcessor) {
+        self.load_with(index, None, renderer, audio);
+    }
+
+    /// Loads with another transition than the one saved with the preset.
+    pub fn load_with(
+        &mut self,
+        index: usize,
+        transition: Option<(Transition, f32)>,
+        renderer: &mut Renderer,
+        audio: &AudioProcessor,
+    ) {
+        let Some(path) = self.list.get(index).filter(|_| !self.locked) else {
+            return;
+        };
+        match Preset::load(path) {
+            std::result::Result::Ok(mut preset) => {
+                if let Some((transition, seconds)) = transition {
+                    preset.transition = transition;
+                    preset.transition_seconds = seconds;
+                }
+                preset.apply(renderer, audio);
+                self.current = Some(index);
+            }
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+
+    pub fn next(&mut self, renderer: &mut Renderer, audio: &AudioProcessor) {
+        if !self.list.is_empty() {
+            let index = self.current.map_or(0, |i| (i + 1) % self.list.len());
+            self.load(index, renderer, audio);
+        }
+    }
+
+    pub fn previous(&mut self, renderer: &mut Renderer, audio: &AudioProcessor) {
+        if !self.list.is_empty() {
+            let count = self.list.len();
+            let index = self.current.map_or(count - 1, |i| (i + count - 1) % count);
+            self.load(index, renderer, audio);
+        }
+    }
+
+    /// Saves what's running as `<name>.toml` in the preset directory,
+    /// overwriting a preset with the same name.
+    pub fn save(
+        &mut self,
+        name: &str,
+        transition: Transition,
+        transition_seconds: f32,
+        renderer: &Renderer,
+        audio: &AudioProcessor,
+    ) -> Result<()> {
+        let path = self.dir.join(file_name(name)?);
+        let preset = Preset {
+            transition,
+            transition_seconds,
+            ..Preset::current(renderer, audio)
+        };
+        preset.save(&path)?;
+        self.refresh();
+        self.current = self.list.iter().position(|p| *p == path);
+        Ok(())
+    }
+}
+
+/// `<name>.toml`, names can have dots but can't go outside the preset directory.
+fn file_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    ensure!(!name.is_empty(), "The preset needs a name");
+    ensure!(
+        !name.contains(['/', '\\', ':']) && !name.starts_with('.'),
+        "`{}` can't be a preset name, leave out / \\ : and a dot at the start",
+        name
+    );
+    Ok(format!("{}.toml", name))
+}
+
+fn list_presets(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut presets = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            presets.push(path);
+        }
+    }
+    presets.sort();
+    Ok(presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_file_names() {
+        assert_eq!(file_name(" v1.2 bright ").unwrap(), "v1.2 bright.toml");
+        for name in ["", "  ", "../x", "a/b", "a\\b", "/abs", "C:x", ".hidden"] {
+            assert!(file_name(name).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use crate::egui_integration::wgpu::RenderPass;
+use crate::renderer::Renderer;
+
+const PREVIEW_WIDTH: f32 = 160.0;
+
+/// Live previews of the textures a frame goes through, registered with the egui render pass.
+#[derive(Default)]
+pub struct TexturePreviews {
+    pub open: bool,
+    /// By name, so a preview keeps its id when the texture is recreated.
+    ids: HashMap<String, egui::TextureId>,
+}
+
+impl TexturePreviews {
+    pub fn show(&mut self, ctx: &egui::Context, egui_rp: &mut RenderPass, renderer: &Renderer) {
+        let textures = renderer.debug_textures();
+        // Layers that were removed.
+        self.ids.retain(|name, id| {
+            let exists = textures.iter().any(|(n, ..)| n == name);
+            if !exists {
+                egui_rp.free_native_texture(*id);
+            }
+            exists
+        });
+        // The bind groups are made again every frame, the targets swap and get recreated.
+        for (name, texture, view) in &textures {
+            match self.ids.get(name) {
+                Some(id) => {
+                    if let Err(e) =
+                        egui_rp.update_native_texture(&renderer.device, *id, texture, view)
+                    {
+                        eprintln!("Failed to update the preview of {}: {}", name, e);
+                    }
+                }
+                None => {
+                    let id = egui_rp.register_native_texture(&renderer.device, texture, view);
+                    self.ids.insert(name.clone(), id);
+                }
+            }
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Textures")
+            .open(&mut open)
+            .default_width(2.0 * PREVIEW_WIDTH + 30.0)
+            .show(ctx, |ui| {
+                ui.weak(
+                    "Shown as they are, linear colors look darker and values above 1 are cut off",
+                );
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("texture_previews").show(ui, |ui| {
+                        for (index, (name, texture, _)) in textures.iter().enumerate() {
+                            let size = texture.size();
+                            let height =
+                                PREVIEW_WIDTH * size.height as f32 / size.width.max(1) as f32;
+                            ui.vertical(|ui| {
+                                ui.image(self.ids[name], [PREVIEW_WIDTH, height]);
+                                ui.label(format!(
+                                    "{} {}x{} {:?}",
+                                    name,
+                                    size.width,
+                                    size.height,
+                                    texture.format()
+                                ));
+                            });
+                            if index % 2 == 1 {
+                                ui.end_row();
+                            }
+                        }
+                    });
+                });
+            });
+        self.open = open;
+    }
+
+    /// Lets go of the previews while they aren't shown.
+    pub fn clear(&mut self, egui_rp: &mut RenderPass) {
+        for (_, id) in self.ids.drain() {
+            egui_rp.free_native_texture(id);
+        }
+    }
+}
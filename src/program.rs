@@ -0,0 +1,514 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::frame_timer::FrameTimer;
+use crate::particles::{Particle, Particles};
+use crate::post::PostSettings;
+use crate::render_target::{PingPong, RenderTarget};
+use crate::shader_meta::{
+    self, CameraDesc, Language, ParamDesc, PassDesc, PassSize, PassSlot, ShaderMeta,
+};
+use crate::shaders::{self, INDICES, VERTICES};
+use crate::texture::UserTexture;
+
+/// Everything the passes of all shader programs share.
+pub struct PassResources {
+    pub pipeline_layout: wgpu::PipelineLayout,
+    /// The render layout plus the particle group.
+    pub compute_pipeline_layout: wgpu::PipelineLayout,
+    feedback_bind_group_layout: wgpu::BindGroupLayout,
+    pub particle_bind_group_layout: wgpu::BindGroupLayout,
+    pub sampler: wgpu::Sampler,
+    // Bound in place of the buffers and textures a shader doesn't declare.
+    empty_target: RenderTarget,
+
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_indices: u32,
+    /// Where `#include`s are looked for when they aren't next to the shader.
+    pub shader_dir: PathBuf,
+}
+
+impl PassResources {
+    /// `bind_group_layouts` are the layouts of group 0 and up, the feedback group goes after them
+    /// and the particle group after that.
+    pub fn new(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader_dir: &Path,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let mut entries = vec![sampler_entry(0)];
+        entries.extend(
+            PassSlot::ALL
+                .iter()
+                .map(|slot| texture_entry(slot.binding())),
+        );
+        for index in 0..shader_meta::MAX_TEXTURES {
+            let binding = shader_meta::texture_binding(index);
+            entries.push(texture_entry(binding));
+            entries.push(sampler_entry(binding + 1));
+        }
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: shader_meta::PARAMS_BINDING,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+
+        let feedback_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &entries,
+                label: Some("feedback_bind_group_layout"),
+            });
+
+        let mut layouts = bind_group_layouts.to_vec();
+        layouts.push(&feedback_bind_group_layout);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &layouts,
+            push_constant_ranges: &[],
+        });
+
+        let particle_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("particle_bind_group_layout"),
+            });
+        layouts.push(&particle_bind_group_layout);
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Compute Pipeline Layout"),
+                bind_group_layouts: &layouts,
+                push_constant_ranges: &[],
+            });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("feedback_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let empty_target = RenderTarget::new(
+            device,
+            "empty_target",
+            1,
+            1,
+            wgpu::TextureFormat::Rgba8Unorm,
+        );
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let num_indices = INDICES.len() as u32;
+
+        Self {
+            pipeline_layout,
+            compute_pipeline_layout,
+            feedback_bind_group_layout,
+            particle_bind_group_layout,
+            sampler,
+            empty_target,
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+            shader_dir: shader_dir.to_path_buf(),
+        }
+    }
+
+    /// Draws the full screen quad with whatever pipeline and bind groups are set.
+    pub fn draw_quad<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+}
+
+struct Pass {
+    desc: PassDesc,
+    pipeline: wgpu::RenderPipeline,
+    targets: PingPong,
+}
+
+/// A `//! param` and what it's set to.
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub desc: ParamDesc,
+    pub value: f32,
+}
+
+impl Param {
+    /// Sets it from a 0 to 1 control, like a MIDI knob.
+    pub fn set_normalized(&mut self, value: f32) {
+        self.value = self.desc.min + value.clamp(0.0, 1.0) * (self.desc.max - self.desc.min);
+    }
+}
+
+/// A loaded user shader with a render pipeline and feedback targets for each of its passes.
+pub struct ShaderProgram {
+    passes: Vec<Pass>,
+    /// Updated before the passes and drawn on top of the image pass.
+    particles: Option<Particles>,
+    pub camera: CameraDesc,
+    pub post: PostSettings,
+    textures: Vec<UserTexture>,
+    pub params: Vec<Param>,
+    params_buffer: wgpu::Buffer,
+    /// The feedback bind group of every pass, for frames with the targets the way they were made
+    /// and for frames with them swapped. Rebuilt with the targets.
+    feedback_bind_groups: [Vec<wgpu::BindGroup>; 2],
+    swapped: bool,
+}
+
+impl ShaderProgram {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        resources: &PassResources,
+        shader: &Path,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> Result<Self> {
+        let user_src = fs::read_to_string(shader)?;
+        Self::from_source(device, queue, resources, shader, &user_src, size)
+    }
+
+    /// Builds the shader from `user_src` instead of the file, includes still come from disk.
+    pub fn from_source(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        resources: &PassResources,
+        shader: &Path,
+        user_src: &str,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> Result<Self> {
+        let language = Language::from_path(shader).context("Not a shader file")?;
+        let meta = ShaderMeta::parse(user_src, language);
+        meta.check()?;
+        // wgpu panics on textures over the limit instead of returning an error.
+        let max = device.limits().max_texture_dimension_2d;
+        for pass in &meta.passes {
+            if let PassSize::Fixed(w, h) = pass.size {
+                ensure!(
+                    w <= max && h <= max,
+                    "pass {} is {}x{}, this device only does up to {}x{}",
+                    pass.slot.name(),
+                    w,
+                    h,
+                    max,
+                    max
+                );
+            }
+        }
+        if let Some(desc) = &meta.particles {
+            let size = desc.count as u64 * std::mem::size_of::<Particle>() as u64;
+            let max = device.limits().max_storage_buffer_binding_size as u64;
+            ensure!(
+                size <= max,
+                "{} particles take {} bytes, this device only does up to {}",
+                desc.count,
+                size,
+                max
+            );
+        }
+        let module = shaders::make_module(
+            device,
+            shader,
+            shaders::build_source(shader, user_src, &meta, &resources.shader_dir)?,
+        )?;
+        let textures = meta
+            .textures
+            .iter()
+            .map(|desc| UserTexture::load(device, queue, desc))
+            .collect::<Result<Vec<_>>>()?;
+
+        let passes = meta
+            .passes
+            .iter()
+            .cloned()
+            .map(|desc| {
+                let pipeline = shaders::make_pipeline(
+                    device,
+                    &resources.pipeline_layout,
+                    desc.format,
+                    &module,
+                    &meta.entry_point(desc.slot),
+                );
+                let targets = Self::make_targets(device, &desc, size);
+                Pass {
+                    desc,
+                    pipeline,
+                    targets,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let image_format = passes
+            .last()
+            .expect("The image pass is always present")
+            .desc
+            .format;
+        let particles = meta
+            .particles
+            .as_ref()
+            .map(|desc| Particles::new(device, resources, &module, desc, image_format));
+
+        let params = meta
+            .params
+            .into_iter()
+            .map(|desc| Param {
+                value: desc.default,
+                desc,
+            })
+            .collect();
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Params Buffer"),
+            size: (shader_meta::MAX_PARAMS * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut program = Self {
+            passes,
+            particles,
+            camera: meta.camera,
+            post: meta.post,
+            textures,
+            params,
+            params_buffer,
+            feedback_bind_groups: Default::default(),
+            swapped: false,
+        };
+        program.feedback_bind_groups = program.feedback_bind_groups(device, resources);
+        Ok(program)
+    }
+
+    fn make_targets(
+        device: &wgpu::Device,
+        desc: &PassDesc,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> PingPong {
+        let max = device.limits().max_texture_dimension_2d;
+        let (width, height) = desc.size.resolve(size.width, size.height, max);
+        PingPong::new(device, desc.slot.name(), width, height, desc.format)
+    }
+
+    /// Recreates the targets, this clears the feedback.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        resources: &PassResources,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        for pass in &mut self.passes {
+            pass.targets = Self::make_targets(device, &pass.desc, size);
+        }
+        self.swapped = false;
+        self.feedback_bind_groups = self.feedback_bind_groups(device, resources);
+    }
+
+    /// Advances the frame sequences and uploads the params, `time` is in seconds.
+    pub fn update(&mut self, queue: &wgpu::Queue, time: f32) {
+        for texture in &mut self.textures {
+            texture.update(queue, time);
+        }
+        let mut values = [0.0f32; shader_meta::MAX_PARAMS];
+        for (value, param) in values.iter_mut().zip(&self.params) {
+            *value = param.value.clamp(param.desc.min, param.desc.max);
+        }
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&values));
+    }
+
+    /// Takes over the values of the params `other` has by the same name,
+    /// so they stay where they were when the shader is reloaded.
+    pub fn keep_params(&mut self, other: &ShaderProgram) {
+        for param in &mut self.params {
+            if let Some(old) = other.params.iter().find(|p| p.desc.name == param.desc.name) {
+                param.value = old.value.clamp(param.desc.min, param.desc.max);
+            }
+        }
+    }
+
+    /// The finished image of the last rendered frame.
+    pub fn output(&self) -> &RenderTarget {
+        self.passes
+            .last()
+            .expect("The image pass is always present")
+            .targets
+            .front()
+    }
+
+    /// The finished image of every pass, the buffers first.
+    pub fn targets(&self) -> impl Iterator<Item = (&'static str, &RenderTarget)> {
+        self.passes
+            .iter()
+            .map(|pass| (pass.desc.slot.name(), pass.targets.front()))
+    }
+
+    /// Updates the particles and renders all passes,
+    /// `bind_groups` are set before the feedback bind group and `timer` times every pass.
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &PassResources,
+        bind_groups: &[&wgpu::BindGroup],
+        timer: &mut FrameTimer,
+    ) {
+        let feedback_bind_groups = &self.feedback_bind_groups[self.swapped as usize];
+        if let Some(particles) = &self.particles {
+            timer.mark(encoder, "particles");
+            let mut groups = bind_groups.to_vec();
+            groups.push(&feedback_bind_groups[0]);
+            particles.update(encoder, &groups);
+        }
+
+        for (i, feedback_bind_group) in feedback_bind_groups.iter().enumerate() {
+            let pass = &self.passes[i];
+            timer.mark(encoder, pass.desc.slot.name());
+
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some(pass.desc.slot.name()),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &pass.targets.back().view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+
+                render_pass.set_pipeline(&pass.pipeline);
+                for (index, bind_group) in bind_groups.iter().enumerate() {
+                    render_pass.set_bind_group(index as u32, bind_group, &[]);
+                }
+                render_pass.set_bind_group(bind_groups.len() as u32, feedback_bind_group, &[]);
+                resources.draw_quad(&mut render_pass);
+                if let (PassSlot::Image, Some(particles)) = (pass.desc.slot, &self.particles) {
+                    particles.draw(&mut render_pass, resources);
+                }
+            }
+        }
+        for pass in &mut self.passes {
+            pass.targets.swap();
+        }
+        self.swapped = !self.swapped;
+    }
+
+    /// The bind groups for `feedback_bind_groups`, made while no target has been swapped yet.
+    fn feedback_bind_groups(
+        &self,
+        device: &wgpu::Device,
+        resources: &PassResources,
+    ) -> [Vec<wgpu::BindGroup>; 2] {
+        [false, true].map(|swapped| {
+            (0..self.passes.len())
+                .map(|drawing| self.feedback_bind_group(device, resources, swapped, drawing))
+                .collect()
+        })
+    }
+
+    /// Binds the front target of every pass, which is this frames output for the passes
+    /// before `drawing` and the previous frames output for the rest, and the textures.
+    fn feedback_bind_group(
+        &self,
+        device: &wgpu::Device,
+        resources: &PassResources,
+        swapped: bool,
+        drawing: usize,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Sampler(&resources.sampler),
+        }];
+        for slot in PassSlot::ALL {
+            let target = self
+                .passes
+                .iter()
+                .enumerate()
+                .find(|(_, p)| p.desc.slot == slot)
+                .map(|(i, p)| {
+                    p.targets
+                        .front_after(swapped as usize + (i < drawing) as usize)
+                })
+                .unwrap_or(&resources.empty_target);
+            entries.push(wgpu::BindGroupEntry {
+                binding: slot.binding(),
+                resource: wgpu::BindingResource::TextureView(&target.view),
+            });
+        }
+
+        for index in 0..shader_meta::MAX_TEXTURES {
+            let binding = shader_meta::texture_binding(index);
+            let (view, sampler) = match self.textures.get(index) {
+                Some(t) => (&t.texture.view, &t.texture.sampler),
+                None => (&resources.empty_target.view, &resources.sampler),
+            };
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            });
+        }
+
+        entries.push(wgpu::BindGroupEntry {
+            binding: shader_meta::PARAMS_BINDING,
+            resource: self.params_buffer.as_entire_binding(),
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &resources.feedback_bind_group_layout,
+            entries: &entries,
+            label: Some("feedback_bind_group"),
+        })
+    }
+}
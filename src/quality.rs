@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use crate::renderer::MIN_RENDER_SCALE;
+
+/// The lowest `util.quality` the controller goes to.
+pub const MIN_QUALITY: f32 = 0.25;
+
+// Over budget by this much for `OVER_HOLD` steps down, under by this much for `UNDER_HOLD` steps up.
+// The gap and the longer hold for stepping up keep it from flipping back and forth.
+const OVER_BUDGET: f32 = 1.05;
+const UNDER_BUDGET: f32 = 0.75;
+const OVER_HOLD: Duration = Duration::from_millis(300);
+const UNDER_HOLD: Duration = Duration::from_secs(2);
+// Changing the render scale clears the feedback, so give every step time to settle.
+const COOLDOWN: Duration = Duration::from_millis(500);
+
+/// Lowers the render scale and the `quality` uniform while frames go over the budget
+/// of `target_fps` and raises them again, up to 1, once there's room.
+pub struct QualityController {
+    pub enabled: bool,
+    pub target_fps: f32,
+    quality: f32,
+    /// Smoothed, in seconds.
+    frame_time: f32,
+    over_since: Option<Instant>,
+    under_since: Option<Instant>,
+    last_change: Instant,
+}
+
+impl QualityController {
+    pub fn new(target_fps: f32) -> Self {
+        Self {
+            enabled: false,
+            target_fps,
+            quality: 1.0,
+            frame_time: 1.0 / target_fps,
+            over_since: None,
+            under_since: None,
+            last_change: Instant::now(),
+        }
+    }
+
+    /// What shaders get as `util.quality`, always 1 while disabled.
+    pub fn quality(&self) -> f32 {
+        if self.enabled {
+            self.quality
+        } else {
+            1.0
+        }
+    }
+
+    /// The smoothed frame time the controller works with.
+    pub fn frame_time(&self) -> Duration {
+        Duration::from_secs_f32(self.frame_time)
+    }
+
+    /// Takes the latest frame time, preferably the gpu time since the cpu waits on vsync,
+    /// and returns the render scale to switch to, if any.
+    pub fn update(&mut self, frame_time: Duration, render_scale: f32) -> Option<f32> {
+        self.frame_time += (frame_time.as_secs_f32() - self.frame_time) * 0.1;
+        if !self.enabled {
+            return None;
+        }
+
+        let budget = 1.0 / self.target_fps;
+        let now = Instant::now();
+        let over = held(
+            &mut self.over_since,
+            self.frame_time > budget * OVER_BUDGET,
+            now,
+            OVER_HOLD,
+        );
+        let under = held(
+            &mut self.under_since,
+            self.frame_time < budget * UNDER_BUDGET,
+            now,
+            UNDER_HOLD,
+        );
+        if now - self.last_change < COOLDOWN || !(over || under) {
+            return None;
+        }
+
+        // Both go down together, the quality alone does nothing for shaders that ignore it.
+        let (quality, scale) = if over {
+            (
+                (self.quality * 0.85).max(MIN_QUALITY),
+                (render_scale * 0.9).max(MIN_RENDER_SCALE),
+            )
+        } else {
+            // A supersampling scale set by hand is left alone.
+            (
+                (self.quality / 0.85).min(1.0),
+                (render_scale / 0.9).min(1.0).max(render_scale),
+            )
+        };
+        if quality == self.quality && scale == render_scale {
+            return None;
+        }
+        self.quality = quality;
+        self.last_change = now;
+        self.over_since = None;
+        self.under_since = None;
+        Some(scale)
+    }
+}
+
+/// Whether `condition` has been true for at least `hold`, `since` is when it became true.
+fn held(since: &mut Option<Instant>, condition: bool, now: Instant, hold: Duration) -> bool {
+    if condition {
+        now - *since.get_or_insert(now) >= hold
+    } else {
+        *since = None;
+        false
+    }
+}
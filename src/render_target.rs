@@ -0,0 +1,87 @@
+/// An offscreen color target that a pass can render into and that later passes can sample.
+#[allow(dead_code)]
+pub struct RenderTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+            format,
+        }
+    }
+}
+
+/// Two targets that trade places every frame, so a pass can sample what it drew last frame
+/// while drawing the next one.
+pub struct PingPong {
+    targets: [RenderTarget; 2],
+    front: usize,
+}
+
+impl PingPong {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let targets = [
+            RenderTarget::new(device, &format!("{}_0", label), width, height, format),
+            RenderTarget::new(device, &format!("{}_1", label), width, height, format),
+        ];
+        Self { targets, front: 0 }
+    }
+
+    /// The most recently finished frame.
+    pub fn front(&self) -> &RenderTarget {
+        &self.targets[self.front]
+    }
+
+    /// The target that is being drawn this frame.
+    pub fn back(&self) -> &RenderTarget {
+        &self.targets[1 - self.front]
+    }
+
+    /// What `front` will be after `swaps` more swaps.
+    pub fn front_after(&self, swaps: usize) -> &RenderTarget {
+        &self.targets[(self.front + swaps) % 2]
+    }
+
+    /// Call once the back target is done, making it the new front.
+    pub fn swap(&mut self) {
+        self.front = 1 - self.front;
+    }
+}
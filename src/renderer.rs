@@ -1,13 +1,27 @@
 use std::num::NonZeroU32;
 
+use std::time::Instant;
 use std::{iter, path};
 
+use anyhow::{Context, Result};
 use wgpu::util::DeviceExt;
 
 use crate::audio_processor::AudioProcessor;
-use crate::fft_buffer;
-use crate::shaders::{self, INDICES, VERTICES};
-use crate::state::State;
+use crate::blit::{Blitter, Tonemap, UpscaleFilter};
+use crate::camera::{Camera, CameraController, CameraUniform};
+use crate::capture::Capture;
+use crate::fft_buffer::{self, FFTDimensions};
+use crate::frame_timer::FrameTimer;
+use crate::keymap::{Action, Keymap};
+use crate::layers::{Compositor, Layer};
+use crate::output::{OutputSource, OutputWindow};
+use crate::post::{PostProcessor, PostSettings};
+use crate::preset::{Preset, Transition};
+use crate::program::{PassResources, ShaderProgram};
+use crate::quality::QualityController;
+use crate::settings::Settings;
+use crate::shadertoy::ShadertoyAudio;
+use crate::state::{present_mode_name, State, PRESENT_MODES};
 use crate::ui::Ui;
 
 #[repr(C)]
@@ -18,31 +32,78 @@ pub struct UtilUniform {
     // Todo make vector
     pub res_width: f32,
     pub res_height: f32,
+    pub frame: u32,
+    pub mouse: glam::Vec4,
+    pub beat: f32,
+    /// The frequency at the right edge of the fft texture, in Hz.
+    pub max_freq: f32,
+    /// Set by the `QualityController`, 1 is full quality, for scaling step counts and such.
+    pub quality: f32,
+    // Uniforms are padded to 16 bytes.
+    pub _padding: f32,
     // pub freq_max: f32,
 }
 
+/// What a frame is drawn from besides the fft and the wave.
+pub struct FrameInput {
+    /// In seconds.
+    pub time: f32,
+    pub frame: u32,
+    pub mouse: glam::Vec4,
+    /// The pulse of the last beat, see `Beat::pulse`.
+    pub beat: f32,
+    pub sample_rate: u32,
+}
+
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
 pub struct Renderer {
-    surface: wgpu::Surface,
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    // None when rendering without a window, for thumbnails.
+    surface: Option<wgpu::Surface>,
     pub device: wgpu::Device,
     queue: wgpu::Queue,
     pub surface_config: wgpu::SurfaceConfiguration,
+    /// The ones of `PRESENT_MODES` the surface supports.
+    pub present_modes: Vec<wgpu::PresentMode>,
 
     pub size: winit::dpi::PhysicalSize<u32>,
+    /// The shader renders at this fraction of `size`, above 1 supersamples.
+    render_scale: f32,
+    pub upscale_filter: UpscaleFilter,
+    pub tonemap: Tonemap,
+    pub frame_timer: FrameTimer,
+    pub quality: QualityController,
     util_buffer: wgpu::Buffer,
     util_bind_group: wgpu::BindGroup,
+    camera: Camera,
+    camera_controller: CameraController,
+    camera_buffer: wgpu::Buffer,
 
-    render_pipeline_layout: wgpu::PipelineLayout,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
+    pass_resources: PassResources,
+    /// Bottom to top, there's always at least one.
+    pub layers: Vec<Layer>,
+    /// The layer picking a shader loads into.
+    pub selected_layer: usize,
+    // When the fade to the last preset started and how long it takes.
+    fade: Option<(Instant, f32)>,
+    compositor: Compositor,
+    pub post: PostProcessor,
+    blitter: Blitter,
+    pub capture: Capture,
+    /// Windows without the ui, drawn along with the main one.
+    pub outputs: Vec<OutputWindow>,
 
     fft_buffer: fft_buffer::FFTBuffer,
     fft_bind_group: wgpu::BindGroup,
+    wave_buffer: Vec<f32>,
+    shadertoy_audio: ShadertoyAudio,
 }
 
 impl Renderer {
-    pub async fn new(state: &State) -> Self {
+    pub async fn new(state: &State, settings: &Settings) -> Self {
         let State {
             window,
             fft_dimensions,
@@ -65,56 +126,133 @@ impl Renderer {
             })
             .await
             .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await;
 
         let surface_caps = surface.get_capabilities(&adapter);
 
-        // Pick a non SRGB surface such that we don't have to convert in-shader.
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .filter(|f| !f.describe().srgb)
-            .next()
-            .unwrap_or(surface_caps.formats[0]);
+        // The preferred format, the blitter encodes the linear frame for it either way.
+        let surface_format = surface_caps.formats[0];
+
+        let present_modes: Vec<wgpu::PresentMode> = PRESENT_MODES
+            .into_iter()
+            .filter(|mode| surface_caps.present_modes.contains(mode))
+            .collect();
+        let present_mode = if present_modes.contains(&state.display.present_mode) {
+            state.display.present_mode
+        } else {
+            eprintln!(
+                "The {} present mode isn't supported, using fifo",
+                present_mode_name(state.display.present_mode)
+            );
+            wgpu::PresentMode::Fifo
+        };
+
+        // Only premultiplied, that's what the blitter puts out.
+        let transparent = state.display.transparent
+            && surface_caps
+                .alpha_modes
+                .contains(&wgpu::CompositeAlphaMode::PreMultiplied);
+        if state.display.transparent && !transparent {
+            eprintln!("The surface can't be transparent here");
+        }
 
         let config = wgpu::SurfaceConfiguration {
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            alpha_mode: if transparent {
+                wgpu::CompositeAlphaMode::PreMultiplied
+            } else {
+                wgpu::CompositeAlphaMode::Auto
+            },
             view_formats: vec![],
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode,
         };
         surface.configure(&device, &config);
 
+        Self::with_device(
+            instance,
+            adapter,
+            device,
+            queue,
+            Some(surface),
+            config,
+            present_modes,
+            transparent,
+            *fft_dimensions,
+            settings,
+        )
+    }
+
+    /// Renders into the capture only, see `render_headless`.
+    pub async fn headless(
+        size: winit::dpi::PhysicalSize<u32>,
+        fft_dimensions: FFTDimensions,
+        settings: &Settings,
+    ) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .context("No graphics adapter")?;
+        let (device, queue) = request_device(&adapter).await;
+        // Never configured, it's what a surface would look like.
+        let config = wgpu::SurfaceConfiguration {
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        Ok(Self::with_device(
+            instance,
+            adapter,
+            device,
+            queue,
+            None,
+            config,
+            vec![],
+            false,
+            fft_dimensions,
+            settings,
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn with_device(
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface>,
+        config: wgpu::SurfaceConfiguration,
+        present_modes: Vec<wgpu::PresentMode>,
+        transparent: bool,
+        fft_dimensions: FFTDimensions,
+        settings: &Settings,
+    ) -> Self {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
         let fft_buffer =
-            fft_buffer::FFTBuffer::from_buffer(&device, &queue, "fft_buffer", fft_dimensions)
+            fft_buffer::FFTBuffer::from_buffer(&device, &queue, "fft_buffer", &fft_dimensions)
                 .unwrap();
+        let shadertoy_audio = ShadertoyAudio::new(&device);
 
         let fft_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
                             view_dimension: wgpu::TextureViewDimension::D2,
@@ -124,10 +262,27 @@ impl Renderer {
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
                         count: None,
                     },
+                    // Shadertoy's iChannel0
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
                 label: Some("fft_bind_group_layout"),
             });
@@ -143,6 +298,14 @@ impl Renderer {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&fft_buffer.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadertoy_audio.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadertoy_audio.sampler),
+                },
             ],
             label: Some("fft_bind_group"),
         });
@@ -155,80 +318,119 @@ impl Renderer {
                 time: 0.0,
                 res_width: size.width as f32,
                 res_height: size.height as f32,
+                frame: 0,
+                mouse: glam::Vec4::ZERO,
+                beat: 0.0,
+                max_freq: 0.0,
+                quality: 1.0,
+                _padding: 0.0,
             }]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let camera = Camera::new(size.width as f32 / size.height as f32);
+        let camera_controller = CameraController::new(2.0);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new(&camera)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX
+                | wgpu::ShaderStages::FRAGMENT
+                | wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let util_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                entries: &[uniform_entry(0), uniform_entry(1)],
                 label: Some("util_bind_group_layout"),
             });
 
         let util_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &util_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: util_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: util_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("util_bind_group"),
         });
 
-        let shaders =
-            crate::shaders::list_shaders().expect("Some shaders available at initial load");
-        let shader_src = &shaders[0];
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&util_bind_group_layout, &fft_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = shaders::make_pipeline(
+        let pass_resources = PassResources::new(
             &device,
-            &render_pipeline_layout,
-            surface_format,
-            &shader_src,
+            &[&util_bind_group_layout, &fft_bind_group_layout],
+            &settings.ui.shader_dir,
         );
+        // The shader from the settings, or else the first one in the directory that compiles.
+        let shaders = crate::shaders::list_shaders(&settings.ui.shader_dir).unwrap_or_default();
+        let layer = settings
+            .rendering
+            .shader
+            .iter()
+            .chain(&shaders)
+            .find_map(|shader| {
+                Layer::new(&device, &queue, &pass_resources, shader, size)
+                    .map_err(|e| eprintln!("Failed to load {}:\n{}", shader.display(), e))
+                    .ok()
+            })
+            .expect("A shader that compiles to start with");
+        let compositor = Compositor::new(&device, size);
+        let post = PostProcessor::new(&device, &queue, size);
+        let blitter = Blitter::new(&device, config.format, transparent);
+        let frame_timer = FrameTimer::new(&device, &queue);
+        let capture = Capture::new(&device);
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        let num_indices = INDICES.len() as u32;
-
-        Self {
+        let mut renderer = Self {
+            instance,
+            adapter,
             surface,
             device,
             queue,
             surface_config: config,
+            present_modes,
             size,
-            render_pipeline_layout,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
+            render_scale: 1.0,
+            upscale_filter: settings.rendering.upscale_filter,
+            tonemap: settings.rendering.tonemap,
+            frame_timer,
+            quality: QualityController::new(60.0),
+            pass_resources,
+            layers: vec![layer],
+            selected_layer: 0,
+            fade: None,
+            compositor,
+            post,
+            blitter,
+            capture,
+            outputs: Vec::new(),
             fft_buffer,
             fft_bind_group,
+            wave_buffer: vec![0.; fft_dimensions.fft_size],
+            shadertoy_audio,
             util_buffer,
             util_bind_group,
-        }
+            camera,
+            camera_controller,
+            camera_buffer,
+        };
+        renderer.shader_loaded();
+        renderer.set_render_scale(settings.rendering.render_scale);
+        renderer.quality.enabled = settings.rendering.adaptive_quality;
+        renderer.quality.target_fps = settings.rendering.target_fps;
+        renderer
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -236,36 +438,415 @@ impl Renderer {
             self.size = new_size;
             self.surface_config.width = new_size.width;
             self.surface_config.height = new_size.height;
-            self.surface.configure(&self.device, &self.surface_config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_config);
+            }
+            self.resize_targets();
+        }
+    }
+
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) {
+        if self.present_modes.contains(&mode) {
+            self.surface_config.present_mode = mode;
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_config);
+            }
+        }
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Resizes the shaders targets, which clears their feedback.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+        if scale != self.render_scale {
+            self.render_scale = scale;
+            self.resize_targets();
+        }
+    }
+
+    fn resize_targets(&mut self) {
+        let size = self.render_size();
+        for layer in &mut self.layers {
+            layer
+                .program
+                .resize(&self.device, &self.pass_resources, size);
         }
+        self.compositor.resize(&self.device, size);
+        self.post.resize(&self.device, size);
+    }
+
+    /// The fft texture as it was uploaded for this frame, the newest run first.
+    pub fn fft_data(&self) -> &[f32] {
+        &self.fft_buffer.buffer
     }
 
-    pub fn change_shader(&mut self, shader: &path::PathBuf) {
-        self.render_pipeline = crate::shaders::make_pipeline(
+    /// The samples of the wave texture uploaded for this frame.
+    pub fn wave_data(&self) -> &[f32] {
+        &self.wave_buffer
+    }
+
+    /// The textures a frame goes through by name, for previewing them in the ui.
+    pub fn debug_textures(&self) -> Vec<(String, &wgpu::Texture, &wgpu::TextureView)> {
+        let mut textures = vec![(
+            "fft".to_string(),
+            &self.fft_buffer.texture,
+            &self.fft_buffer.view,
+        )];
+        for (index, layer) in self.layers.iter().enumerate() {
+            for (slot, target) in layer.program.targets() {
+                textures.push((
+                    format!("layer {} {}", index, slot),
+                    &target.texture,
+                    &target.view,
+                ));
+            }
+        }
+        let compositor = self.compositor.target();
+        textures.push(("layers".to_string(), &compositor.texture, &compositor.view));
+        for (name, target) in self.post.targets() {
+            textures.push((name.to_string(), &target.texture, &target.view));
+        }
+        textures
+    }
+
+    /// The size the shader renders at.
+    pub fn render_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(
+            ((self.size.width as f32 * self.render_scale).round() as u32).max(1),
+            ((self.size.height as f32 * self.render_scale).round() as u32).max(1),
+        )
+    }
+
+    /// Loads the shader into the selected layer,
+    /// keeps the current shader running if the new one fails to compile.
+    pub fn change_shader(&mut self, shader: &path::Path) {
+        match ShaderProgram::new(
             &self.device,
-            &self.render_pipeline_layout,
-            self.surface_config.format,
+            &self.queue,
+            &self.pass_resources,
             shader,
-        );
+            self.render_size(),
+        ) {
+            Ok(program) => {
+                self.layers[self.selected_layer].set_program(program, shader);
+                self.shader_loaded();
+            }
+            Err(e) => eprintln!("Failed to load {}:\n{}", shader.display(), e),
+        }
+    }
+
+    /// Loads edited source of the shader into the selected layer without saving it,
+    /// the error is returned so the editor can show it.
+    pub fn apply_source(&mut self, shader: &path::Path, src: &str) -> Result<()> {
+        let program = ShaderProgram::from_source(
+            &self.device,
+            &self.queue,
+            &self.pass_resources,
+            shader,
+            src,
+            self.render_size(),
+        )?;
+        self.layers[self.selected_layer].set_program(program, shader);
+        self.shader_loaded();
+        Ok(())
+    }
+
+    /// Adds the shader as a new layer on top and selects it.
+    pub fn add_layer(&mut self, shader: &path::Path) {
+        match Layer::new(
+            &self.device,
+            &self.queue,
+            &self.pass_resources,
+            shader,
+            self.render_size(),
+        ) {
+            Ok(layer) => {
+                self.layers.push(layer);
+                self.selected_layer = self.layers.len() - 1;
+                self.shader_loaded();
+            }
+            Err(e) => eprintln!("Failed to load {}:\n{}", shader.display(), e),
+        }
+    }
+
+    /// The last layer can't be removed.
+    pub fn remove_layer(&mut self, index: usize) {
+        if self.layers.len() > 1 && index < self.layers.len() {
+            self.layers.remove(index);
+            if self.selected_layer > index || self.selected_layer == self.layers.len() {
+                self.selected_layer -= 1;
+            }
+            for output in &mut self.outputs {
+                match output.source {
+                    OutputSource::Layer(i) if i == index => output.source = OutputSource::Frame,
+                    OutputSource::Layer(i) if i > index => {
+                        output.source = OutputSource::Layer(i - 1)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Swaps the layer with the one above it, the selection moves along.
+    pub fn raise_layer(&mut self, index: usize) {
+        if index + 1 < self.layers.len() {
+            self.layers.swap(index, index + 1);
+            if self.selected_layer == index {
+                self.selected_layer += 1;
+            } else if self.selected_layer == index + 1 {
+                self.selected_layer -= 1;
+            }
+            for output in &mut self.outputs {
+                if output.source == OutputSource::Layer(index) {
+                    output.source = OutputSource::Layer(index + 1);
+                } else if output.source == OutputSource::Layer(index + 1) {
+                    output.source = OutputSource::Layer(index);
+                }
+            }
+        }
+    }
+
+    /// Swaps the layers for the ones of the preset, right away or fading.
+    /// Returns false and keeps the current layers if none of the shaders load.
+    pub fn load_preset(&mut self, preset: &Preset) -> bool {
+        let size = self.render_size();
+        let fade = preset.transition == Transition::Fade && preset.transition_seconds > 0.0;
+        let mut layers = Vec::new();
+        for desc in &preset.layers {
+            match Layer::new(
+                &self.device,
+                &self.queue,
+                &self.pass_resources,
+                &desc.shader,
+                size,
+            ) {
+                Ok(mut layer) => {
+                    layer.blend = desc.blend;
+                    layer.opacity = desc.opacity;
+                    layer.audio = desc.audio;
+                    layer.visible = desc.visible;
+                    for param in &mut layer.program.params {
+                        if let Some(value) = desc.params.get(&param.desc.name) {
+                            param.value = value.clamp(param.desc.min, param.desc.max);
+                        }
+                    }
+                    if fade {
+                        layer.fade = 0.0;
+                    }
+                    layers.push(layer);
+                }
+                Err(e) => eprintln!("Failed to load {}:\n{}", desc.shader.display(), e),
+            }
+        }
+        if layers.is_empty() {
+            eprintln!("Nothing in the preset loaded");
+            return false;
+        }
+
+        for output in &mut self.outputs {
+            output.source = OutputSource::Frame;
+        }
+        if fade {
+            // A fade that was still going is cut short.
+            self.layers.retain(|layer| !layer.leaving);
+            for layer in &mut self.layers {
+                layer.leaving = true;
+            }
+            self.layers.append(&mut layers);
+            self.fade = Some((Instant::now(), preset.transition_seconds));
+        } else {
+            self.layers = layers;
+            self.fade = None;
+        }
+        self.selected_layer = self.layers.len() - 1;
+        self.reset_camera();
+        self.post
+            .set_settings(&self.device, &self.queue, preset.post.clone());
+        true
+    }
+
+    /// Replaces the post effects, loading a shader or a preset sets them too.
+    pub fn set_post(&mut self, settings: PostSettings) {
+        self.post.set_settings(&self.device, &self.queue, settings);
+    }
+
+    /// Moves the fade along, the layers that faded out are removed at the end.
+    fn update_fade(&mut self) {
+        let Some((start, seconds)) = self.fade else {
+            return;
+        };
+        let t = (start.elapsed().as_secs_f32() / seconds).min(1.0);
+        for layer in &mut self.layers {
+            layer.fade = if layer.leaving { 1.0 - t } else { t };
+        }
+        if t >= 1.0 {
+            self.fade = None;
+            // Unless all the new ones were removed in the meantime.
+            if self.layers.iter().all(|layer| layer.leaving) {
+                for layer in &mut self.layers {
+                    layer.leaving = false;
+                    layer.fade = 1.0;
+                }
+            }
+            while let Some(index) = self.layers.iter().position(|layer| layer.leaving) {
+                self.remove_layer(index);
+            }
+        }
+    }
+
+    /// The camera and the post effects follow the shader that was loaded last.
+    fn shader_loaded(&mut self) {
+        let program = &self.layers[self.selected_layer].program;
+        self.post
+            .set_settings(&self.device, &self.queue, program.post.clone());
+        self.reset_camera();
+    }
+
+    /// Puts the camera where the selected shader wants it.
+    fn reset_camera(&mut self) {
+        let desc = &self.layers[self.selected_layer].program.camera;
+        self.camera = Camera::new(self.size.width as f32 / self.size.height as f32);
+        if let Some(eye) = desc.eye {
+            self.camera.eye = eye;
+        }
+        if let Some(target) = desc.target {
+            self.camera.target = target;
+        }
+        self.camera_controller.set_mode(desc.mode, &self.camera);
+    }
+
+    pub fn add_output(&mut self, window: winit::window::Window) {
+        match OutputWindow::new(window, &self.instance, &self.adapter, &self.device) {
+            Ok(output) => self.outputs.push(output),
+            Err(e) => eprintln!("Failed to set up the output window:\n{}", e),
+        }
+    }
+
+    /// Handles the events of the output windows, returns whether the event was for one.
+    /// The fullscreen key of `keymap` toggles fullscreen on the output.
+    pub fn output_input(
+        &mut self,
+        window_id: winit::window::WindowId,
+        event: &winit::event::WindowEvent,
+        keymap: &Keymap,
+    ) -> bool {
+        use winit::event::{ElementState, KeyboardInput, WindowEvent};
+
+        let Some(index) = self.outputs.iter().position(|o| o.id() == window_id) else {
+            return false;
+        };
+        match event {
+            WindowEvent::CloseRequested => {
+                self.outputs.remove(index);
+            }
+            WindowEvent::Resized(size) => self.outputs[index].resize(&self.device, *size),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                self.outputs[index].resize(&self.device, **new_inner_size)
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } if keymap.action(*key) == Some(Action::Fullscreen) => {
+                self.outputs[index].toggle_fullscreen()
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Moves the camera, returns whether the event was used.
+    pub fn input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        self.camera_controller.process_events(event)
     }
 
     pub fn update(&mut self, ap: &AudioProcessor, state: &mut State) {
-        let util_uniform = [UtilUniform {
-            time: state.get_elapsed_time().as_secs_f32(),
-            res_width: self.size.width as f32,
-            res_height: self.size.height as f32,
-        }];
-        let data: &[u8] = bytemuck::cast_slice(&util_uniform);
-        self.queue.write_buffer(&self.util_buffer, 0, data);
+        // The cpu frame time is stuck at the refresh rate with vsync, so it's only a fallback.
+        let frame_time = self.frame_timer.gpu_total().unwrap_or(state.frame_time);
+        if let Some(scale) = self.quality.update(frame_time, self.render_scale) {
+            self.set_render_scale(scale);
+        }
 
-        let fft = &mut self.fft_buffer;
         // We might not have gotten the lock, so just leave the data the same.
         let fft_texture = ap.fft_texture();
         if let Ok(fft_texture) = fft_texture.try_lock() {
-            fft.buffer.copy_from_slice(fft_texture.as_slice());
+            self.fft_buffer
+                .buffer
+                .copy_from_slice(fft_texture.as_slice());
         }
         drop(fft_texture);
 
+        let wave_texture = ap.wave_texture();
+        if let Ok(wave_texture) = wave_texture.try_lock() {
+            self.wave_buffer.copy_from_slice(wave_texture.as_slice());
+        }
+        drop(wave_texture);
+
+        self.update_frame(FrameInput {
+            time: state.get_elapsed_time().as_secs_f32(),
+            frame: state.frame,
+            mouse: state.mouse,
+            beat: ap.beat().pulse(),
+            sample_rate: ap.sample_rate(),
+        });
+    }
+
+    /// Takes the audio from somewhere other than the audio processor, e.g. a clip for thumbnails.
+    /// `fft` is the whole fft texture and `wave` the newest samples, like the audio processor has them.
+    pub fn update_offline(&mut self, input: FrameInput, fft: &[f32], wave: &[f32]) {
+        self.fft_buffer.buffer.copy_from_slice(fft);
+        self.wave_buffer.copy_from_slice(wave);
+        self.update_frame(input);
+    }
+
+    fn update_frame(&mut self, input: FrameInput) {
+        let FrameInput {
+            time,
+            frame,
+            mouse,
+            beat,
+            sample_rate,
+        } = input;
+        self.camera.aspect = self.size.width as f32 / self.size.height as f32;
+        self.camera_controller.update_camera(&mut self.camera, beat);
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[CameraUniform::new(&self.camera)]),
+        );
+
+        self.update_fade();
+        for layer in &mut self.layers {
+            layer.update(&self.queue, time, beat);
+        }
+        self.post.update(&self.queue, beat, time);
+
+        let render_size = self.render_size();
+        let util_uniform = [UtilUniform {
+            time,
+            res_width: render_size.width as f32,
+            res_height: render_size.height as f32,
+            frame,
+            // The mouse is in window pixels.
+            mouse: mouse * self.render_scale,
+            beat,
+            max_freq: sample_rate as f32 * self.fft_buffer.size.width as f32
+                / self.wave_buffer.len() as f32,
+            quality: self.quality.quality(),
+            _padding: 0.0,
+        }];
+        let data: &[u8] = bytemuck::cast_slice(&util_uniform);
+        self.queue.write_buffer(&self.util_buffer, 0, data);
+
+        let fft = &self.fft_buffer;
         self.queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
@@ -281,10 +862,18 @@ impl Renderer {
             },
             fft.size,
         );
+
+        let newest_fft = &fft.buffer[..fft.size.width as usize];
+        self.shadertoy_audio
+            .update(&self.queue, newest_fft, &self.wave_buffer);
     }
 
     pub fn render(&mut self, state: &State, ui: &mut Ui) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let output = self
+            .surface
+            .as_ref()
+            .expect("A window to render to")
+            .get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -295,28 +884,9 @@ impl Renderer {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.util_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.fft_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-        }
+        let presents = self.draw(&mut encoder, Some(&view));
 
+        self.frame_timer.mark(&mut encoder, "ui");
         let _ok = ui.render(
             &mut encoder,
             &view,
@@ -326,9 +896,140 @@ impl Renderer {
             &self.surface_config,
         );
 
+        self.frame_timer.end_frame(&mut encoder);
+
         self.queue.submit(iter::once(encoder.finish()));
+        self.frame_timer.after_submit();
+        self.capture.after_submit();
         output.present();
+        for texture in presents {
+            texture.present();
+        }
 
         Ok(())
     }
+
+    /// Renders a frame for the capture only, without a window.
+    pub fn render_headless(&mut self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+        let presents = self.draw(&mut encoder, None);
+        self.frame_timer.end_frame(&mut encoder);
+        self.queue.submit(iter::once(encoder.finish()));
+        self.frame_timer.after_submit();
+        self.capture.after_submit();
+        for texture in presents {
+            texture.present();
+        }
+    }
+
+    /// Renders the layers and the post effects, then puts the frame on `view` and the outputs.
+    /// Returns the textures of the outputs, to present after the submit.
+    fn draw(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: Option<&wgpu::TextureView>,
+    ) -> Vec<wgpu::SurfaceTexture> {
+        self.frame_timer.begin_frame(&self.device);
+        self.capture.poll(&self.device);
+        // Hidden layers still render when an output shows them on their own.
+        let shown: Vec<usize> = self
+            .outputs
+            .iter()
+            .filter_map(|o| match o.source {
+                OutputSource::Layer(index) => Some(index),
+                OutputSource::Frame => None,
+            })
+            .collect();
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            if !layer.visible && !shown.contains(&index) {
+                continue;
+            }
+            layer.program.render(
+                encoder,
+                &self.pass_resources,
+                &[&self.util_bind_group, &self.fft_bind_group],
+                &mut self.frame_timer,
+            );
+        }
+        self.frame_timer.mark(encoder, "layers");
+        let layers =
+            self.compositor
+                .render(&self.device, encoder, &self.pass_resources, &self.layers);
+        let frame = match self.post.render(
+            &self.device,
+            encoder,
+            &self.pass_resources,
+            &layers.view,
+            &mut self.frame_timer,
+        ) {
+            Some(target) => target,
+            None => layers,
+        };
+        self.capture.capture(
+            &self.device,
+            encoder,
+            &self.pass_resources,
+            frame,
+            self.tonemap,
+        );
+        if let Some(view) = view {
+            self.frame_timer.mark(encoder, "blit");
+            self.blitter.blit(
+                &self.device,
+                encoder,
+                &self.pass_resources,
+                &frame.view,
+                view,
+                self.upscale_filter,
+                self.tonemap,
+            );
+        }
+
+        self.frame_timer.mark(encoder, "outputs");
+        let mut presents = Vec::new();
+        for output in &self.outputs {
+            let source = match output.source {
+                OutputSource::Layer(index) => self.layers.get(index).map(|l| l.program.output()),
+                OutputSource::Frame => None,
+            }
+            .unwrap_or(frame);
+            match output.render(
+                &self.device,
+                encoder,
+                &self.pass_resources,
+                &source.view,
+                self.upscale_filter,
+                self.tonemap,
+            ) {
+                Ok(texture) => presents.push(texture),
+                Err(e) => eprintln!("Output window: {:?}", e),
+            }
+        }
+        presents
+    }
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                // For the frame timer, where there are timestamps.
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web we'll have to disable some.
+                limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+            },
+            None, // Trace path
+        )
+        .await
+        .unwrap()
 }
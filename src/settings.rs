@@ -0,0 +1,523 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::audio_processor::{Analysis, AudioProcessor, Binning};
+use crate::blit::{Tonemap, UpscaleFilter};
+use crate::fft_buffer::FFTDimensions;
+use crate::keymap::Bindings;
+use crate::midi::Mapping;
+use crate::preset::PRESET_DIR;
+use crate::renderer::Renderer;
+use crate::shaders::SHADER_DIR;
+use crate::state::{present_mode_name, DisplayMode, DisplaySettings, State, PRESENT_MODES};
+use crate::ui::Ui;
+
+/// Where the settings are kept between runs, next to the shaders.
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// A music visualizer running WGSL and Shadertoy shaders.
+/// Anything given here overrides the settings file for this run.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Args {
+    /// The shader to start with.
+    pub shader: Option<PathBuf>,
+    /// Where shaders are listed from.
+    #[arg(long)]
+    pub shader_dir: Option<PathBuf>,
+    /// Where presets are saved and listed from.
+    #[arg(long)]
+    pub preset_dir: Option<PathBuf>,
+    /// A preset to start with, over the shader.
+    #[arg(long)]
+    pub preset: Option<PathBuf>,
+    /// The input device to listen to, by name, see `--list-devices`.
+    #[arg(long, conflicts_with = "audio_file")]
+    pub audio_device: Option<String>,
+    /// The MIDI port to listen to, by name, see `--list-midi-ports`.
+    /// Without one there's a virtual port to connect to.
+    #[arg(long)]
+    pub midi_port: Option<String>,
+    /// Listen for OSC messages on this UDP port.
+    #[arg(long)]
+    pub osc_port: Option<u16>,
+    /// Send the audio features over OSC to this address, like `127.0.0.1:9001`.
+    #[arg(long)]
+    pub osc_broadcast: Option<String>,
+    /// Analyse a wav file on repeat instead of listening to a device.
+    #[arg(long)]
+    pub audio_file: Option<PathBuf>,
+    /// Samples per fft run, a power of two from 256 to 16384.
+    #[arg(long, value_parser = parse_fft_size)]
+    pub fft_size: Option<usize>,
+    /// The window size, like `1280x720`.
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    #[arg(long)]
+    pub fullscreen: bool,
+    #[arg(long)]
+    pub always_on_top: bool,
+    /// A see-through window, black becomes transparent.
+    #[arg(long)]
+    pub transparent: bool,
+    /// fifo, fifo_relaxed, mailbox or immediate.
+    #[arg(long)]
+    pub present_mode: Option<String>,
+    /// Read settings from and save them to this file instead.
+    #[arg(long)]
+    pub settings: Option<PathBuf>,
+    /// Don't save the settings on exit.
+    #[arg(long)]
+    pub no_save: bool,
+
+    /// Print the prelude reference as markdown and exit.
+    #[arg(long, help_heading = "Without a window")]
+    pub prelude_docs: bool,
+    /// List the audio input devices and exit.
+    #[arg(long, help_heading = "Without a window")]
+    pub list_devices: bool,
+    /// List the MIDI input ports and exit.
+    #[arg(long, help_heading = "Without a window")]
+    pub list_midi_ports: bool,
+    /// List the shaders and exit.
+    #[arg(long, help_heading = "Without a window")]
+    pub list_shaders: bool,
+    /// Render thumbnails of the shaders and presets that changed since and exit.
+    /// They run against `--audio-file`, or a made up beat without one.
+    #[arg(long, help_heading = "Without a window")]
+    pub thumbnails: bool,
+    /// Also save an animated gif preview with the thumbnails.
+    #[arg(long, requires = "thumbnails", help_heading = "Without a window")]
+    pub animated: bool,
+}
+
+impl Args {
+    pub fn settings_path(&self) -> PathBuf {
+        self.settings
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(SETTINGS_FILE))
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32)> {
+    let (width, height) = s
+        .split_once('x')
+        .with_context(|| format!("expected <width>x<height> but got `{}`", s))?;
+    Ok((width.parse()?, height.parse()?))
+}
+
+/// Smaller ones leave next to nothing in the fft texture, bigger ones lag behind the audio.
+const MIN_FFT_SIZE: usize = 256;
+const MAX_FFT_SIZE: usize = 16384;
+
+fn parse_fft_size(s: &str) -> Result<usize> {
+    let size = s.parse()?;
+    check_fft_size(size)?;
+    Ok(size)
+}
+
+fn check_fft_size(size: usize) -> Result<()> {
+    ensure!(
+        size.is_power_of_two() && (MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&size),
+        "the fft size has to be a power of two from {} to {}, not {}",
+        MIN_FFT_SIZE,
+        MAX_FFT_SIZE,
+        size
+    );
+    Ok(())
+}
+
+/// The rows of the fft texture, it's one texture so this stays well under the device limit.
+const MAX_TIME_SLICES: usize = 2048;
+
+fn check_time_slices(time_slices: usize) -> Result<()> {
+    ensure!(
+        (1..=MAX_TIME_SLICES).contains(&time_slices),
+        "time_slices has to be from 1 to {}, not {}",
+        MAX_TIME_SLICES,
+        time_slices
+    );
+    Ok(())
+}
+
+/// Everything that's kept between runs, saved on exit.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub audio: AudioSettings,
+    pub analysis: AnalysisSettings,
+    pub rendering: RenderSettings,
+    pub window: WindowSettings,
+    pub ui: UiSettings,
+    pub midi: MidiSettings,
+    pub osc: OscSettings,
+    /// Only the ones that differ from the defaults are needed.
+    pub keys: Bindings,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// The default input device when unset or when it's gone.
+    pub device: Option<String>,
+    /// Takes the place of the device.
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalysisSettings {
+    pub fft_size: usize,
+    /// How many fft runs the fft texture keeps.
+    pub time_slices: usize,
+    pub smoothing: f32,
+    pub db_min: f32,
+    pub db_max: f32,
+    pub binning: Binning,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        let dimensions = FFTDimensions::default();
+        let analysis = Analysis::default();
+        Self {
+            fft_size: dimensions.fft_size,
+            time_slices: dimensions.texture_height() as usize,
+            smoothing: analysis.smoothing,
+            db_min: analysis.db_min,
+            db_max: analysis.db_max,
+            binning: analysis.binning,
+        }
+    }
+}
+
+impl AnalysisSettings {
+    pub fn dimensions(&self) -> FFTDimensions {
+        FFTDimensions::new(self.fft_size, self.time_slices.clamp(1, MAX_TIME_SLICES), 4)
+    }
+
+    pub fn analysis(&self) -> Analysis {
+        Analysis {
+            smoothing: self.smoothing,
+            db_min: self.db_min,
+            db_max: self.db_max,
+            binning: self.binning,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// Loaded into the first layer, the first shader in the directory when unset.
+    pub shader: Option<PathBuf>,
+    pub render_scale: f32,
+    pub upscale_filter: UpscaleFilter,
+    pub tonemap: Tonemap,
+    pub adaptive_quality: bool,
+    pub target_fps: f32,
+    pub present_mode: String,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            shader: None,
+            render_scale: 1.0,
+            upscale_filter: UpscaleFilter::default(),
+            tonemap: Tonemap::default(),
+            adaptive_quality: false,
+            target_fps: 60.0,
+            present_mode: present_mode_name(wgpu::PresentMode::Fifo).to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    /// The size of the window when it isn't fullscreen.
+    pub width: u32,
+    pub height: u32,
+    pub mode: DisplayMode,
+    pub monitor: usize,
+    pub always_on_top: bool,
+    pub transparent: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            mode: DisplayMode::default(),
+            monitor: 0,
+            always_on_top: false,
+            transparent: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiSettings {
+    pub visible: bool,
+    pub shader_dir: PathBuf,
+    pub preset_dir: PathBuf,
+    /// Shaders by their path in the shader directory, like `tunnels/warp.wgsl`.
+    pub favorites: BTreeSet<String>,
+    pub ratings: BTreeMap<String, u8>,
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            shader_dir: PathBuf::from(SHADER_DIR),
+            preset_dir: PathBuf::from(PRESET_DIR),
+            favorites: BTreeSet::new(),
+            ratings: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiSettings {
+    /// The virtual port when unset.
+    pub port: Option<String>,
+    pub mappings: Vec<Mapping>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscSettings {
+    /// Not listening when unset.
+    pub port: Option<u16>,
+    pub broadcast: Option<String>,
+}
+
+impl Settings {
+    /// The defaults when there's no file yet, a broken file is reported and ignored.
+    pub fn load(path: &Path) -> Self {
+        let Some(text) = fs::read_to_string(path).ok() else {
+            return Self::default();
+        };
+        let mut settings: Self = toml::from_str(&text).unwrap_or_else(|e| {
+            eprintln!("Ignoring {}: {}", path.display(), e);
+            Self::default()
+        });
+        if let Err(e) = check_fft_size(settings.analysis.fft_size) {
+            let fft_size = AnalysisSettings::default().fft_size;
+            eprintln!("In {}, {}, using {}", path.display(), e, fft_size);
+            settings.analysis.fft_size = fft_size;
+        }
+        if let Err(e) = check_time_slices(settings.analysis.time_slices) {
+            let time_slices = AnalysisSettings::default().time_slices;
+            eprintln!("In {}, {}, using {}", path.display(), e, time_slices);
+            settings.analysis.time_slices = time_slices;
+        }
+        if let Err(e) = settings.analysis.analysis().check() {
+            eprintln!("In {}, {}, using the default analysis", path.display(), e);
+            let AnalysisSettings {
+                fft_size,
+                time_slices,
+                ..
+            } = settings.analysis;
+            settings.analysis = AnalysisSettings {
+                fft_size,
+                time_slices,
+                ..AnalysisSettings::default()
+            };
+        }
+        settings
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string_pretty(self)?;
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The command line wins over the file, for this run. See `for_file` for what's saved.
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(shader) = &args.shader {
+            self.rendering.shader = Some(shader.clone());
+        }
+        if let Some(dir) = &args.shader_dir {
+            self.ui.shader_dir = dir.clone();
+        }
+        if let Some(dir) = &args.preset_dir {
+            self.ui.preset_dir = dir.clone();
+        }
+        if let Some(device) = &args.audio_device {
+            self.audio.device = Some(device.clone());
+            self.audio.file = None;
+        }
+        if let Some(port) = &args.midi_port {
+            self.midi.port = Some(port.clone());
+        }
+        if let Some(port) = args.osc_port {
+            self.osc.port = Some(port);
+        }
+        if let Some(broadcast) = &args.osc_broadcast {
+            self.osc.broadcast = Some(broadcast.clone());
+        }
+        if let Some(file) = &args.audio_file {
+            self.audio.file = Some(file.clone());
+        }
+        if let Some(fft_size) = args.fft_size {
+            self.analysis.fft_size = fft_size;
+        }
+        if let Some((width, height)) = args.size {
+            self.window.width = width;
+            self.window.height = height;
+        }
+        if args.fullscreen {
+            self.window.mode = DisplayMode::Borderless;
+        }
+        self.window.always_on_top |= args.always_on_top;
+        self.window.transparent |= args.transparent;
+        if let Some(mode) = &args.present_mode {
+            self.rendering.present_mode = mode.clone();
+        }
+    }
+
+    /// What to save: these settings, except the ones the command line can set are put back
+    /// to what was `loaded` from the file when they're still what the run `started` with.
+    /// Ones that were changed while running are kept.
+    pub fn for_file(&self, started: &Settings, loaded: &Settings) -> Settings {
+        fn restore<T: PartialEq + Clone>(now: &mut T, started: &T, loaded: &T) {
+            if now == started {
+                *now = loaded.clone();
+            }
+        }
+        let mut file = self.clone();
+        restore(
+            &mut file.rendering.shader,
+            &started.rendering.shader,
+            &loaded.rendering.shader,
+        );
+        restore(
+            &mut file.rendering.present_mode,
+            &started.rendering.present_mode,
+            &loaded.rendering.present_mode,
+        );
+        restore(
+            &mut file.ui.shader_dir,
+            &started.ui.shader_dir,
+            &loaded.ui.shader_dir,
+        );
+        restore(
+            &mut file.ui.preset_dir,
+            &started.ui.preset_dir,
+            &loaded.ui.preset_dir,
+        );
+        restore(
+            &mut file.audio.device,
+            &started.audio.device,
+            &loaded.audio.device,
+        );
+        restore(
+            &mut file.audio.file,
+            &started.audio.file,
+            &loaded.audio.file,
+        );
+        restore(&mut file.midi.port, &started.midi.port, &loaded.midi.port);
+        restore(&mut file.osc.port, &started.osc.port, &loaded.osc.port);
+        restore(
+            &mut file.osc.broadcast,
+            &started.osc.broadcast,
+            &loaded.osc.broadcast,
+        );
+        restore(
+            &mut file.analysis.fft_size,
+            &started.analysis.fft_size,
+            &loaded.analysis.fft_size,
+        );
+        restore(
+            &mut file.window.width,
+            &started.window.width,
+            &loaded.window.width,
+        );
+        restore(
+            &mut file.window.height,
+            &started.window.height,
+            &loaded.window.height,
+        );
+        restore(
+            &mut file.window.mode,
+            &started.window.mode,
+            &loaded.window.mode,
+        );
+        restore(
+            &mut file.window.always_on_top,
+            &started.window.always_on_top,
+            &loaded.window.always_on_top,
+        );
+        restore(
+            &mut file.window.transparent,
+            &started.window.transparent,
+            &loaded.window.transparent,
+        );
+        file
+    }
+
+    pub fn display(&self) -> DisplaySettings {
+        let present_mode = PRESENT_MODES
+            .into_iter()
+            .find(|m| present_mode_name(*m) == self.rendering.present_mode)
+            .unwrap_or_else(|| {
+                eprintln!("Unknown present mode `{}`", self.rendering.present_mode);
+                wgpu::PresentMode::Fifo
+            });
+        DisplaySettings {
+            present_mode,
+            mode: self.window.mode,
+            always_on_top: self.window.always_on_top,
+            transparent: self.window.transparent,
+        }
+    }
+
+    /// Picks up what was changed while running.
+    pub fn update(&mut self, state: &State, audio: &AudioProcessor, renderer: &Renderer, ui: &Ui) {
+        if state.display.mode == DisplayMode::Windowed {
+            let size = state.window.inner_size();
+            self.window.width = size.width;
+            self.window.height = size.height;
+        }
+        self.window.mode = state.display.mode;
+        self.window.monitor = state.monitor;
+        self.window.always_on_top = state.display.always_on_top;
+        self.window.transparent = state.display.transparent;
+
+        let analysis = audio.analysis();
+        self.analysis.smoothing = analysis.smoothing;
+        self.analysis.db_min = analysis.db_min;
+        self.analysis.db_max = analysis.db_max;
+        self.analysis.binning = analysis.binning;
+
+        let layer = &renderer.layers[renderer.selected_layer];
+        self.rendering.shader = Some(layer.path.clone());
+        self.rendering.render_scale = renderer.render_scale();
+        self.rendering.upscale_filter = renderer.upscale_filter;
+        self.rendering.tonemap = renderer.tonemap;
+        self.rendering.adaptive_quality = renderer.quality.enabled;
+        self.rendering.target_fps = renderer.quality.target_fps;
+        self.rendering.present_mode =
+            present_mode_name(renderer.surface_config.present_mode).to_string();
+
+        self.ui.visible = ui.visible;
+        self.ui.favorites = ui.browser.favorites.clone();
+        self.ui.ratings = ui.browser.ratings.clone();
+        self.midi.port = ui.midi.port.clone();
+        self.midi.mappings = ui.midi.mappings.clone();
+        self.keys = ui.keymap.bindings.clone();
+        self.osc.port = ui.osc.listening_port();
+        self.osc.broadcast = Some(ui.osc.broadcast.clone()).filter(|b| !b.is_empty());
+    }
+}
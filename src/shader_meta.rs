@@ -0,0 +1,628 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+use crate::camera::CameraMode;
+use crate::post::{Effect, EffectSettings, PostSettings};
+use crate::shaders;
+use crate::shadertoy;
+
+/// Shaders configure how they are run with directive comments, one per line:
+///
+/// `//! pass buffer_a scale=0.5 format=rgba16float`
+///
+/// Lines that don't start with `//!` are left alone, so a shader without any
+/// directives runs as a single image pass like before.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderMeta {
+    pub language: Language,
+    /// `//! prelude <version>`, the prelude the shader was written against.
+    pub prelude: usize,
+    /// The passes in the order they are rendered, always ending with the image pass.
+    pub passes: Vec<PassDesc>,
+    /// `//! shadertoy`, `fs_user` is generated and calls `mainImage` with the Shadertoy inputs set up.
+    pub shadertoy: bool,
+    /// `//! particles count=<n>`, a compute stage updates the particles every frame.
+    pub particles: Option<ParticlesDesc>,
+    /// `//! camera <mode> [eye=x,y,z] [target=x,y,z]`, the shader still has to use `camera`.
+    pub camera: CameraDesc,
+    /// `//! texture <name> <path>`, bound as `<name>` and `<name>_sampler`.
+    pub textures: Vec<TextureDesc>,
+    /// `//! post <effect>`, the effects applied to the image pass.
+    pub post: PostSettings,
+    /// `//! param <name> <min> <max> [default]`, read as `params.<name>` and set from the ui,
+    /// MIDI and OSC.
+    pub params: Vec<ParamDesc>,
+    pub info: ShaderInfo,
+    /// The directives that couldn't be used, as `line <n>: <error>`.
+    pub errors: Vec<String>,
+}
+
+/// What the shader browser shows about a shader, `//! author <name>`,
+/// `//! description <text>` and `//! source <url>`.
+/// Without the directives the comments at the top stand in, like a pasted Shadertoy header:
+/// the first line for the description, the first link for the source and `Created by` for the author.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderInfo {
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub source: Option<String>,
+}
+
+impl ShaderInfo {
+    /// Doesn't look at the other directives, so it's cheap to do for every shader.
+    pub fn parse(src: &str) -> Self {
+        let mut info = Self::default();
+        let mut author = None;
+        let mut description = None;
+        let mut link = None;
+        // The header ends with the first line that isn't a comment, blank lines before it aside.
+        let mut in_header = true;
+        let mut header_started = false;
+        for line in src.lines().map(str::trim) {
+            if let Some(directive) = line.strip_prefix("//!") {
+                let directive = directive.trim();
+                let (name, rest) = directive
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((directive, ""));
+                let value = Some(rest.trim().to_string()).filter(|v| !v.is_empty());
+                match name {
+                    "author" => info.author = value,
+                    "description" => info.description = value,
+                    "source" => info.source = value,
+                    _ => {}
+                }
+            } else if let Some(comment) = line.strip_prefix("//").filter(|_| in_header) {
+                header_started = true;
+                let comment = comment.trim();
+                let url = comment
+                    .split_whitespace()
+                    .find(|word| word.starts_with("http://") || word.starts_with("https://"));
+                let lower = comment.to_lowercase();
+                if let Some(url) = url {
+                    link.get_or_insert_with(|| url.to_string());
+                } else if let Some(name) = lower
+                    .starts_with("created by ")
+                    .then(|| comment.get("created by ".len()..))
+                    .flatten()
+                {
+                    author.get_or_insert_with(|| name.trim().to_string());
+                } else if !comment.is_empty()
+                    && !lower.starts_with("based on")
+                    && !lower.starts_with("derived from")
+                {
+                    description.get_or_insert_with(|| comment.to_string());
+                }
+            } else if !line.is_empty() || header_started {
+                in_header = false;
+            }
+        }
+        info.author = info.author.or(author);
+        info.description = info.description.or(description);
+        info.source = info.source.or(link);
+        info
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    Wgsl,
+    /// Shadertoy GLSL, translated to WGSL before it's compiled.
+    Glsl,
+}
+
+impl Language {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "wgsl" => Some(Language::Wgsl),
+            "glsl" => Some(Language::Glsl),
+            _ => None,
+        }
+    }
+}
+
+impl ShaderMeta {
+    pub fn parse(src: &str, language: Language) -> Self {
+        let mut meta = Self {
+            language,
+            prelude: 1,
+            // Pasted Shadertoy code is all there is in GLSL.
+            shadertoy: language == Language::Glsl,
+            ..Default::default()
+        };
+
+        for (line_nr, line) in src.lines().enumerate() {
+            let Some(directive) = line.trim_start().strip_prefix("//!") else {
+                continue;
+            };
+            let mut words = directive.split_whitespace();
+            let result = match words.next() {
+                Some("pass") if language == Language::Glsl => {
+                    Err(anyhow!("GLSL shaders only have the image pass"))
+                }
+                Some("pass") => PassDesc::parse(words).map(|pass| meta.set_pass(pass)),
+                Some("particles") if language == Language::Glsl => {
+                    Err(anyhow!("GLSL shaders can't have particles"))
+                }
+                Some("particles") => ParticlesDesc::parse(words).map(|p| meta.particles = Some(p)),
+                Some("camera") if language == Language::Glsl => {
+                    Err(anyhow!("GLSL shaders don't have the camera"))
+                }
+                Some("camera") => CameraDesc::parse(words).map(|c| meta.camera = c),
+                Some("texture") if language == Language::Glsl => {
+                    Err(anyhow!("GLSL shaders only have iChannel0"))
+                }
+                Some("texture") => TextureDesc::parse(words).and_then(|t| meta.add_texture(t)),
+                Some("prelude") if language == Language::Glsl => {
+                    Err(anyhow!("GLSL shaders don't use the prelude"))
+                }
+                Some("prelude") => parse_prelude(words).map(|v| meta.prelude = v),
+                Some("param") if language == Language::Glsl => {
+                    Err(anyhow!("GLSL shaders don't have params"))
+                }
+                Some("param") => ParamDesc::parse(words).and_then(|p| meta.add_param(p)),
+                Some("post") => parse_post(words, &mut meta.post),
+                Some("shadertoy") => {
+                    meta.shadertoy = true;
+                    Ok(())
+                }
+                // Read by `ShaderInfo`.
+                Some("author" | "description" | "source") => Ok(()),
+                Some(other) => Err(anyhow!("unknown directive `{}`", other)),
+                None => Ok(()),
+            };
+            if let Err(e) = result {
+                meta.errors.push(format!("line {}: {}", line_nr + 1, e));
+            }
+        }
+
+        if !meta.passes.iter().any(|p| p.slot == PassSlot::Image) {
+            meta.passes.push(PassDesc::new(PassSlot::Image));
+        }
+        meta.passes.sort_by_key(|p| p.slot);
+        meta.info = ShaderInfo::parse(src);
+        meta
+    }
+
+    /// The directive errors as one error, so they end up where the compile errors do.
+    pub fn check(&self) -> Result<()> {
+        ensure!(
+            self.errors.is_empty(),
+            "Shader directives:\n{}",
+            self.errors.join("\n")
+        );
+        Ok(())
+    }
+
+    /// The fragment entry point of the pass.
+    /// Buffer passes get a generated entry point that calls the users `fs_<slot name>`.
+    pub fn entry_point(&self, slot: PassSlot) -> String {
+        match slot {
+            PassSlot::Image if self.language == Language::Glsl => {
+                shadertoy::GLSL_ENTRY_POINT.to_string()
+            }
+            PassSlot::Image => "fs_main".to_string(),
+            _ => format!("fs_main_{}", slot.name()),
+        }
+    }
+
+    fn add_texture(&mut self, texture: TextureDesc) -> Result<()> {
+        self.textures.retain(|t| t.name != texture.name);
+        ensure!(
+            self.textures.len() < MAX_TEXTURES,
+            "at most {} textures are supported",
+            MAX_TEXTURES
+        );
+        self.textures.push(texture);
+        Ok(())
+    }
+
+    fn add_param(&mut self, param: ParamDesc) -> Result<()> {
+        self.params.retain(|p| p.name != param.name);
+        ensure!(
+            self.params.len() < MAX_PARAMS,
+            "at most {} params are supported",
+            MAX_PARAMS
+        );
+        self.params.push(param);
+        Ok(())
+    }
+
+    fn set_pass(&mut self, pass: PassDesc) {
+        self.passes.retain(|p| p.slot != pass.slot);
+        self.passes.push(pass);
+    }
+}
+
+/// The fixed set of passes a shader can declare, in the order they are rendered.
+/// Each one has a texture the other passes can sample, the image pass is `prev_frame`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PassSlot {
+    BufferA,
+    BufferB,
+    BufferC,
+    BufferD,
+    Image,
+}
+
+impl PassSlot {
+    pub const ALL: [PassSlot; 5] = [
+        PassSlot::BufferA,
+        PassSlot::BufferB,
+        PassSlot::BufferC,
+        PassSlot::BufferD,
+        PassSlot::Image,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|slot| slot.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PassSlot::BufferA => "buffer_a",
+            PassSlot::BufferB => "buffer_b",
+            PassSlot::BufferC => "buffer_c",
+            PassSlot::BufferD => "buffer_d",
+            PassSlot::Image => "image",
+        }
+    }
+
+    /// The binding of the slots texture in the feedback bind group, see the prelude.
+    pub fn binding(&self) -> u32 {
+        match self {
+            PassSlot::Image => 1,
+            PassSlot::BufferA => 2,
+            PassSlot::BufferB => 3,
+            PassSlot::BufferC => 4,
+            PassSlot::BufferD => 5,
+        }
+    }
+}
+
+/// The largest `size=` a pass takes, what wgpu allows by default.
+const MAX_PASS_SIZE: u32 = 8192;
+/// The largest `scale=`, anything more is likely a typo.
+const MAX_PASS_SCALE: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PassSize {
+    /// Fraction of the output size.
+    Scale(f32),
+    /// Fixed size in pixels.
+    Fixed(u32, u32),
+}
+
+impl PassSize {
+    /// The size in pixels, scaled ones are kept within `max` so a big window can't go over
+    /// the texture limit.
+    pub fn resolve(&self, width: u32, height: u32, max: u32) -> (u32, u32) {
+        match *self {
+            PassSize::Scale(s) => (
+                ((width as f32 * s).round() as u32).clamp(1, max),
+                ((height as f32 * s).round() as u32).clamp(1, max),
+            ),
+            PassSize::Fixed(w, h) => (w, h),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PassDesc {
+    pub slot: PassSlot,
+    pub size: PassSize,
+    pub format: wgpu::TextureFormat,
+}
+
+impl PassDesc {
+    pub fn new(slot: PassSlot) -> Self {
+        // The image is linear and can go above 1 until it's tonemapped,
+        // buffers are mostly used for feedback where 8 bits run out fast.
+        Self {
+            slot,
+            size: PassSize::Scale(1.0),
+            format: wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+
+    /// Parses the arguments of `//! pass <slot> [scale=<f32>] [size=<w>x<h>] [format=<format>]`.
+    fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let name = words.next().context("pass is missing a name")?;
+        let slot = PassSlot::from_name(name).with_context(|| {
+            format!(
+                "unknown pass `{}`, expected buffer_a, buffer_b, buffer_c, buffer_d or image",
+                name
+            )
+        })?;
+        let mut pass = Self::new(slot);
+
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .with_context(|| format!("expected key=value but got `{}`", word))?;
+            match key {
+                "scale" => {
+                    let scale: f32 = value.parse()?;
+                    ensure!(
+                        scale > 0.0 && scale <= MAX_PASS_SCALE,
+                        "scale must be above 0 and at most {}",
+                        MAX_PASS_SCALE
+                    );
+                    pass.size = PassSize::Scale(scale);
+                }
+                "size" => {
+                    let (w, h) = value.split_once('x').with_context(|| {
+                        format!("expected <width>x<height> but got `{}`", value)
+                    })?;
+                    let (w, h) = (w.parse()?, h.parse()?);
+                    ensure!(
+                        (1..=MAX_PASS_SIZE).contains(&w) && (1..=MAX_PASS_SIZE).contains(&h),
+                        "size must be from 1 to {} on each side",
+                        MAX_PASS_SIZE
+                    );
+                    pass.size = PassSize::Fixed(w, h);
+                }
+                "format" => pass.format = parse_format(value)?,
+                _ => bail!("unknown pass option `{}`", key),
+            }
+        }
+        Ok(pass)
+    }
+}
+
+fn parse_prelude<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<usize> {
+    let version = words
+        .next()
+        .context("prelude is missing a version")?
+        .parse()?;
+    ensure!(
+        (1..=shaders::LATEST_PRELUDE).contains(&version),
+        "unknown prelude version {}, the latest is {}",
+        version,
+        shaders::LATEST_PRELUDE
+    );
+    Ok(version)
+}
+
+/// Parses the arguments of `//! post <effect> [strength=<f32>] [audio=<f32>]`,
+/// with `threshold=<f32>` for bloom, `segments=<n>` for kaleidoscope and a path first for lut.
+fn parse_post<'a>(mut words: impl Iterator<Item = &'a str>, post: &mut PostSettings) -> Result<()> {
+    let name = words.next().context("post is missing an effect")?;
+    let effect = Effect::from_name(name).with_context(|| {
+        let names: Vec<_> = Effect::ALL.iter().map(|e| e.name()).collect();
+        format!("unknown effect `{}`, expected {}", name, names.join(", "))
+    })?;
+    if effect == Effect::Lut {
+        post.lut = Some(PathBuf::from(
+            words.next().context("lut is missing a path")?,
+        ));
+    }
+
+    let mut settings = EffectSettings {
+        strength: 1.0,
+        audio: 0.0,
+    };
+    for word in words {
+        let (key, value) = word
+            .split_once('=')
+            .with_context(|| format!("expected key=value but got `{}`", word))?;
+        match (effect, key) {
+            (_, "strength") => settings.strength = value.parse()?,
+            (_, "audio") => settings.audio = value.parse()?,
+            (Effect::Bloom, "threshold") => post.bloom_threshold = value.parse()?,
+            (Effect::Kaleidoscope, "segments") => {
+                post.kaleidoscope_segments = value.parse()?;
+                ensure!(
+                    post.kaleidoscope_segments >= 2,
+                    "segments must be at least 2"
+                );
+            }
+            _ => bail!("unknown {} option `{}`", name, key),
+        }
+    }
+    *post.get_mut(effect) = settings;
+    Ok(())
+}
+
+/// Only filterable formats, as the passes are sampled with a linear sampler.
+fn parse_format(name: &str) -> Result<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+    Ok(match name {
+        "rgba8unorm" => Rgba8Unorm,
+        "rgba16float" => Rgba16Float,
+        "rg16float" => Rg16Float,
+        "r16float" => R16Float,
+        _ => bail!(
+            "unsupported format `{}`, expected rgba8unorm, rgba16float, rg16float or r16float",
+            name
+        ),
+    })
+}
+
+/// The most particles a shader gets, 48 MB of them, and few enough to dispatch in one go.
+const MAX_PARTICLES: u32 = 1 << 20;
+
+#[derive(Clone, Debug)]
+pub struct ParticlesDesc {
+    pub count: u32,
+}
+
+impl ParticlesDesc {
+    /// Parses the arguments of `//! particles count=<n>`.
+    fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let mut particles = Self { count: 1024 };
+        for word in words {
+            match word.split_once('=') {
+                Some(("count", value)) => {
+                    particles.count = value.parse()?;
+                    ensure!(
+                        (1..=MAX_PARTICLES).contains(&particles.count),
+                        "count must be from 1 to {}",
+                        MAX_PARTICLES
+                    );
+                }
+                _ => bail!("unknown particles option `{}`", word),
+            }
+        }
+        Ok(particles)
+    }
+}
+
+/// Where the camera starts and how it moves, unset positions keep the defaults of `Camera`.
+#[derive(Clone, Debug, Default)]
+pub struct CameraDesc {
+    pub mode: CameraMode,
+    pub eye: Option<glam::Vec3>,
+    pub target: Option<glam::Vec3>,
+}
+
+impl CameraDesc {
+    /// Parses the arguments of `//! camera [orbit|fly|audio] [eye=x,y,z] [target=x,y,z]`.
+    fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let mut camera = Self::default();
+        for word in words {
+            match word.split_once('=') {
+                Some(("eye", value)) => camera.eye = Some(parse_vec3(value)?),
+                Some(("target", value)) => camera.target = Some(parse_vec3(value)?),
+                Some(_) => bail!("unknown camera option `{}`", word),
+                None => {
+                    camera.mode = CameraMode::from_name(word).with_context(|| {
+                        format!(
+                            "unknown camera mode `{}`, expected orbit, fly or audio",
+                            word
+                        )
+                    })?
+                }
+            }
+        }
+        Ok(camera)
+    }
+}
+
+fn parse_vec3(value: &str) -> Result<glam::Vec3> {
+    let v = value
+        .split(',')
+        .map(|c| c.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    ensure!(v.len() == 3, "expected x,y,z but got `{}`", value);
+    Ok(glam::Vec3::from_slice(&v))
+}
+
+/// The textures are bound after the passes in the feedback bind group.
+pub const MAX_TEXTURES: usize = 4;
+
+/// The binding of the `index`th texture in the feedback bind group, its sampler is the next one.
+pub fn texture_binding(index: usize) -> u32 {
+    6 + 2 * index as u32
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureDesc {
+    pub name: String,
+    /// Relative to `texture::TEXTURE_DIR`, a directory is played back as a frame sequence.
+    pub path: PathBuf,
+    pub filter: wgpu::FilterMode,
+    pub address_mode: wgpu::AddressMode,
+    /// Frames per second of a frame sequence.
+    pub fps: f32,
+}
+
+impl TextureDesc {
+    /// Parses the arguments of
+    /// `//! texture <name> <path> [filter=linear|nearest] [wrap=clamp|repeat|mirror] [fps=<f32>]`.
+    fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let name = words.next().context("texture is missing a name")?;
+        ensure!(
+            is_identifier(name),
+            "texture name `{}` isn't a valid identifier",
+            name
+        );
+        let path = words.next().context("texture is missing a path")?;
+        let mut texture = Self {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            filter: wgpu::FilterMode::Linear,
+            address_mode: wgpu::AddressMode::Repeat,
+            fps: 24.0,
+        };
+
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .with_context(|| format!("expected key=value but got `{}`", word))?;
+            match (key, value) {
+                ("filter", "linear") => texture.filter = wgpu::FilterMode::Linear,
+                ("filter", "nearest") => texture.filter = wgpu::FilterMode::Nearest,
+                ("wrap", "clamp") => texture.address_mode = wgpu::AddressMode::ClampToEdge,
+                ("wrap", "repeat") => texture.address_mode = wgpu::AddressMode::Repeat,
+                ("wrap", "mirror") => texture.address_mode = wgpu::AddressMode::MirrorRepeat,
+                ("fps", _) => {
+                    texture.fps = value.parse()?;
+                    ensure!(texture.fps > 0.0, "fps must be positive");
+                }
+                _ => bail!("unknown texture option `{}`", word),
+            }
+        }
+        Ok(texture)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The params are the fields of a uniform struct bound after the textures.
+pub const MAX_PARAMS: usize = 16;
+pub const PARAMS_BINDING: u32 = 6 + 2 * MAX_TEXTURES as u32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamDesc {
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+}
+
+impl ParamDesc {
+    /// Parses the arguments of `//! param <name> <min> <max> [default]`,
+    /// the default is `min` when it's left out.
+    fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let name = words.next().context("param is missing a name")?;
+        ensure!(
+            is_identifier(name),
+            "param name `{}` isn't a valid identifier",
+            name
+        );
+        let mut number = |what: &str| -> Result<Option<f32>> {
+            let Some(word) = words.next() else {
+                return Ok(None);
+            };
+            let value: f32 = word
+                .parse()
+                .with_context(|| format!("param {} `{}` isn't a number", what, word))?;
+            ensure!(value.is_finite(), "param {} must be finite", what);
+            Ok(Some(value))
+        };
+        let min = number("min")?.context("param is missing min and max")?;
+        let max = number("max")?.context("param is missing max")?;
+        ensure!(min < max, "param min must be less than max");
+        let default = number("default")?.unwrap_or(min);
+        ensure!(
+            (min..=max).contains(&default),
+            "param default must be from {} to {}",
+            min,
+            max
+        );
+        if let Some(word) = words.next() {
+            bail!("unexpected `{}` after the param default", word);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            min,
+            max,
+            default,
+        })
+    }
+}
@@ -1,14 +1,42 @@
 // Vertex shader
 
+/// The basics, in `util`.
 struct UtilUniform {
+    // Seconds since the start.
     time: f32,
     res_width: f32,
     res_height: f32,
+    frame: u32,
+    // Shadertoy style mouse in pixels, see `iMouse`.
+    mouse: vec4<f32>,
+    // 1 on a beat, fading to 0 after.
+    beat: f32,
+    // The frequency at the right edge of the fft, in Hz.
+    max_freq: f32,
+    // Lowered from 1 when frames take too long, scale loop counts and such with it.
+    quality: f32,
 };
 
 @group(0) @binding(0)
 var<uniform> util: UtilUniform;
 
+/// Moved with the mouse and keyboard, see `//! camera` for how. In `camera`.
+struct CameraUniform {
+	eye: vec4<f32>,
+	look_at: vec4<f32>,
+	view_proj: mat4x4<f32>,
+	inv_view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(1)
+var<uniform> camera: CameraUniform;
+
+/// The direction of the camera ray through uv, for raymarching from `camera.eye`.
+fn camera_ray(uv: vec2<f32>) -> vec3<f32> {
+	let far = camera.inv_view_proj * vec4<f32>(uv * 2.0 - 1.0, 1.0, 1.0);
+	return normalize(far.xyz / far.w - camera.eye.xyz);
+}
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
 	@location(1) tex_coords: vec2<f32>,
@@ -38,31 +66,133 @@ var fft_buffer: texture_2d<f32>;
 @group(1) @binding(1)
 var fft_sampler: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+// Feedback, the previous frame and the output of the users buffer passes.
+// A pass sees the current frame of the passes before it and the previous frame of itself
+// and the passes after it, buffers that aren't declared by the shader are black.
+
+@group(2) @binding(0)
+var feedback_sampler: sampler;
+/// The previous frame of the image pass, sample it with `feedback_sample`.
+@group(2) @binding(1)
+var prev_frame: texture_2d<f32>;
+/// The buffer passes, black unless declared with `//! pass`.
+@group(2) @binding(2)
+var buffer_a: texture_2d<f32>;
+@group(2) @binding(3)
+var buffer_b: texture_2d<f32>;
+@group(2) @binding(4)
+var buffer_c: texture_2d<f32>;
+@group(2) @binding(5)
+var buffer_d: texture_2d<f32>;
+
+fn frag_uv(in: VertexOutput) -> vec2<f32> {
 	// Texture coords have 0,0 in top left and 1,1 in bottom right
 	// UV has 0,0 in bottom left and 1,1 in top right
+	return vec2<f32>(in.tex_coords.x, 1.0 - in.tex_coords.y);
+}
 
-    let uv = vec2<f32>(in.tex_coords.x, 1.0 - in.tex_coords.y);
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = frag_uv(in);
+	shadertoy_setup();
 	let frag = fs_user(uv);
 	let fade = 1.0;
 	return vec4<f32>(frag, fade);
 }
 
+/// The number of fft rows kept, the newest is row 0.
 fn time_steps() -> i32 {
 	return textureDimensions(fft_buffer).y;
 }
 
+/// The fft at `uvx` in [0, 1] of row `time_step`, normalized to about [0, 1].
+/// Nearest sampled, see `fft_linear` and `fft_hz` in prelude 2 for smoother results.
 fn fft_sample(uvx: f32, time_step: i32) -> f32 {
 	let time_steps = f32(time_steps());
 	let line = f32(time_step) / time_steps + 1.0 / time_steps / 2.0;
-    let fft_sample = textureSample(fft_buffer, fft_sampler, vec2<f32>(uvx, line)).r;
+    // Explicit level so it can be used from the particle compute stage as well.
+    let fft_sample = textureSampleLevel(fft_buffer, fft_sampler, vec2<f32>(uvx, line), 0.0).r;
 	return fft_sample;
 }
 
+// Shadertoy compatibility, shaders with `//! shadertoy` define
+// `fn mainImage(fragCoord: vec2<f32>) -> vec4<f32>` instead of `fs_user`.
+
+/// Shadertoy style audio, the fft on the first row and the wave on the second, 512 wide.
+@group(1) @binding(2)
+var iChannel0: texture_2d<f32>;
+@group(1) @binding(3)
+var iChannel0_sampler: sampler;
+
+var<private> iTime: f32;
+var<private> iResolution: vec3<f32>;
+var<private> iMouse: vec4<f32>;
+var<private> iFrame: i32;
+
+fn shadertoy_setup() {
+	iTime = util.time;
+	iResolution = vec3<f32>(util.res_width, util.res_height, 1.0);
+	iMouse = util.mouse;
+	iFrame = i32(util.frame);
+}
+
+/// Sample `prev_frame` or one of `buffer_a` to `buffer_d` at the same uv the passes use.
+fn feedback_sample(buffer: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+	return textureSample(buffer, feedback_sampler, vec2<f32>(uv.x, 1.0 - uv.y));
+}
+
+// Particles, shaders with `//! particles count=<n>` define
+// `fn cs_particle(index: u32, particle: Particle) -> Particle` which updates every particle
+// each frame. They are drawn as soft dots on top of the image pass.
+
+/// One of the `particles`, see `//! particles`.
+struct Particle {
+	// xy in uv space, w is the radius in uv units of the height.
+	position: vec4<f32>,
+	// Free for the shader to use, particles start out with it zeroed.
+	velocity: vec4<f32>,
+	// Added to the image, a is the intensity.
+	color: vec4<f32>,
+};
+
+/// Updated by `cs_particle` in shaders with `//! particles`.
+@group(3) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+
+struct ParticleVertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+	@location(0) corner: vec2<f32>,
+	@location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main_particle(
+	model: VertexInput,
+	@location(2) position: vec4<f32>,
+	@location(3) velocity: vec4<f32>,
+	@location(4) color: vec4<f32>,
+) -> ParticleVertexOutput {
+	var out: ParticleVertexOutput;
+	let aspect = util.res_width / util.res_height;
+	let center = position.xy * 2.0 - 1.0;
+	let offset = model.position.xy * position.w * 2.0 * vec2<f32>(1.0 / aspect, 1.0);
+	out.clip_position = vec4<f32>(center + offset, 0.0, 1.0);
+	out.corner = model.position.xy;
+	out.color = color;
+	return out;
+}
+
+@fragment
+fn fs_main_particle(in: ParticleVertexOutput) -> @location(0) vec4<f32> {
+	let fade = smoothstep(1.0, 0.0, length(in.corner));
+	return vec4<f32>(in.color.rgb * in.color.a * fade, 0.0);
+}
+
 // The users shader will be appended to this file.
 // Expect the user shader to define function
 // `fn fs_user(uv: vec2<f32>) -> vec3<f32>`
+// and `fn fs_buffer_a(uv: vec2<f32>) -> vec4<f32>` for every buffer pass it declares
+// with `//! pass buffer_a`.
 /*     let color = vec3<f32>(1.0, 0.0, 0.0); */
 /*     return vec4<f32>(ledColor, 1.0); */
 /* } */
@@ -0,0 +1,231 @@
+// Prelude 2, appended to the first one for shaders with `//! prelude 2`.
+// Shaders written against the first prelude often define these names themselves,
+// which is why they are only added on request.
+
+// Constants and math
+
+/// Half a turn in radians.
+const PI: f32 = 3.14159265358979;
+/// A full turn in radians.
+const TAU: f32 = 6.28318530717959;
+
+/// Maps `value` from [min_in, max_in] to [min_out, max_out], without clamping.
+fn map_range(value: f32, min_in: f32, max_in: f32, min_out: f32, max_out: f32) -> f32 {
+	return min_out + (value - min_in) * (max_out - min_out) / (max_in - min_in);
+}
+
+/// Rotates a 2d point counter clockwise by `angle` radians when multiplied with it.
+fn rotate2d(angle: f32) -> mat2x2<f32> {
+	let c = cos(angle);
+	let s = sin(angle);
+	return mat2x2<f32>(c, s, -s, c);
+}
+
+/// uv centered on the screen and scaled so y is in [-0.5, 0.5] and x keeps the aspect ratio.
+fn centered_uv(uv: vec2<f32>) -> vec2<f32> {
+	return (uv - 0.5) * vec2<f32>(util.res_width / util.res_height, 1.0);
+}
+
+// Audio
+
+/// The number of fft bins per row.
+fn fft_bins() -> i32 {
+	return textureDimensions(fft_buffer).x;
+}
+
+/// The frequency of the last bin in Hz, a quarter of the sample rate.
+fn fft_max_hz() -> f32 {
+	return util.max_freq;
+}
+
+/// Bin `bin` of row `time_step`, 0 outside of the spectrum. The rows are clamped.
+fn fft_bin(bin: i32, time_step: i32) -> f32 {
+	if bin < 0 || bin >= fft_bins() {
+		return 0.0;
+	}
+	let row = clamp(time_step, 0, time_steps() - 1);
+	return textureLoad(fft_buffer, vec2<i32>(bin, row), 0).r;
+}
+
+/// Linearly interpolates between the bins around the fractional bin `pos`.
+fn fft_interpolated(pos: f32, time_step: i32) -> f32 {
+	let bin = i32(floor(pos));
+	return mix(fft_bin(bin, time_step), fft_bin(bin + 1, time_step), pos - floor(pos));
+}
+
+/// `fft_sample` without the steps between bins.
+fn fft_linear(uvx: f32, time_step: i32) -> f32 {
+	return fft_interpolated(uvx * f32(fft_bins()) - 0.5, time_step);
+}
+
+/// The fft at `hz`, linearly interpolated, only right with linear binning.
+fn fft_hz(hz: f32, time_step: i32) -> f32 {
+	return fft_interpolated(hz / fft_max_hz() * f32(fft_bins()), time_step);
+}
+
+/// The average of the bins from `low_hz` to `high_hz`.
+fn fft_band(low_hz: f32, high_hz: f32, time_step: i32) -> f32 {
+	let bin_hz = fft_max_hz() / f32(fft_bins());
+	let first = clamp(i32(floor(low_hz / bin_hz)), 0, fft_bins() - 1);
+	let last = clamp(i32(ceil(high_hz / bin_hz)), first, fft_bins() - 1);
+	var sum = 0.0;
+	for (var bin = first; bin <= last; bin += 1) {
+		sum += fft_bin(bin, time_step);
+	}
+	return sum / f32(last - first + 1);
+}
+
+/// The newest 20 to 150 Hz.
+fn fft_bass() -> f32 {
+	return fft_band(20.0, 150.0, 0);
+}
+
+/// The newest 150 to 2000 Hz.
+fn fft_mids() -> f32 {
+	return fft_band(150.0, 2000.0, 0);
+}
+
+/// The newest 2000 Hz and up.
+fn fft_highs() -> f32 {
+	return fft_band(2000.0, fft_max_hz(), 0);
+}
+
+/// Blends the last `steps` rows at `uvx`, each row weighted `falloff` times the newer one.
+/// A falloff around 0.7 smooths out flicker without lagging much.
+fn fft_history(uvx: f32, steps: i32, falloff: f32) -> f32 {
+	var sum = 0.0;
+	var total = 0.0;
+	var weight = 1.0;
+	for (var step = 0; step < min(steps, time_steps()); step += 1) {
+		sum += fft_linear(uvx, step) * weight;
+		total += weight;
+		weight *= falloff;
+	}
+	return sum / max(total, 0.000001);
+}
+
+/// The newest wave at `x` in [0, 1], in [-1, 1].
+fn wave_sample(x: f32) -> f32 {
+	return textureSampleLevel(iChannel0, iChannel0_sampler, vec2<f32>(x, 0.75), 0.0).r * 2.0 - 1.0;
+}
+
+// Noise, hashes are from https://www.shadertoy.com/view/4djSRW
+
+/// Pseudo random in [0, 1).
+fn hash11(p: f32) -> f32 {
+	var q = fract(p * 0.1031);
+	q *= q + 33.33;
+	q *= q + q;
+	return fract(q);
+}
+
+/// Pseudo random in [0, 1).
+fn hash21(p: vec2<f32>) -> f32 {
+	var p3 = fract(vec3<f32>(p.xyx) * 0.1031);
+	p3 += dot(p3, p3.yzx + 33.33);
+	return fract((p3.x + p3.y) * p3.z);
+}
+
+/// Pseudo random in [0, 1).
+fn hash22(p: vec2<f32>) -> vec2<f32> {
+	var p3 = fract(vec3<f32>(p.xyx) * vec3<f32>(0.1031, 0.1030, 0.0973));
+	p3 += dot(p3, p3.yzx + 33.33);
+	return fract((p3.xx + p3.yz) * p3.zy);
+}
+
+/// Pseudo random in [0, 1).
+fn hash33(p: vec3<f32>) -> vec3<f32> {
+	var p3 = fract(p * vec3<f32>(0.1031, 0.1030, 0.0973));
+	p3 += dot(p3, p3.yxz + 33.33);
+	return fract((p3.xxy + p3.yxx) * p3.zyx);
+}
+
+/// Smooth value noise in [0, 1].
+fn value_noise(p: vec2<f32>) -> f32 {
+	let i = floor(p);
+	let f = fract(p);
+	let u = f * f * (3.0 - 2.0 * f);
+	return mix(
+		mix(hash21(i), hash21(i + vec2<f32>(1.0, 0.0)), u.x),
+		mix(hash21(i + vec2<f32>(0.0, 1.0)), hash21(i + vec2<f32>(1.0, 1.0)), u.x),
+		u.y
+	);
+}
+
+/// `octaves` of value noise, each at twice the frequency and half the amplitude, in [0, 1].
+fn fbm(p: vec2<f32>, octaves: i32) -> f32 {
+	var sum = 0.0;
+	var amplitude = 0.5;
+	var total = 0.0;
+	var q = p;
+	for (var i = 0; i < octaves; i += 1) {
+		sum += value_noise(q) * amplitude;
+		total += amplitude;
+		amplitude *= 0.5;
+		q = q * 2.0 + vec2<f32>(17.0, 31.0);
+	}
+	return sum / max(total, 0.000001);
+}
+
+// Signed distances, negative inside. From https://iquilezles.org/articles/distfunctions/
+
+/// A circle of radius `r` around the origin.
+fn sd_circle(p: vec2<f32>, r: f32) -> f32 {
+	return length(p) - r;
+}
+
+/// A rectangle with half size `b` around the origin.
+fn sd_box2(p: vec2<f32>, b: vec2<f32>) -> f32 {
+	let d = abs(p) - b;
+	return length(max(d, vec2<f32>(0.0))) + min(max(d.x, d.y), 0.0);
+}
+
+/// A line from `a` to `b`, add a thickness by subtracting it.
+fn sd_segment(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+	let pa = p - a;
+	let ba = b - a;
+	let h = clamp(dot(pa, ba) / dot(ba, ba), 0.0, 1.0);
+	return length(pa - ba * h);
+}
+
+/// A sphere of radius `r` around the origin.
+fn sd_sphere(p: vec3<f32>, r: f32) -> f32 {
+	return length(p) - r;
+}
+
+/// A box with half size `b` around the origin.
+fn sd_box3(p: vec3<f32>, b: vec3<f32>) -> f32 {
+	let q = abs(p) - b;
+	return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+/// A torus around the y axis, `t.x` is the ring radius and `t.y` the thickness.
+fn sd_torus(p: vec3<f32>, t: vec2<f32>) -> f32 {
+	let q = vec2<f32>(length(p.xz) - t.x, p.y);
+	return length(q) - t.y;
+}
+
+/// A union of two distances with the seam rounded off over `k`.
+fn op_smooth_union(a: f32, b: f32, k: f32) -> f32 {
+	let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+	return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+// Color
+
+/// The cosine palette from https://iquilezles.org/articles/palettes/
+fn palette(t: f32, a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec3<f32> {
+	return a + b * cos(TAU * (c * t + d));
+}
+
+/// Hue, saturation and value in [0, 1] to rgb.
+fn hsv_to_rgb(c: vec3<f32>) -> vec3<f32> {
+	let k = vec3<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0);
+	let p = abs(fract(c.xxx + k) * 6.0 - 3.0);
+	return c.z * mix(vec3<f32>(1.0), clamp(p - 1.0, vec3<f32>(0.0), vec3<f32>(1.0)), c.y);
+}
+
+/// Perceived brightness of a linear rgb color.
+fn luminance(c: vec3<f32>) -> f32 {
+	return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
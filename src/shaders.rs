@@ -1,60 +1,417 @@
 use std::{
-    ffi::OsStr,
-    fs::{self, FileType},
-    io,
-    path::PathBuf,
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
 };
 
-const PRELUDE: &str = include_str!("shader_prelude.wgsl");
+use anyhow::{anyhow, bail, Context, Result};
 
-pub fn list_shaders() -> Result<Vec<PathBuf>, io::Error> {
-    let paths = fs::read_dir("./shaders")?;
+use crate::particles;
+use crate::shader_meta::{self, Language, PassSlot, ShaderMeta};
+use crate::shadertoy;
 
+/// The preludes by version, a shader gets all of them up to the one it declares with
+/// `//! prelude <version>`. Later versions only add to the earlier ones.
+const PRELUDES: &[&str] = &[
+    include_str!("shader_prelude.wgsl"),
+    include_str!("shader_prelude_v2.wgsl"),
+];
+pub const LATEST_PRELUDE: usize = PRELUDES.len();
+
+/// Where shaders are listed from without `--shader-dir`.
+pub const SHADER_DIR: &str = "./shaders";
+
+/// Where includes are kept, not shaders to run on their own.
+pub const LIB_DIR: &str = "lib";
+
+/// The shaders in `dir` and its subdirectories, sorted by path.
+pub fn list_shaders(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
     let mut files: Vec<PathBuf> = vec![];
-    for path in paths {
-        let p = path?;
-        const ACCEPTED: &str = "wgsl";
-        if p.path().extension() != Some(OsStr::new(ACCEPTED)) {
-            continue;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !path.ends_with(LIB_DIR) {
+                files.extend(list_shaders(&path)?);
+            }
+        } else if Language::from_path(&path).is_some() {
+            files.push(path);
         }
-        files.push(p.path());
     }
+    files.sort();
     Ok(files)
 }
 
-pub fn make_pipeline(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    format: wgpu::TextureFormat,
-    shader: &PathBuf,
-) -> wgpu::RenderPipeline {
-    let user_src = fs::read_to_string(shader).expect("Should have been able to read the file");
+/// The shader `offset` places from `current` in the list, going around at the ends.
+/// Without `current` in the list it starts from the first or the last one.
+pub fn step<'a>(shaders: &'a [PathBuf], current: &Path, offset: isize) -> Option<&'a PathBuf> {
+    let count = shaders.len() as isize;
+    let index = match shaders.iter().position(|p| p == current) {
+        Some(index) => (index as isize + offset).rem_euclid(count.max(1)),
+        None if offset > 0 => 0,
+        None => count - 1,
+    };
+    shaders.get(usize::try_from(index).ok()?)
+}
 
-    let shader_src = PRELUDE.to_string() + &user_src;
+/// A built shader and where its lines came from.
+pub struct ShaderSource {
+    pub src: String,
+    pub map: SourceMap,
+}
+
+impl ShaderSource {
+    /// `at <file>:<line>` for an error at `location` in the built source, if it's known.
+    fn locate(&self, location: Option<naga::SourceLocation>) -> String {
+        location
+            .and_then(|l| self.map.lookup(l.line_number as usize))
+            .map(|(file, line)| format!("at {}:{}\n", file, line))
+            .unwrap_or_default()
+    }
+}
+
+/// Maps lines of a built shader back to the file they came from.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    // The first line in the built source, the file and the first line in the file, 0 based.
+    segments: Vec<(usize, String, usize)>,
+}
+
+impl SourceMap {
+    /// The file and line of `line` in the built source, lines are 1 based.
+    pub fn lookup(&self, line: usize) -> Option<(&str, usize)> {
+        let line = line.checked_sub(1)?;
+        let (start, file, first) = self.segments.iter().rev().find(|(s, ..)| *s <= line)?;
+        Some((file, line - start + first + 1))
+    }
+}
+
+struct SourceBuilder<'a> {
+    src: String,
+    lines: usize,
+    map: SourceMap,
+    /// The fallback for `#include`.
+    shader_dir: &'a Path,
+}
 
+impl<'a> SourceBuilder<'a> {
+    fn new(shader_dir: &'a Path) -> Self {
+        Self {
+            src: String::new(),
+            lines: 0,
+            map: SourceMap::default(),
+            shader_dir,
+        }
+    }
+
+    /// Appends `text`, which starts at the 0 based `line` of `file`.
+    fn push(&mut self, text: &str, file: &str, line: usize) {
+        if text.is_empty() {
+            return;
+        }
+        self.map.segments.push((self.lines, file.to_string(), line));
+        self.src += text;
+        if !text.ends_with('\n') {
+            self.src.push('\n');
+        }
+        self.lines += text.lines().count();
+    }
+
+    /// Appends `src` of `path` with every `#include "<file>"` line replaced by that file.
+    /// Files are looked up next to the including file and then in the shader directory,
+    /// each is only included once.
+    fn push_with_includes(
+        &mut self,
+        path: &Path,
+        src: &str,
+        stack: &mut Vec<PathBuf>,
+        included: &mut HashSet<PathBuf>,
+    ) -> Result<()> {
+        let file = path.display().to_string();
+        let mut chunk = String::new();
+        let mut chunk_start = 0;
+        for (line_nr, line) in src.lines().enumerate() {
+            let Some(include) = line.trim().strip_prefix("#include") else {
+                chunk += line;
+                chunk.push('\n');
+                continue;
+            };
+            self.push(&chunk, &file, chunk_start);
+            chunk.clear();
+            chunk_start = line_nr + 1;
+
+            let at = format!("{}:{}", file, line_nr + 1);
+            let name = include
+                .trim()
+                .strip_prefix('"')
+                .and_then(|n| n.strip_suffix('"'))
+                .with_context(|| format!("{}: expected #include \"<file>\"", at))?;
+            let resolved = resolve_include(path, name, self.shader_dir)
+                .with_context(|| format!("{}: can't find include \"{}\"", at, name))?;
+            if stack.contains(&resolved) {
+                let cycle: Vec<String> = stack
+                    .iter()
+                    .chain([&resolved])
+                    .map(|p| p.display().to_string())
+                    .collect();
+                bail!("{}: include cycle {}", at, cycle.join(" -> "));
+            }
+            if !included.insert(resolved.clone()) {
+                continue;
+            }
+
+            let include_src = fs::read_to_string(&resolved)
+                .with_context(|| format!("{}: can't read {}", at, resolved.display()))?;
+            stack.push(resolved.clone());
+            self.push_with_includes(&resolved, &include_src, stack, included)?;
+            stack.pop();
+        }
+        self.push(&chunk, &file, chunk_start);
+        Ok(())
+    }
+}
+
+fn resolve_include(from: &Path, name: &str, shader_dir: &Path) -> Option<PathBuf> {
+    let dir = from.parent().unwrap_or_else(|| Path::new("."));
+    [dir.join(name), shader_dir.join(name)]
+        .into_iter()
+        .find(|p| p.is_file())
+        .and_then(|p| p.canonicalize().ok())
+}
+
+/// The full source of a user shader: the prelude, the users code with its includes and
+/// the entry points for any passes it declared. Includes are looked up in `shader_dir` too.
+pub fn build_source(
+    shader: &Path,
+    user_src: &str,
+    meta: &ShaderMeta,
+    shader_dir: &Path,
+) -> Result<ShaderSource> {
+    if meta.language == Language::Glsl {
+        // The translation already reports errors in the users lines.
+        return Ok(ShaderSource {
+            src: shadertoy::translate_glsl(user_src)?,
+            map: SourceMap::default(),
+        });
+    }
+
+    let mut builder = SourceBuilder::new(shader_dir);
+    for (index, prelude) in PRELUDES[..meta.prelude].iter().enumerate() {
+        builder.push(prelude, &format!("prelude {}", index + 1), 0);
+    }
+    for (index, texture) in meta.textures.iter().enumerate() {
+        let binding = shader_meta::texture_binding(index);
+        builder.push(
+            &format!(
+                "@group(2) @binding({})\nvar {}: texture_2d<f32>;\n@group(2) @binding({})\nvar {}_sampler: sampler;\n",
+                binding,
+                texture.name,
+                binding + 1,
+                texture.name
+            ),
+            "generated",
+            0,
+        );
+    }
+
+    if !meta.params.is_empty() {
+        let fields: String = meta
+            .params
+            .iter()
+            .map(|param| format!("\t{}: f32,\n", param.name))
+            .collect();
+        builder.push(
+            &format!(
+                "struct Params {{\n{}}}\n@group(2) @binding({})\nvar<uniform> params: Params;\n",
+                fields,
+                shader_meta::PARAMS_BINDING
+            ),
+            "generated",
+            0,
+        );
+    }
+    let canonical = shader
+        .canonicalize()
+        .unwrap_or_else(|_| shader.to_path_buf());
+    let mut stack = vec![canonical.clone()];
+    let mut included = HashSet::from([canonical]);
+    builder.push_with_includes(shader, user_src, &mut stack, &mut included)?;
+
+    for pass in meta.passes.iter().filter(|p| p.slot != PassSlot::Image) {
+        // Expect the user shader to define `fn fs_<slot>(uv: vec2<f32>) -> vec4<f32>`
+        builder.push(
+            &format!(
+                "\n@fragment\nfn {}(in: VertexOutput) -> @location(0) vec4<f32> {{\n\tshadertoy_setup();\n\treturn fs_{}(frag_uv(in));\n}}\n",
+                meta.entry_point(pass.slot),
+                pass.slot.name()
+            ),
+            "generated",
+            0,
+        );
+    }
+    if meta.particles.is_some() {
+        // Expect the user shader to define `fn cs_particle(index: u32, particle: Particle) -> Particle`
+        builder.push(
+            &format!(
+                "\n@compute @workgroup_size({})\nfn {}(@builtin(global_invocation_id) id: vec3<u32>) {{\n\tif id.x >= arrayLength(&particles) {{\n\t\treturn;\n\t}}\n\tshadertoy_setup();\n\tparticles[id.x] = cs_particle(id.x, particles[id.x]);\n}}\n",
+                particles::WORKGROUP_SIZE,
+                particles::COMPUTE_ENTRY_POINT,
+            ),
+            "generated",
+            0,
+        );
+    }
+    if meta.shadertoy {
+        // Expect the user shader to define `fn mainImage(fragCoord: vec2<f32>) -> vec4<f32>`
+        builder.push(
+            "\nfn fs_user(uv: vec2<f32>) -> vec3<f32> {\n\treturn mainImage(uv * iResolution.xy).rgb;\n}\n",
+            "generated",
+            0,
+        );
+    }
+    Ok(ShaderSource {
+        src: builder.src,
+        map: builder.map,
+    })
+}
+
+/// A markdown reference of the prelude, generated from the `///` comments in front of
+/// declarations. Printed with `--prelude-docs`, which is how `PRELUDE.md` is made.
+pub fn prelude_docs() -> String {
+    let mut docs = String::from("# Prelude reference\n\nGenerated with `cargo run -- --prelude-docs > PRELUDE.md`, don't edit by hand.\nShaders get prelude 1 unless they declare another version with `//! prelude <version>`.\nColors are linear and can go above 1, the frame is tonemapped and sRGB encoded for the screen after the post effects.\n");
+    for (index, prelude) in PRELUDES.iter().enumerate() {
+        docs += &format!("\n## Prelude {}\n", index + 1);
+        let mut comment: Vec<&str> = vec![];
+        let mut lines = prelude.lines();
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if let Some(doc) = line.strip_prefix("///") {
+                comment.push(doc.trim());
+                continue;
+            }
+            if comment.is_empty() || line.starts_with('@') {
+                continue;
+            }
+            // Structs are shown whole, everything else up to the body.
+            let declaration = if line.starts_with("struct") {
+                let mut body = vec![line];
+                for field in lines.by_ref() {
+                    body.push(field);
+                    if field.starts_with('}') {
+                        break;
+                    }
+                }
+                body.join("\n")
+            } else {
+                line.trim_end_matches('{').trim_end().to_string()
+            };
+            docs += &format!("\n```wgsl\n{}\n```\n{}\n", declaration, comment.join("\n"));
+            comment.clear();
+        }
+    }
+    docs
+}
+
+/// Builds and validates the shader without a device, like loading it would.
+pub fn check(shader: &Path, shader_dir: &Path) -> Result<()> {
+    check_source(shader, &fs::read_to_string(shader)?, shader_dir)
+}
+
+/// Like `check`, with the source of the shader given instead of read from the file.
+pub fn check_source(shader: &Path, user_src: &str, shader_dir: &Path) -> Result<()> {
+    let language = Language::from_path(shader).context("Not a shader")?;
+    let meta = ShaderMeta::parse(user_src, language);
+    meta.check()?;
+    validate(&build_source(shader, user_src, &meta, shader_dir)?)
+}
+
+/// The 1 based lines of `shader` an error from building it points at,
+/// errors in the prelude or includes don't count.
+pub fn error_lines(shader: &Path, error: &str) -> Vec<usize> {
+    let file = shader.display().to_string();
+    let mut lines: Vec<usize> = error
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            // `at <file>:<line>` from naga, `<file>:<line>: ...` from the includes and
+            // `line <line>: ...` from the GLSL translation.
+            let number = line
+                .strip_prefix("at ")
+                .unwrap_or(line)
+                .strip_prefix(file.as_str())
+                .and_then(|rest| rest.strip_prefix(':'))
+                .or_else(|| line.strip_prefix("line "))?;
+            let end = number
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(number.len());
+            number[..end].parse().ok()
+        })
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+/// Validates the source before handing it to wgpu, which would panic on errors.
+pub fn make_module(
+    device: &wgpu::Device,
+    shader: &Path,
+    source: ShaderSource,
+) -> Result<wgpu::ShaderModule> {
+    validate(&source)?;
+
+    let name = shader
+        .file_name()
+        .context("The shader has no file name")?
+        .to_string_lossy();
     let desc = wgpu::ShaderModuleDescriptor {
-        label: Some(
-            shader
-                .file_name()
-                .expect("Shader file to have a filename")
-                .to_str()
-                .expect("Filename to be valid utf-8"),
-        ),
-        source: wgpu::ShaderSource::Wgsl(shader_src.into()),
+        label: Some(&name),
+        source: wgpu::ShaderSource::Wgsl(source.src.into()),
     };
-    let shader = device.create_shader_module(desc);
+    Ok(device.create_shader_module(desc))
+}
+
+fn validate(source: &ShaderSource) -> Result<()> {
+    let src = &source.src;
+    let module = naga::front::wgsl::parse_str(src).map_err(|e| {
+        anyhow!(
+            "{}{}",
+            source.locate(e.location(src)),
+            e.emit_to_string(src)
+        )
+    })?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|e| {
+        anyhow!(
+            "{}{}",
+            source.locate(e.location(src)),
+            e.emit_to_string(src)
+        )
+    })?;
+    Ok(())
+}
 
+pub fn make_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::RenderPipeline {
     let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
         layout: Some(layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main",
             buffers: &[Vertex::desc()],
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
+            module: shader,
+            entry_point,
             targets: &[Some(wgpu::ColorTargetState {
                 format,
                 blend: Some(wgpu::BlendState {
@@ -139,3 +496,95 @@ pub const VERTICES: &[Vertex] = &[
 ];
 
 pub const INDICES: &[u16] = &[0, 2, 1, 1, 2, 3];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for the files of one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shaders_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    fn build(path: &Path, shader_dir: &Path) -> Result<ShaderSource> {
+        let src = fs::read_to_string(path)?;
+        let meta = ShaderMeta::parse(&src, Language::Wgsl);
+        build_source(path, &src, &meta, shader_dir)
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = test_dir("cycle");
+        fs::write(dir.join("a.wgsl"), "#include \"b.wgsl\"\n").unwrap();
+        fs::write(dir.join("b.wgsl"), "\n#include \"a.wgsl\"\n").unwrap();
+        let error = build(&dir.join("a.wgsl"), &dir).err().unwrap().to_string();
+        assert!(error.contains("include cycle"), "{}", error);
+        assert!(error.contains("b.wgsl:2"), "{}", error);
+    }
+
+    #[test]
+    fn duplicate_include() {
+        let dir = test_dir("duplicate");
+        fs::write(
+            dir.join("lib/x.wgsl"),
+            "fn x() -> f32 {\n\treturn 1.0;\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/y.wgsl"),
+            "#include \"x.wgsl\"\nfn y() -> f32 {\n\treturn x();\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.wgsl"),
+            "#include \"lib/x.wgsl\"\n#include \"lib/y.wgsl\"\n#include \"lib/x.wgsl\"\nfn fs_user(uv: vec2<f32>) -> vec3<f32> {\n\treturn vec3<f32>(y());\n}\n",
+        )
+        .unwrap();
+        let built = build(&dir.join("main.wgsl"), &dir).unwrap();
+        assert_eq!(built.src.matches("fn x()").count(), 1);
+        validate(&built).unwrap();
+    }
+
+    #[test]
+    fn include_from_shader_dir() {
+        let dir = test_dir("shader_dir");
+        let other = test_dir("shader_dir_other");
+        fs::write(
+            dir.join("lib/x.wgsl"),
+            "fn x() -> f32 {\n\treturn 1.0;\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            other.join("main.wgsl"),
+            "#include \"lib/x.wgsl\"\nfn fs_user(uv: vec2<f32>) -> vec3<f32> {\n\treturn vec3<f32>(x());\n}\n",
+        )
+        .unwrap();
+        assert!(build(&other.join("main.wgsl"), &dir).is_ok());
+        assert!(build(&other.join("main.wgsl"), &other).is_err());
+    }
+
+    #[test]
+    fn error_line() {
+        let dir = test_dir("error_line");
+        fs::write(
+            dir.join("lib/x.wgsl"),
+            "fn x() -> f32 {\n\treturn 1.0;\n}\n",
+        )
+        .unwrap();
+        let path = dir.join("main.wgsl");
+        let src = "#include \"lib/x.wgsl\"\nfn fs_user(uv: vec2<f32>) -> vec3<f32> {\n\treturn vec3<f32>(x() + oops);\n}\n";
+        let error = format!("{:#}", check_source(&path, src, &dir).unwrap_err());
+        assert_eq!(error_lines(&path, &error), vec![3], "{}", error);
+
+        let src = "#include \"lib/nope.wgsl\"\n";
+        let error = format!("{:#}", check_source(&path, src, &dir).unwrap_err());
+        assert_eq!(error_lines(&path, &error), vec![1], "{}", error);
+
+        let src = "//! pass nope\n\n//! wobble\nfn fs_user(uv: vec2<f32>) -> vec3<f32> {\n\treturn vec3<f32>(0.0);\n}\n";
+        let error = format!("{:#}", check_source(&path, src, &dir).unwrap_err());
+        assert_eq!(error_lines(&path, &error), vec![1, 3], "{}", error);
+    }
+}
@@ -0,0 +1,176 @@
+use std::num::NonZeroU32;
+
+use anyhow::*;
+
+/// Shadertoy's audio input is 512x2, the spectrum on the first row and the wave on the second.
+pub const AUDIO_WIDTH: u32 = 512;
+pub const AUDIO_HEIGHT: u32 = 2;
+
+/// Declares what the Shadertoy code expects on top of our bindings, see the prelude for the
+/// WGSL version. The bindings must match the ones in `Renderer`.
+const GLSL_HEADER: &str = r#"#version 450
+layout(set = 0, binding = 0) uniform UtilUniform {
+    float time;
+    float res_width;
+    float res_height;
+    uint frame;
+    vec4 mouse;
+    float beat;
+    float max_freq;
+    float quality;
+} util;
+layout(set = 1, binding = 2) uniform texture2D shadertoy_audio;
+layout(set = 1, binding = 3) uniform sampler shadertoy_audio_sampler;
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 out_color;
+#define iTime util.time
+#define iResolution vec3(util.res_width, util.res_height, 1.0)
+#define iFrame int(util.frame)
+#define iMouse util.mouse
+#define iChannel0 sampler2D(shadertoy_audio, shadertoy_audio_sampler)
+"#;
+
+const GLSL_FOOTER: &str = r#"
+void main() {
+    vec2 uv = vec2(v_tex_coords.x, 1.0 - v_tex_coords.y);
+    mainImage(out_color, uv * iResolution.xy);
+    // Shadertoy shows colors as they are, which is sRGB, the frame is linear.
+    out_color = vec4(pow(max(out_color.rgb, vec3(0.0)), vec3(2.2)), 1.0);
+}
+"#;
+
+/// The translated fragment shader can't use the prelude, so it gets its own vertex stage.
+const WGSL_VERTEX: &str = r#"
+struct ShadertoyVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) tex_coords: vec2<f32>) -> ShadertoyVertexOutput {
+    var out: ShadertoyVertexOutput;
+    out.tex_coords = tex_coords;
+    out.clip_position = vec4<f32>(position, 1.0);
+    return out;
+}
+"#;
+
+/// The fragment entry point of translated GLSL shaders.
+pub const GLSL_ENTRY_POINT: &str = "main";
+
+/// Translates pasted Shadertoy GLSL, which defines
+/// `void mainImage(out vec4 fragColor, in vec2 fragCoord)`, to a complete WGSL shader.
+pub fn translate_glsl(user_src: &str) -> Result<String> {
+    let src = format!("{}{}{}", GLSL_HEADER, user_src, GLSL_FOOTER);
+
+    let mut parser = naga::front::glsl::Parser::default();
+    let options = naga::front::glsl::Options::from(naga::ShaderStage::Fragment);
+    let module = parser.parse(&options, &src).map_err(|errors| {
+        let header_lines = GLSL_HEADER.matches('\n').count();
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| match e.meta.to_range() {
+                Some(range) => {
+                    let line = src[..range.start].matches('\n').count() + 1;
+                    format!("line {}: {}", line.saturating_sub(header_lines), e.kind)
+                }
+                None => e.kind.to_string(),
+            })
+            .collect();
+        anyhow!(messages.join("\n"))
+    })?;
+
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .context("Translated shader is invalid")?;
+
+    let wgsl =
+        naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())?;
+    Ok(wgsl + WGSL_VERTEX)
+}
+
+/// `iChannel0`, the fft and wave resampled to Shadertoy's layout.
+pub struct ShadertoyAudio {
+    data: Vec<u8>,
+    pub size: wgpu::Extent3d,
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl ShadertoyAudio {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let size = wgpu::Extent3d {
+            width: AUDIO_WIDTH,
+            height: AUDIO_HEIGHT,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadertoy_audio"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Shadertoy uses 8 bits too, and this way we get linear filtering.
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadertoy_audio_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            data: vec![0; (AUDIO_WIDTH * AUDIO_HEIGHT) as usize],
+            size,
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// `fft` is the newest row of the fft texture, normalized to [0;1] and `wave` the raw samples.
+    pub fn update(&mut self, queue: &wgpu::Queue, fft: &[f32], wave: &[f32]) {
+        let width = AUDIO_WIDTH as usize;
+        let (fft_row, wave_row) = self.data.split_at_mut(width);
+
+        // Stretch the spectrum over the row, both cover 0 to a quarter of the sample rate.
+        for (x, out) in fft_row.iter_mut().enumerate() {
+            let pos = x as f32 / width as f32 * (fft.len() - 1) as f32;
+            let i = pos as usize;
+            let next = (i + 1).min(fft.len() - 1);
+            let v = fft[i] + (fft[next] - fft[i]) * pos.fract();
+            *out = (v.clamp(0., 1.) * 255.) as u8;
+        }
+        for (out, &sample) in wave_row.iter_mut().zip(wave) {
+            *out = ((0.5 + 0.5 * sample).clamp(0., 1.) * 255.) as u8;
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &self.data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(AUDIO_WIDTH),
+                rows_per_image: NonZeroU32::new(AUDIO_HEIGHT),
+            },
+            self.size,
+        );
+    }
+}
@@ -1,16 +1,109 @@
 use std::time::Duration;
 use std::time::Instant;
 
-use winit::event_loop::EventLoop;
-use winit::window::{Window, WindowBuilder};
+use serde::{Deserialize, Serialize};
+
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::event_loop::{EventLoop, EventLoopWindowTarget};
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Window, WindowBuilder, WindowLevel};
 
 use crate::fft_buffer::FFTDimensions;
+use crate::settings::Settings;
+
+/// Whether and how the window covers a monitor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayMode {
+    #[default]
+    Windowed,
+    /// A borderless window the size of the monitor, quick to switch in and out of.
+    Borderless,
+    /// Takes over the monitor at its largest video mode.
+    Exclusive,
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 3] = [
+        DisplayMode::Windowed,
+        DisplayMode::Borderless,
+        DisplayMode::Exclusive,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayMode::Windowed => "Windowed",
+            DisplayMode::Borderless => "Borderless",
+            DisplayMode::Exclusive => "Exclusive",
+        }
+    }
+}
+
+/// The present modes worth picking from, the rest are variations of these.
+pub const PRESENT_MODES: [wgpu::PresentMode; 4] = [
+    wgpu::PresentMode::Fifo,
+    wgpu::PresentMode::FifoRelaxed,
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+];
+
+pub fn present_mode_name(mode: wgpu::PresentMode) -> &'static str {
+    match mode {
+        wgpu::PresentMode::Fifo => "fifo",
+        wgpu::PresentMode::FifoRelaxed => "fifo_relaxed",
+        wgpu::PresentMode::Mailbox => "mailbox",
+        wgpu::PresentMode::Immediate => "immediate",
+        wgpu::PresentMode::AutoVsync => "auto_vsync",
+        wgpu::PresentMode::AutoNoVsync => "auto_no_vsync",
+    }
+}
+
+/// How the window and surface start out, from the settings file with the command line over it,
+/// see `Settings::display`.
+#[derive(Clone, Debug)]
+pub struct DisplaySettings {
+    /// Falls back to fifo where it isn't supported.
+    pub present_mode: wgpu::PresentMode,
+    pub mode: DisplayMode,
+    pub always_on_top: bool,
+    /// A see-through window for overlaying the desktop, black becomes transparent.
+    /// The window can only be made transparent when it's created.
+    pub transparent: bool,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            present_mode: wgpu::PresentMode::Fifo,
+            mode: DisplayMode::Windowed,
+            always_on_top: false,
+            transparent: false,
+        }
+    }
+}
 
 pub struct State {
     pub window: Window,
+    pub display: DisplaySettings,
+    /// The monitor to go fullscreen on, an index into `monitors()`.
+    pub monitor: usize,
+    // What the fullscreen hotkey switches to.
+    last_fullscreen: DisplayMode,
+    // Output windows to open, they need the event loop.
+    output_requests: usize,
     pub fft_dimensions: FFTDimensions,
     time: Instant,
     frame_timer: Instant,
+    /// How long the last frame took, from update to update.
+    pub frame_time: Duration,
+    /// Frames rendered since the start.
+    pub frame: u32,
+
+    /// Shadertoy style mouse, xy is the position while the left button is held
+    /// and zw where it was clicked, negated once it's released. In pixels from the bottom left.
+    pub mouse: glam::Vec4,
+    cursor: glam::Vec2,
+    mouse_down: bool,
 
     // FPS Meter
     fps_timer: Instant,
@@ -18,23 +111,164 @@ pub struct State {
 }
 
 impl State {
-    pub fn new(event_loop: &EventLoop<()>) -> Self {
+    pub fn new(event_loop: &EventLoop<()>, settings: &Settings) -> Self {
+        let display = settings.display();
         let window = WindowBuilder::new()
-            .with_transparent(false)
+            .with_inner_size(winit::dpi::PhysicalSize::new(
+                settings.window.width.max(1),
+                settings.window.height.max(1),
+            ))
+            .with_transparent(display.transparent)
             .build(event_loop)
             .unwrap();
         let time = Instant::now();
-        let fft_dimensions = FFTDimensions::default();
+        let fft_dimensions = settings.analysis.dimensions();
         let fps_timer = Instant::now();
         let frame_timer = Instant::now();
 
-        Self {
+        let mut state = Self {
             time,
             fft_dimensions,
             window,
+            display: display.clone(),
+            monitor: 0,
+            last_fullscreen: DisplayMode::Borderless,
+            output_requests: 0,
             frame_timer,
+            frame_time: Duration::ZERO,
+            frame: 0,
+            mouse: glam::Vec4::ZERO,
+            cursor: glam::Vec2::ZERO,
+            mouse_down: false,
             fps_timer,
             delayed_fps: 60,
+        };
+        state.monitor = if settings.window.monitor < state.monitors().len() {
+            settings.window.monitor
+        } else {
+            state.current_monitor()
+        };
+        state.set_display_mode(display.mode);
+        state.set_always_on_top(display.always_on_top);
+        state
+    }
+
+    pub fn monitors(&self) -> Vec<MonitorHandle> {
+        self.window.available_monitors().collect()
+    }
+
+    /// The index of the monitor the window is mostly on.
+    fn current_monitor(&self) -> usize {
+        let current = self.window.current_monitor();
+        self.monitors()
+            .iter()
+            .position(|m| Some(m) == current.as_ref())
+            .unwrap_or(0)
+    }
+
+    /// Goes fullscreen on `self.monitor`, or back to a window.
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        let monitor = self.monitors().into_iter().nth(self.monitor);
+        let fullscreen = match mode {
+            DisplayMode::Windowed => None,
+            DisplayMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            DisplayMode::Exclusive => {
+                let video_mode = monitor.and_then(|m| {
+                    m.video_modes().max_by_key(|v| {
+                        (
+                            v.size().width * v.size().height,
+                            v.refresh_rate_millihertz(),
+                        )
+                    })
+                });
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        eprintln!("No video modes to go exclusive fullscreen with");
+                        Some(Fullscreen::Borderless(None))
+                    }
+                }
+            }
+        };
+        self.window.set_fullscreen(fullscreen);
+        if mode != DisplayMode::Windowed {
+            self.last_fullscreen = mode;
+        }
+        self.display.mode = mode;
+    }
+
+    /// Switches between a window and the last fullscreen mode, on the monitor the window is on.
+    pub fn toggle_fullscreen(&mut self) {
+        if self.display.mode == DisplayMode::Windowed {
+            self.monitor = self.current_monitor();
+            self.set_display_mode(self.last_fullscreen);
+        } else {
+            self.set_display_mode(DisplayMode::Windowed);
+        }
+    }
+
+    pub fn set_always_on_top(&mut self, always_on_top: bool) {
+        self.window.set_window_level(if always_on_top {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        });
+        self.display.always_on_top = always_on_top;
+    }
+
+    /// Opens an output window once the event loop gets to it, see `build_outputs`.
+    pub fn request_output(&mut self) {
+        self.output_requests += 1;
+    }
+
+    /// Builds the requested output windows, on another monitor than this window if there is one.
+    pub fn build_outputs(&mut self, target: &EventLoopWindowTarget<()>) -> Vec<Window> {
+        let current = self.window.current_monitor();
+        let monitor = self
+            .monitors()
+            .into_iter()
+            .find(|m| Some(m) != current.as_ref());
+        let requests = std::mem::take(&mut self.output_requests);
+        (0..requests)
+            .filter_map(|_| {
+                let mut builder = WindowBuilder::new().with_title("Output");
+                if let Some(monitor) = &monitor {
+                    builder = builder.with_position(monitor.position());
+                }
+                builder
+                    .build(target)
+                    .map_err(|e| eprintln!("Failed to open an output window: {}", e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Tracks the mouse, this never captures the event.
+    pub fn input(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let height = self.window.inner_size().height as f32;
+                self.cursor = glam::vec2(position.x as f32, height - position.y as f32);
+                if self.mouse_down {
+                    self.mouse.x = self.cursor.x;
+                    self.mouse.y = self.cursor.y;
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.mouse_down = *state == ElementState::Pressed;
+                if self.mouse_down {
+                    self.mouse =
+                        glam::vec4(self.cursor.x, self.cursor.y, self.cursor.x, self.cursor.y);
+                } else {
+                    self.mouse.z = -self.mouse.z.abs();
+                    self.mouse.w = -self.mouse.w.abs();
+                }
+            }
+            _ => {}
         }
     }
 
@@ -48,7 +282,9 @@ impl State {
             self.fps_timer = Instant::now();
             self.delayed_fps = self.get_fps();
         }
+        self.frame_time = self.frame_timer.elapsed();
         self.frame_timer = Instant::now();
+        self.frame = self.frame.wrapping_add(1);
     }
 
     pub fn get_fps(&self) -> u64 {
@@ -1,15 +1,25 @@
 use std::num::NonZeroU32;
+use std::{fs, path::Path, path::PathBuf};
 
 use anyhow::*;
-use image::GenericImageView;
+
+use crate::shader_meta::TextureDesc;
+
+/// `//! texture` paths are relative to this.
+pub const TEXTURE_DIR: &str = "./textures";
+/// Frame sequences are all decoded up front, this keeps them from taking all the memory.
+const MAX_FRAMES: usize = 1024;
+const MAX_SEQUENCE_BYTES: u64 = 512 * 1024 * 1024;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub size: wgpu::Extent3d,
 }
 
 impl Texture {
+    #[allow(dead_code)]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -17,7 +27,14 @@ impl Texture {
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(
+            device,
+            queue,
+            &img,
+            Some(label),
+            wgpu::FilterMode::Nearest,
+            wgpu::AddressMode::ClampToEdge,
+        )
     }
 
     pub fn from_image(
@@ -25,9 +42,34 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        filter: wgpu::FilterMode,
+        address_mode: wgpu::AddressMode,
+    ) -> Result<Self> {
+        Self::from_image_with_format(
+            device,
+            queue,
+            img,
+            label,
+            filter,
+            address_mode,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    /// `format` has to be one of the 8 bit rgba formats.
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        filter: wgpu::FilterMode,
+        address_mode: wgpu::AddressMode,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
-        let rgba = img.as_rgba8().unwrap();
-        let dimensions = img.dimensions();
+        check_size(device, img.width(), img.height())?;
+        // Not every image is rgba, jpegs usually aren't.
+        let rgba = img.to_rgba8();
+        let dimensions = rgba.dimensions();
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -40,42 +82,174 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture = Self {
+            texture,
+            view,
+            sampler,
+            size,
+        };
+        texture.write(queue, &rgba);
+        Ok(texture)
+    }
+
+    /// Replaces the contents, `rgba` has to be the same size as the texture.
+    pub fn write(&self, queue: &wgpu::Queue, rgba: &image::RgbaImage) {
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
             rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: NonZeroU32::new(dimensions.1),
+                bytes_per_row: NonZeroU32::new(4 * self.size.width),
+                rows_per_image: NonZeroU32::new(self.size.height),
             },
-            size,
+            self.size,
         );
+    }
+}
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+/// A `//! texture` input of a shader, either a single image or
+/// a directory of frames that is played back in a loop.
+pub struct UserTexture {
+    pub texture: Texture,
+    // Decoded up front, so only the upload happens while playing.
+    frames: Vec<image::RgbaImage>,
+    fps: f32,
+    current: usize,
+}
+
+impl UserTexture {
+    pub fn load(device: &wgpu::Device, queue: &wgpu::Queue, desc: &TextureDesc) -> Result<Self> {
+        let path = Path::new(TEXTURE_DIR).join(&desc.path);
+        let frames = if path.is_dir() {
+            let mut paths: Vec<PathBuf> = fs::read_dir(&path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| image::ImageFormat::from_path(p).is_ok())
+                .collect();
+            // Frame sequences are numbered, so this is the playback order.
+            paths.sort();
+            check_sequence(device, &path, &paths)?;
+            paths
+                .iter()
+                .map(|p| load_rgba(p))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            let (width, height) = image::image_dimensions(&path)
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+            check_size(device, width, height)
+                .with_context(|| format!("Can't use {}", path.display()))?;
+            vec![load_rgba(&path)?]
+        };
+
+        let first = frames
+            .first()
+            .with_context(|| format!("{} has no images", path.display()))?;
+        ensure!(
+            frames.iter().all(|f| f.dimensions() == first.dimensions()),
+            "The frames in {} aren't all the same size",
+            path.display()
+        );
 
+        let texture = Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(first.clone()),
+            Some(&desc.name),
+            desc.filter,
+            desc.address_mode,
+        )?;
         Ok(Self {
             texture,
-            view,
-            sampler,
+            frames,
+            fps: desc.fps,
+            current: 0,
         })
     }
+
+    /// Uploads the frame for `time`, in seconds.
+    pub fn update(&mut self, queue: &wgpu::Queue, time: f32) {
+        if self.frames.len() < 2 {
+            return;
+        }
+        let frame = (time * self.fps) as usize % self.frames.len();
+        if frame != self.current {
+            self.current = frame;
+            self.texture.write(queue, &self.frames[frame]);
+        }
+    }
+}
+
+fn load_rgba(path: &Path) -> Result<image::RgbaImage> {
+    let img = image::open(path).with_context(|| format!("Failed to load {}", path.display()))?;
+    Ok(img.to_rgba8())
+}
+
+/// wgpu panics on textures over the limit instead of returning an error.
+pub fn check_size(device: &wgpu::Device, width: u32, height: u32) -> Result<()> {
+    let max = device.limits().max_texture_dimension_2d;
+    ensure!(
+        width > 0 && height > 0 && width <= max && height <= max,
+        "the image is {}x{}, this device only does up to {}x{}",
+        width,
+        height,
+        max,
+        max
+    );
+    Ok(())
+}
+
+/// Looks at the headers only, before any of the frames are decoded.
+fn check_sequence(device: &wgpu::Device, dir: &Path, paths: &[PathBuf]) -> Result<()> {
+    ensure!(
+        paths.len() <= MAX_FRAMES,
+        "{} has {} frames, at most {} are supported",
+        dir.display(),
+        paths.len(),
+        MAX_FRAMES
+    );
+    let Some(first) = paths.first() else {
+        return Ok(());
+    };
+    let dimensions = image::image_dimensions(first)
+        .with_context(|| format!("Failed to load {}", first.display()))?;
+    check_size(device, dimensions.0, dimensions.1)
+        .with_context(|| format!("Can't use {}", first.display()))?;
+    for path in &paths[1..] {
+        let other = image::image_dimensions(path)
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+        ensure!(
+            other == dimensions,
+            "The frames in {} aren't all the same size",
+            dir.display()
+        );
+    }
+    let bytes = dimensions.0 as u64 * dimensions.1 as u64 * 4 * paths.len() as u64;
+    ensure!(
+        bytes <= MAX_SEQUENCE_BYTES,
+        "{} takes {} MB decoded, at most {} MB is supported",
+        dir.display(),
+        bytes / (1024 * 1024),
+        MAX_SEQUENCE_BYTES / (1024 * 1024)
+    );
+    Ok(())
 }
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::*;
+use image::codecs::gif::{GifEncoder, Repeat};
+
+use crate::audio_processor::{read_wav, Analysis, Analyzer, Beat, Wav};
+use crate::fft_buffer::FFTDimensions;
+use crate::preset::{LayerPreset, Preset, Presets, Transition};
+use crate::renderer::{FrameInput, Renderer};
+use crate::settings::Settings;
+use crate::shaders;
+
+/// Where thumbnails are kept, `shaders/` and `presets/` in here.
+pub const THUMBNAIL_DIR: &str = "thumbnails";
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 144;
+/// How long every shader runs before the thumbnail is taken.
+const SECONDS: f32 = 4.0;
+const FPS: u32 = 30;
+/// Every how many frames the animated preview takes one.
+const PREVIEW_EVERY: u32 = 3;
+
+/// The thumbnail of a shader or preset file in `dir`, a png. The animated preview is a gif
+/// next to it. Kept by the path in `dir`, like `thumbnails/shaders/tunnels/warp.wgsl.png`,
+/// so shaders with the same name in different directories get their own.
+pub fn thumbnail_path(source: &Path, dir: &Path) -> PathBuf {
+    let kind = match source.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => "presets",
+        _ => "shaders",
+    };
+    let relative = source
+        .strip_prefix(dir)
+        .unwrap_or_else(|_| Path::new(source.file_name().unwrap_or_default()));
+    let mut path = Path::new(THUMBNAIL_DIR)
+        .join(kind)
+        .join(relative)
+        .into_os_string();
+    path.push(".png");
+    PathBuf::from(path)
+}
+
+/// Renders the shaders and presets that are newer than their thumbnails, without a window.
+/// Every one runs for a few seconds against the audio file from the settings,
+/// or a made up beat without one.
+pub async fn generate(settings: &Settings, animated: bool) -> Result<()> {
+    let clip = match &settings.audio.file {
+        Some(path) => {
+            read_wav(path).with_context(|| format!("Failed to read {}", path.display()))?
+        }
+        None => reference_clip(),
+    };
+    ensure!(!clip.samples.is_empty(), "The audio clip is empty");
+    let dimensions = settings.analysis.dimensions();
+    let size = winit::dpi::PhysicalSize::new(WIDTH, HEIGHT);
+    let mut renderer = Renderer::headless(size, dimensions, settings).await?;
+    renderer.set_render_scale(1.0);
+    // Keeps every frame of the preview, however long rendering takes.
+    renderer.capture.seconds = 3600.0;
+
+    let mut sources = shaders::list_shaders(&settings.ui.shader_dir).unwrap_or_default();
+    sources.extend(Presets::new(&settings.ui.preset_dir).list);
+    for source in sources {
+        let is_preset = source.extension().is_some_and(|ext| ext == "toml");
+        let dir = if is_preset {
+            &settings.ui.preset_dir
+        } else {
+            &settings.ui.shader_dir
+        };
+        let thumbnail = thumbnail_path(&source, dir);
+        let preset = if is_preset {
+            match Preset::load(&source) {
+                std::result::Result::Ok(preset) => preset,
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    continue;
+                }
+            }
+        } else {
+            shader_preset(&source)
+        };
+        if !is_stale(&thumbnail, &source, &preset) {
+            continue;
+        }
+        if !renderer.load_preset(&Preset {
+            transition: Transition::Cut,
+            ..preset.clone()
+        }) {
+            continue;
+        }
+        if !is_preset {
+            renderer.set_post(renderer.layers[0].program.post.clone());
+        }
+        if let Some(dir) = thumbnail.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        println!("Rendering {}", source.display());
+        run(
+            &mut renderer,
+            &clip,
+            dimensions,
+            preset.analysis,
+            &thumbnail,
+            animated,
+        );
+        if animated {
+            let preview = thumbnail.with_extension("gif");
+            if let Err(e) = save_gif(&preview, renderer.capture.take_recording()) {
+                eprintln!("Failed to save {}: {:#}", preview.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A preset with just the shader.
+fn shader_preset(shader: &Path) -> Preset {
+    Preset {
+        layers: vec![LayerPreset {
+            shader: shader.to_path_buf(),
+            ..LayerPreset::default()
+        }],
+        ..Preset::default()
+    }
+}
+
+/// Whether the thumbnail is older than the source or one of the shaders it uses.
+fn is_stale(thumbnail: &Path, source: &Path, preset: &Preset) -> bool {
+    let Some(made) = modified(thumbnail) else {
+        return true;
+    };
+    std::iter::once(source)
+        .chain(preset.layers.iter().map(|layer| layer.shader.as_path()))
+        .any(|path| modified(path).is_some_and(|changed| changed > made))
+}
+
+/// Steps through the clip as fast as the gpu goes, at a fixed frame rate.
+fn run(
+    renderer: &mut Renderer,
+    clip: &Wav,
+    dimensions: FFTDimensions,
+    analysis: Analysis,
+    thumbnail: &Path,
+    animated: bool,
+) {
+    let fft_size = dimensions.fft_size;
+    let mut analyzer = Analyzer::new(dimensions, clip.sample_rate);
+    let mut texture = vec![0.; dimensions.texture_size()];
+    let mut wave = vec![0.; fft_size];
+    let mut runs = 0;
+    let start = Instant::now();
+    let mut beat = Beat::default();
+
+    let frames = SECONDS as u32 * FPS;
+    for frame in 0..frames {
+        let time = frame as f32 / FPS as f32;
+        // The fft runs that would have happened by now.
+        while (runs * fft_size) as f32 / clip.sample_rate as f32 <= time {
+            for (i, sample) in wave.iter_mut().enumerate() {
+                *sample = clip.samples[(runs * fft_size + i) % clip.samples.len()];
+            }
+            if analyzer.process(&wave) {
+                let at = (runs * fft_size) as f32 / clip.sample_rate as f32;
+                beat.hit(start + Duration::from_secs_f32(at));
+            }
+            analyzer.write_texture(&mut texture, analysis);
+            runs += 1;
+        }
+
+        let now = start + Duration::from_secs_f32(time);
+        renderer.update_offline(
+            FrameInput {
+                time,
+                frame,
+                mouse: glam::Vec4::ZERO,
+                beat: beat.pulse_at(now),
+                sample_rate: clip.sample_rate,
+            },
+            &texture,
+            &wave,
+        );
+        let last = frame + 1 == frames;
+        if last {
+            renderer.capture.screenshot_to(thumbnail.to_path_buf());
+        }
+        let preview = animated && frame % PREVIEW_EVERY == 0;
+        if preview {
+            renderer.capture.record_next();
+        }
+        renderer.render_headless();
+        if last || preview {
+            renderer.capture.finish(&renderer.device);
+        }
+    }
+}
+
+fn save_gif(path: &Path, frames: Vec<image::RgbaImage>) -> Result<()> {
+    let file = fs::File::create(path)?;
+    let mut encoder = GifEncoder::new(file);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = image::Delay::from_numer_denom_ms(1000 * PREVIEW_EVERY, FPS);
+    encoder.encode_frames(
+        frames
+            .into_iter()
+            .map(|frame| image::Frame::from_parts(frame, 0, 0, delay)),
+    )?;
+    Ok(())
+}
+
+/// Four seconds at 120 bpm, a kick on the beat, a hat in between and a chord over it,
+/// so there's something going on in the lows, mids and highs.
+fn reference_clip() -> Wav {
+    const SAMPLE_RATE: u32 = 44100;
+    const BEAT: f32 = 0.5;
+    let chord = [220.0, 277.18, 329.63];
+    let samples = (0..SAMPLE_RATE * SECONDS as u32)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let since_beat = t % BEAT;
+            let kick = (TAU * 55.0 * since_beat).sin() * (-since_beat * 12.0).exp();
+            let since_hat = (t + BEAT / 2.0) % BEAT;
+            // A cheap hash for the noise, the same every time.
+            let noise = ((i as f32 * 12.9898).sin() * 43758.547).fract() * 2.0 - 1.0;
+            let hat = noise * (-since_hat * 60.0).exp() * 0.3;
+            let pad = chord.iter().map(|f| (TAU * f * t).sin()).sum::<f32>() * 0.1;
+            (kick * 0.8 + hat + pad).clamp(-1.0, 1.0)
+        })
+        .collect();
+    Wav {
+        sample_rate: SAMPLE_RATE,
+        samples,
+    }
+}
+
+/// When the file was last written, to notice a thumbnail has been redone.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The thumbnails as egui textures for the browsers, loaded again when they're redone.
+#[derive(Default)]
+pub struct ThumbnailCache {
+    textures: HashMap<PathBuf, Cached>,
+}
+
+struct Cached {
+    texture: Option<egui::TextureHandle>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl ThumbnailCache {
+    /// The thumbnail of the shader or preset in `dir`, if there is one by now.
+    pub fn get(
+        &mut self,
+        ctx: &egui::Context,
+        source: &Path,
+        dir: &Path,
+    ) -> Option<egui::TextureId> {
+        // Looking at the file every frame for every entry adds up.
+        const CHECK_EVERY: Duration = Duration::from_secs(1);
+        let due = self
+            .textures
+            .get(source)
+            .is_none_or(|cached| cached.checked.elapsed() >= CHECK_EVERY);
+        if due {
+            let thumbnail = thumbnail_path(source, dir);
+            let made = modified(&thumbnail);
+            let cached = self.textures.entry(source.to_path_buf()).or_insert(Cached {
+                texture: None,
+                modified: None,
+                checked: Instant::now(),
+            });
+            if cached.modified != made {
+                cached.texture = made.and_then(|_| load(ctx, &thumbnail));
+                // It might still have been written to, try again next time.
+                cached.modified = made.filter(|_| cached.texture.is_some());
+            }
+            cached.checked = Instant::now();
+        }
+        self.textures[source].texture.as_ref().map(|t| t.id())
+    }
+
+    /// Renders the ones that changed in another process, so the ui keeps going.
+    pub fn render_changed(&self, shader_dir: &Path, preset_dir: &Path) {
+        let spawned = std::env::current_exe().and_then(|exe| {
+            std::process::Command::new(exe)
+                .arg("--thumbnails")
+                .arg("--shader-dir")
+                .arg(shader_dir)
+                .arg("--preset-dir")
+                .arg(preset_dir)
+                .spawn()
+        });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start rendering thumbnails: {}", e);
+        }
+    }
+}
+
+fn load(ctx: &egui::Context, path: &Path) -> Option<egui::TextureHandle> {
+    let image = image::open(path)
+        .map_err(|e| eprintln!("Failed to load {}: {}", path.display(), e))
+        .ok()?
+        .to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    let image = egui::ColorImage::from_rgba_unmultiplied(size, image.as_flat_samples().as_slice());
+    Some(ctx.load_texture(
+        path.display().to_string(),
+        image,
+        egui::TextureOptions::LINEAR,
+    ))
+}
@@ -1,24 +1,58 @@
-use std::path::PathBuf;
+use std::time::Duration;
 
+use rand::seq::SliceRandom;
 use wgpu::{CommandEncoder, TextureView};
 use winit::{event::*, window::Window};
 
+use crate::audio_processor::{AudioProcessor, Binning};
+use crate::blit::{Tonemap, UpscaleFilter};
+use crate::browser::{Entry, ShaderBrowser, Status};
+use crate::capture::Capture;
+use crate::editor::ShaderEditor;
 use crate::egui_integration::wgpu::{RenderPass, ScreenDescriptor};
 use crate::egui_integration::winit::{Platform, PlatformDescriptor};
-use crate::renderer::Renderer;
+use crate::frame_timer::FrameTimer;
+use crate::keymap::{Action, Keymap};
+use crate::layers::BlendMode;
+use crate::midi::{Midi, Target};
+use crate::osc::Osc;
+use crate::output::OutputSource;
+use crate::plots::AudioPlots;
+use crate::post::{Effect, PostProcessor};
+use crate::preset::{Presets, Transition};
+use crate::previews::TexturePreviews;
+use crate::renderer::{self, Renderer};
+use crate::settings::Settings;
 use crate::shaders;
-use crate::state::State;
+use crate::state::{present_mode_name, DisplayMode, State};
+use crate::thumbnails::ThumbnailCache;
 
 pub struct Ui {
     platform: Platform,
     egui_rp: RenderPass,
-    visible: bool,
-    pressed_last_frame: bool,
-    shaders: Vec<PathBuf>,
+    pub visible: bool,
+    /// The list of keys, shown with the ui hidden too.
+    help: bool,
+    pub keymap: Keymap,
+    pub browser: ShaderBrowser,
+    editor: ShaderEditor,
+    plots: AudioPlots,
+    previews: TexturePreviews,
+    pub presets: Presets,
+    thumbnails: ThumbnailCache,
+    // What the next preset is saved as.
+    preset_name: String,
+    transition: Transition,
+    transition_seconds: f32,
+    pub midi: Midi,
+    midi_ports: Vec<String>,
+    // What the Learn button maps the next control to.
+    midi_target: Target,
+    pub osc: Osc,
 }
 
 impl Ui {
-    pub fn new(state: &State, renderer: &Renderer) -> Self {
+    pub fn new(state: &State, renderer: &Renderer, settings: &Settings) -> Self {
         let size = state.window.inner_size();
 
         let platform = Platform::new(PlatformDescriptor {
@@ -54,13 +88,39 @@ impl Ui {
         Self {
             platform,
             egui_rp: render_pass,
-            visible: false,
-            pressed_last_frame: false,
-            shaders: shaders::list_shaders().unwrap_or(vec![]),
+            visible: settings.ui.visible,
+            help: false,
+            keymap: Keymap::new(&settings.keys),
+            browser: ShaderBrowser::new(
+                &settings.ui.shader_dir,
+                settings.ui.favorites.clone(),
+                settings.ui.ratings.clone(),
+            ),
+            editor: ShaderEditor::new(&settings.ui.shader_dir),
+            plots: AudioPlots::default(),
+            previews: TexturePreviews::default(),
+            presets: Presets::new(&settings.ui.preset_dir),
+            thumbnails: ThumbnailCache::default(),
+            preset_name: String::new(),
+            transition: Transition::Fade,
+            transition_seconds: 2.0,
+            midi: Midi::new(
+                settings.midi.port.as_deref(),
+                settings.midi.mappings.clone(),
+            ),
+            midi_ports: Midi::ports(),
+            midi_target: Target::LayerOpacity(0),
+            osc: Osc::new(settings.osc.port, settings.osc.broadcast.as_deref()),
         }
     }
 
-    pub fn input(&mut self, event: &WindowEvent, state: &mut State) -> bool {
+    pub fn input(
+        &mut self,
+        event: &WindowEvent,
+        app_state: &mut State,
+        audio: &AudioProcessor,
+        renderer: &mut Renderer,
+    ) -> bool {
         match event {
             WindowEvent::KeyboardInput {
                 input:
@@ -72,33 +132,125 @@ impl Ui {
                 ..
             } => {
                 let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    VirtualKeyCode::F1 => {
-                        if is_pressed && !self.pressed_last_frame {
-                            self.visible = !self.visible;
-                        }
-                        self.pressed_last_frame = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::F2 => {
-                        if is_pressed && !self.pressed_last_frame {
-                            self.visible = !self.visible;
+                // Every release counts, one that egui took would leave the key held for good.
+                if !is_pressed {
+                    self.keymap.pressed(*keycode, false);
+                }
+                if self.keymap.rebind(*keycode, is_pressed) {
+                    return true;
+                }
+                match self.keymap.action(*keycode) {
+                    // Typing into a text field shouldn't switch shaders, the ui can still be closed.
+                    Some(action)
+                        if matches!(action, Action::ToggleUi | Action::Help)
+                            || !self.egui_wants(event) =>
+                    {
+                        if self.keymap.pressed(*keycode, is_pressed) {
+                            self.run(action, app_state, audio, renderer);
                         }
-                        self.pressed_last_frame = is_pressed;
                         true
                     }
-                    _ => false,
+                    _ => self.egui_wants(event),
+                }
+            }
+            WindowEvent::Focused(false) => {
+                self.keymap.release_all();
+                self.egui_wants(event)
+            }
+            _ => self.egui_wants(event),
+        }
+    }
+
+    fn run(
+        &mut self,
+        action: Action,
+        state: &mut State,
+        audio: &AudioProcessor,
+        renderer: &mut Renderer,
+    ) {
+        let current = renderer.layers[renderer.selected_layer].path.clone();
+        let shader = match action {
+            Action::NextShader => shaders::step(&self.browser.list, &current, 1),
+            Action::PreviousShader => shaders::step(&self.browser.list, &current, -1),
+            Action::RandomShader => {
+                let others: Vec<_> = self
+                    .browser
+                    .list
+                    .iter()
+                    .filter(|p| **p != current)
+                    .collect();
+                others.choose(&mut rand::thread_rng()).copied()
+            }
+            _ => None,
+        };
+        if let Some(shader) = shader {
+            renderer.change_shader(shader);
+        }
+        match action {
+            Action::Help => self.help = !self.help,
+            Action::ToggleUi => self.visible = !self.visible,
+            Action::Fullscreen => state.toggle_fullscreen(),
+            Action::Screenshot => renderer.capture.screenshot(),
+            Action::DumpRecording => renderer.capture.dump(),
+            Action::NextPreset => self.presets.next(renderer, audio),
+            Action::PreviousPreset => self.presets.previous(renderer, audio),
+            Action::LockPreset => self.presets.locked = !self.presets.locked,
+            Action::PauseAudio if audio.is_paused() => audio.start(),
+            Action::PauseAudio => audio.pause(),
+            Action::MoreSensitive | Action::LessSensitive => {
+                // Moves the level that ends up as 1, so quiet input fills the texture more.
+                const STEP_DB: f32 = 3.0;
+                let mut analysis = audio.analysis();
+                let step = if action == Action::MoreSensitive {
+                    -STEP_DB
+                } else {
+                    STEP_DB
+                };
+                // Nothing to move with the floor that close to 0 dB, clamp would panic.
+                let lowest = analysis.db_min + STEP_DB;
+                if lowest <= 0.0 {
+                    analysis.db_max = (analysis.db_max + step).clamp(lowest, 0.0);
+                    audio.set_analysis(analysis);
                 }
             }
+            Action::NextShader | Action::PreviousShader | Action::RandomShader => {}
+        }
+    }
+
+    /// Whether egui is using the mouse or keyboard, e.g. the pointer is over the panel.
+    /// Releasing the mouse is never captured so a drag started outside the panel can end on it.
+    fn egui_wants(&self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let ctx = self.platform.context();
+        match event {
+            WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_) => {
+                ctx.wants_keyboard_input()
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                ..
+            }
+            | WindowEvent::MouseWheel { .. } => ctx.wants_pointer_input(),
             _ => false,
         }
     }
 
-    pub fn update(&mut self, state: &State, renderer: &mut Renderer) {
+    pub fn update(&mut self, state: &mut State, audio: &AudioProcessor, renderer: &mut Renderer) {
         let time = state.get_elapsed_time();
         self.platform.update_time(time.as_secs_f64());
 
-        if !self.visible {
+        // Controllers work with the ui hidden too.
+        for (target, value) in self.midi.poll() {
+            target.apply(value, renderer, &mut self.presets, audio);
+        }
+        for command in self.osc.poll(audio) {
+            command.apply(renderer, &self.browser.list, &mut self.presets, audio);
+        }
+        self.osc.broadcast(audio, state.fft_dimensions);
+
+        if !self.visible && !self.help {
             // Returning at this point pauses animations,
             // so if you want to have them continue in the background you have to
             // do something about letting the ui render but not take input.
@@ -121,6 +273,12 @@ impl Ui {
             visuals,
             ..Default::default()
         };
+        if self.help {
+            self.help_window(&ctx);
+        }
+        if !self.visible {
+            return;
+        }
         egui::SidePanel::left("debug_panel")
             .default_width(300.0)
             .frame(egui::Frame::side_top_panel(&style))
@@ -128,14 +286,684 @@ impl Ui {
                 ui.label("egui");
                 ui.add_space(12.0);
                 ui.separator();
-                for p in &self.shaders {
-                    if ui.link(p.file_name().unwrap().to_str().unwrap()).clicked() {
-                        renderer.change_shader(p);
-                    }
+                egui::CollapsingHeader::new("Shaders")
+                    .default_open(true)
+                    .show(ui, |ui| self.browser_ui(ui, renderer));
+                ui.separator();
+                egui::CollapsingHeader::new("Presets").show(ui, |ui| {
+                    self.presets_ui(ui, audio, renderer);
+                });
+                ui.separator();
+                Self::layers(ui, renderer);
+                ui.separator();
+                let mut scale = renderer.render_scale();
+                let slider = egui::Slider::new(
+                    &mut scale,
+                    renderer::MIN_RENDER_SCALE..=renderer::MAX_RENDER_SCALE,
+                )
+                .text("Render scale");
+                if ui.add_enabled(!renderer.quality.enabled, slider).changed() {
+                    renderer.set_render_scale(scale);
                 }
+                egui::ComboBox::from_label("Upscale filter")
+                    .selected_text(renderer.upscale_filter.name())
+                    .show_ui(ui, |ui| {
+                        for filter in UpscaleFilter::ALL {
+                            ui.selectable_value(
+                                &mut renderer.upscale_filter,
+                                filter,
+                                filter.name(),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_label("Tonemap")
+                    .selected_text(renderer.tonemap.name())
+                    .show_ui(ui, |ui| {
+                        for tonemap in Tonemap::ALL {
+                            ui.selectable_value(&mut renderer.tonemap, tonemap, tonemap.name());
+                        }
+                    });
+                let size = renderer.render_size();
+                ui.label(format!("Rendering at {}x{}", size.width, size.height));
+                ui.checkbox(&mut self.previews.open, "Show textures");
+                ui.separator();
+                ui.checkbox(&mut renderer.quality.enabled, "Adaptive quality");
+                ui.add(
+                    egui::Slider::new(&mut renderer.quality.target_fps, 24.0..=240.0)
+                        .text("Target FPS"),
+                );
+                ui.label(format!(
+                    "Quality {:.2}, frame {:.2} ms",
+                    renderer.quality.quality(),
+                    renderer.quality.frame_time().as_secs_f64() * 1000.0
+                ));
+                ui.separator();
+                egui::CollapsingHeader::new("Display")
+                    .show(ui, |ui| Self::display(ui, state, renderer, &self.keymap));
+                egui::CollapsingHeader::new("Capture").show(ui, |ui| {
+                    Self::capture(ui, &mut renderer.capture, &self.keymap)
+                });
+                egui::CollapsingHeader::new("Outputs")
+                    .show(ui, |ui| Self::outputs(ui, state, renderer));
+                egui::CollapsingHeader::new("Post processing")
+                    .show(ui, |ui| Self::post(ui, &mut renderer.post));
+                egui::CollapsingHeader::new("Analysis").show(ui, |ui| {
+                    Self::analysis(ui, audio);
+                    ui.checkbox(&mut self.plots.open, "Plots");
+                });
+                egui::CollapsingHeader::new("MIDI").show(ui, |ui| self.midi_ui(ui, renderer));
+                egui::CollapsingHeader::new("OSC").show(ui, |ui| Self::osc(ui, &mut self.osc));
                 ui.separator();
                 ui.label(format!("FPS: {}", state.delayed_fps));
+                Self::timings(ui, &renderer.frame_timer);
+            });
+        // Saving changes how the shader checks out.
+        if self.editor.open && self.editor.show(&ctx, renderer) {
+            self.browser.refresh();
+        }
+        if self.plots.open {
+            self.plots.show(&ctx, renderer, audio, state.fft_dimensions);
+        }
+        if self.previews.open {
+            self.previews.show(&ctx, &mut self.egui_rp, renderer);
+        } else {
+            self.previews.clear(&mut self.egui_rp);
+        }
+    }
+
+    /// The shaders by category with their thumbnails, picking one loads it into the selected layer.
+    fn browser_ui(&mut self, ui: &mut egui::Ui, renderer: &mut Renderer) {
+        self.browser.update();
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.browser.search).hint_text("Search"));
+            ui.checkbox(&mut self.browser.favorites_only, "♡");
+            if ui.button("Refresh").clicked() {
+                self.browser.refresh();
+            }
+        });
+
+        let mut load = None;
+        let mut add = None;
+        let mut favorite = None;
+        let searching = !self.browser.search.trim().is_empty();
+        let categories = self.browser.filtered();
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                for (category, entries) in &categories {
+                    let mut rows = |ui: &mut egui::Ui| {
+                        for entry in entries {
+                            ui.horizontal(|ui| {
+                                let size = egui::vec2(48.0, 27.0);
+                                match self
+                                    .thumbnails
+                                    .get(ui.ctx(), &entry.path, &self.browser.dir)
+                                {
+                                    Some(texture) => {
+                                        if ui.add(egui::ImageButton::new(texture, size)).clicked() {
+                                            load = Some(entry.path.clone());
+                                        }
+                                    }
+                                    None => {
+                                        ui.allocate_space(size);
+                                    }
+                                }
+                                match &entry.status {
+                                    Status::Checking => ui.weak("…"),
+                                    Status::Ok => ui.colored_label(egui::Color32::GREEN, "✔"),
+                                    Status::Failed(e) => {
+                                        ui.colored_label(egui::Color32::RED, "🗙").on_hover_text(e)
+                                    }
+                                };
+                                let response = ui.link(&entry.name);
+                                let response = Self::info_tooltip(response, entry);
+                                if response.clicked() {
+                                    load = Some(entry.path.clone());
+                                }
+                                let is_favorite = self.browser.favorites.contains(&entry.key);
+                                if ui.selectable_label(is_favorite, "♡").clicked() {
+                                    favorite = Some(entry.key.clone());
+                                }
+                                if ui
+                                    .small_button("+")
+                                    .on_hover_text("Add as a layer")
+                                    .clicked()
+                                {
+                                    add = Some(entry.path.clone());
+                                }
+                            });
+                        }
+                    };
+                    if category.is_empty() {
+                        rows(ui);
+                    } else {
+                        egui::CollapsingHeader::new(*category)
+                            .open(searching.then_some(true))
+                            .show(ui, rows);
+                    }
+                }
+                if categories.is_empty() {
+                    ui.label(format!("No shaders in {}", self.browser.dir.display()));
+                }
+            });
+        if let Some(path) = load {
+            renderer.change_shader(&path);
+        }
+        if let Some(path) = add {
+            renderer.add_layer(&path);
+        }
+        if let Some(key) = favorite {
+            self.browser.toggle_favorite(&key);
+        }
+
+        // More on the one that's running.
+        let current = renderer.layers[renderer.selected_layer].path.clone();
+        let Some(entry) = self.browser.entry(&current) else {
+            return;
+        };
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.strong(&entry.name);
+            if ui.button("Edit").clicked() {
+                self.editor.open = true;
+            }
+        });
+        let info = &entry.info;
+        if let Some(author) = &info.author {
+            ui.label(format!("By {}", author));
+        }
+        if let Some(description) = &info.description {
+            ui.label(description);
+        }
+        if let Some(source) = &info.source {
+            ui.hyperlink(source);
+        }
+        if let Status::Failed(e) = &entry.status {
+            ui.colored_label(egui::Color32::RED, e);
+        }
+        let key = entry.key.clone();
+        let rating = self.browser.rating(&key);
+        ui.horizontal(|ui| {
+            for stars in 1..=5 {
+                let star = if stars <= rating { "★" } else { "☆" };
+                if ui.small_button(star).clicked() {
+                    // Clicking the rating it has takes it away.
+                    let stars = if stars == rating { 0 } else { stars };
+                    self.browser.rate(&key, stars);
+                }
+            }
+        });
+    }
+
+    fn info_tooltip(response: egui::Response, entry: &Entry) -> egui::Response {
+        let info = &entry.info;
+        if info.author.is_none() && info.description.is_none() {
+            return response;
+        }
+        response.on_hover_ui(|ui| {
+            if let Some(description) = &info.description {
+                ui.label(description);
+            }
+            if let Some(author) = &info.author {
+                ui.weak(format!("By {}", author));
+            }
+        })
+    }
+
+    /// The keys and what they do, clicking one binds the next key pressed instead.
+    fn help_window(&mut self, ctx: &egui::Context) {
+        let keymap = &mut self.keymap;
+        egui::Window::new("Keys")
+            .open(&mut self.help)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("keys").striped(true).show(ui, |ui| {
+                    for action in Action::ALL {
+                        ui.label(action.name());
+                        let keys = if keymap.rebinding == Some(action) {
+                            "Press a key".to_string()
+                        } else {
+                            keymap.keys(action)
+                        };
+                        if ui.button(keys).clicked() {
+                            keymap.rebinding = Some(action);
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.label("Escape keeps the key as it was");
+            });
+    }
+
+    /// Loading and saving presets, the next and previous preset keys step through them.
+    fn presets_ui(&mut self, ui: &mut egui::Ui, audio: &AudioProcessor, renderer: &mut Renderer) {
+        let mut load = None;
+        for (index, path) in self.presets.list.iter().enumerate() {
+            let current = self.presets.current == Some(index);
+            ui.horizontal(|ui| {
+                let size = egui::vec2(64.0, 36.0);
+                match self.thumbnails.get(ui.ctx(), path, &self.presets.dir) {
+                    Some(texture) => {
+                        if ui.add(egui::ImageButton::new(texture, size)).clicked() {
+                            load = Some(index);
+                        }
+                    }
+                    None => {
+                        ui.allocate_space(size);
+                    }
+                }
+                if ui.selectable_label(current, Presets::name(path)).clicked() {
+                    load = Some(index);
+                }
+            });
+        }
+        if self.presets.list.is_empty() {
+            ui.label(format!("No presets in {}", self.presets.dir.display()));
+        } else if self.keymap.is_bound(Action::PreviousPreset)
+            && self.keymap.is_bound(Action::NextPreset)
+        {
+            ui.weak(format!(
+                "{} and {} step through them",
+                self.keymap.keys(Action::PreviousPreset),
+                self.keymap.keys(Action::NextPreset)
+            ));
+        }
+        if let Some(index) = load {
+            self.presets.load(index, renderer, audio);
+            if let Some(index) = self.presets.current {
+                self.preset_name = Presets::name(&self.presets.list[index]);
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Refresh").clicked() {
+                self.presets.refresh();
+            }
+            if ui
+                .button("Thumbnails")
+                .on_hover_text("Renders the ones that changed in the background")
+                .clicked()
+            {
+                self.thumbnails
+                    .render_changed(&self.browser.dir, &self.presets.dir);
+            }
+            ui.checkbox(&mut self.presets.locked, "Locked");
+        });
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.preset_name);
+            if ui.button("Save").clicked() {
+                match self.presets.save(
+                    &self.preset_name,
+                    self.transition,
+                    self.transition_seconds,
+                    renderer,
+                    audio,
+                ) {
+                    Ok(()) => self
+                        .thumbnails
+                        .render_changed(&self.browser.dir, &self.presets.dir),
+                    Err(e) => eprintln!("{:#}", e),
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            for transition in Transition::ALL {
+                ui.selectable_value(&mut self.transition, transition, transition.name());
+            }
+            ui.add_enabled(
+                self.transition == Transition::Fade,
+                egui::Slider::new(&mut self.transition_seconds, 0.1..=10.0).text("Seconds"),
+            );
+        });
+        ui.label("The transition is used when switching to the saved preset");
+    }
+
+    /// Picking the port and mapping controls, Learn maps the next control that moves.
+    fn midi_ui(&mut self, ui: &mut egui::Ui, renderer: &Renderer) {
+        let midi = &mut self.midi;
+        let status = if midi.is_connected() {
+            ""
+        } else {
+            " (not connected)"
+        };
+        let selected = format!(
+            "{}{}",
+            midi.port.as_deref().unwrap_or("Virtual port"),
+            status
+        );
+        let mut connect = None;
+        egui::ComboBox::from_label("Port")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                if ui
+                    .selectable_label(midi.port.is_none(), "Virtual port")
+                    .clicked()
+                {
+                    connect = Some(None);
+                }
+                for port in &self.midi_ports {
+                    let current = midi.port.as_ref() == Some(port);
+                    if ui.selectable_label(current, port).clicked() {
+                        connect = Some(Some(port.clone()));
+                    }
+                }
+            });
+        if let Some(port) = connect {
+            midi.connect(port.as_deref());
+        }
+        if ui.button("Rescan").clicked() {
+            self.midi_ports = Midi::ports();
+        }
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("midi_target")
+                .selected_text(self.midi_target.name())
+                .show_ui(ui, |ui| {
+                    let targets = Target::all(renderer, self.presets.list.len());
+                    for target in targets {
+                        ui.selectable_value(&mut self.midi_target, target, target.name());
+                    }
+                });
+            if midi.learning.is_some() {
+                if ui.button("Cancel").clicked() {
+                    midi.learning = None;
+                }
+                ui.label("Move a control");
+            } else if ui.button("Learn").clicked() {
+                midi.learning = Some(self.midi_target);
+            }
+        });
+        let mut remove = None;
+        for (index, mapping) in midi.mappings.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} -> {}",
+                    mapping.control.name(),
+                    mapping.target.name()
+                ));
+                if ui.small_button("x").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            midi.mappings.remove(index);
+        }
+        if let Some((control, value)) = midi.last {
+            ui.label(format!("Last: {} at {:.2}", control.name(), value));
+        }
+    }
+
+    /// The addresses are listed with `Osc`.
+    fn osc(ui: &mut egui::Ui, osc: &mut Osc) {
+        ui.horizontal(|ui| {
+            let mut listening = osc.is_listening();
+            if ui.checkbox(&mut listening, "Listen on port").changed() {
+                if listening {
+                    osc.listen();
+                } else {
+                    osc.stop();
+                }
+            }
+            ui.add_enabled(!listening, egui::DragValue::new(&mut osc.port));
+        });
+        ui.horizontal(|ui| {
+            let mut broadcast = osc.broadcast.clone();
+            let response = ui.text_edit_singleline(&mut broadcast);
+            if response.changed() {
+                osc.set_broadcast(&broadcast);
+            }
+            ui.label("Send features to");
+        });
+        if !osc.broadcast.is_empty() && !osc.is_broadcasting() {
+            ui.label("Not an address like 127.0.0.1:9001");
+        }
+        if let Some(last) = &osc.last {
+            ui.label(format!("Last: {}", last));
+        }
+    }
+
+    /// Top to bottom like in an image editor, picking a shader loads it into the selected layer.
+    fn layers(ui: &mut egui::Ui, renderer: &mut Renderer) {
+        let mut remove = None;
+        let mut raise = None;
+        let count = renderer.layers.len();
+        for index in (0..count).rev() {
+            let layer = &mut renderer.layers[index];
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut renderer.selected_layer, index, layer.name());
+                ui.checkbox(&mut layer.visible, "");
+                if ui
+                    .add_enabled(index + 1 < count, egui::Button::new("up").small())
+                    .clicked()
+                {
+                    raise = Some(index);
+                }
+                if ui
+                    .add_enabled(index > 0, egui::Button::new("down").small())
+                    .clicked()
+                {
+                    raise = Some(index - 1);
+                }
+                if ui
+                    .add_enabled(count > 1, egui::Button::new("x").small())
+                    .clicked()
+                {
+                    remove = Some(index);
+                }
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source(("blend", index))
+                    .width(80.0)
+                    .selected_text(layer.blend.name())
+                    .show_ui(ui, |ui| {
+                        for mode in BlendMode::ALL {
+                            ui.selectable_value(&mut layer.blend, mode, mode.name());
+                        }
+                    });
+                ui.add(egui::Slider::new(&mut layer.opacity, 0.0..=1.0).text("Opacity"));
+            });
+            ui.add(egui::Slider::new(&mut layer.audio, -1.0..=1.0).text("Beat opacity"));
+            for param in &mut layer.program.params {
+                let range = param.desc.min..=param.desc.max;
+                ui.add(egui::Slider::new(&mut param.value, range).text(&param.desc.name));
+            }
+        }
+        if let Some(index) = raise {
+            renderer.raise_layer(index);
+        }
+        if let Some(index) = remove {
+            renderer.remove_layer(index);
+        }
+    }
+
+    fn display(ui: &mut egui::Ui, state: &mut State, renderer: &mut Renderer, keymap: &Keymap) {
+        let mut present_mode = renderer.surface_config.present_mode;
+        egui::ComboBox::from_label("Present mode")
+            .selected_text(present_mode_name(present_mode))
+            .show_ui(ui, |ui| {
+                for &mode in &renderer.present_modes {
+                    ui.selectable_value(&mut present_mode, mode, present_mode_name(mode));
+                }
+            });
+        if present_mode != renderer.surface_config.present_mode {
+            renderer.set_present_mode(present_mode);
+        }
+
+        let monitors = state.monitors();
+        let monitor_name = |index: usize| {
+            monitors
+                .get(index)
+                .and_then(|m| m.name())
+                .unwrap_or_else(|| format!("Monitor {}", index + 1))
+        };
+        let mut monitor = state.monitor;
+        egui::ComboBox::from_label("Monitor")
+            .selected_text(monitor_name(monitor))
+            .show_ui(ui, |ui| {
+                for index in 0..monitors.len() {
+                    ui.selectable_value(&mut monitor, index, monitor_name(index));
+                }
             });
+        let mut mode = state.display.mode;
+        ui.horizontal(|ui| {
+            for m in DisplayMode::ALL {
+                ui.selectable_value(&mut mode, m, m.name());
+            }
+        });
+        if mode != state.display.mode || monitor != state.monitor {
+            state.monitor = monitor;
+            state.set_display_mode(mode);
+        }
+        ui.label(keymap.label("Toggle fullscreen", Action::Fullscreen));
+
+        let mut always_on_top = state.display.always_on_top;
+        if ui.checkbox(&mut always_on_top, "Always on top").changed() {
+            state.set_always_on_top(always_on_top);
+        }
+        if state.display.transparent {
+            ui.label("Transparent, black is see-through");
+        } else {
+            ui.label("Start with --transparent for a see-through window");
+        }
+    }
+
+    /// Screenshots and the recording go to `captures/`, without the ui.
+    fn capture(ui: &mut egui::Ui, capture: &mut Capture, keymap: &Keymap) {
+        if ui
+            .button(keymap.label("Screenshot", Action::Screenshot))
+            .clicked()
+        {
+            capture.screenshot();
+        }
+        ui.checkbox(&mut capture.recording, "Record the last few seconds");
+        ui.add(egui::Slider::new(&mut capture.seconds, 1.0..=30.0).text("Seconds"));
+        ui.add(egui::Slider::new(&mut capture.fps, 10.0..=60.0).text("Recording FPS"));
+        let (frames, bytes) = capture.recorded();
+        ui.label(format!("{} frames, {} MB", frames, bytes / (1024 * 1024)));
+        if ui
+            .add_enabled(
+                frames > 0,
+                egui::Button::new(keymap.label("Save recording", Action::DumpRecording)),
+            )
+            .clicked()
+        {
+            capture.dump();
+        }
+    }
+
+    /// Extra windows for projectors, each showing the frame or a single layer.
+    fn outputs(ui: &mut egui::Ui, state: &mut State, renderer: &mut Renderer) {
+        let layer_names: Vec<String> = renderer.layers.iter().map(|l| l.name()).collect();
+        let source_name = |source: OutputSource| match source {
+            OutputSource::Frame => "Frame".to_string(),
+            OutputSource::Layer(index) => layer_names
+                .get(index)
+                .map(|name| format!("{}: {}", index + 1, name))
+                .unwrap_or_else(|| "Frame".to_string()),
+        };
+        let mut close = None;
+        for (index, output) in renderer.outputs.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("Output {}", index + 1));
+                egui::ComboBox::from_id_source(("output", index))
+                    .selected_text(source_name(output.source))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut output.source,
+                            OutputSource::Frame,
+                            source_name(OutputSource::Frame),
+                        );
+                        for layer in 0..layer_names.len() {
+                            let source = OutputSource::Layer(layer);
+                            ui.selectable_value(&mut output.source, source, source_name(source));
+                        }
+                    });
+                if ui.small_button("fullscreen").clicked() {
+                    output.toggle_fullscreen();
+                }
+                if ui.small_button("x").clicked() {
+                    close = Some(index);
+                }
+            });
+        }
+        if let Some(index) = close {
+            renderer.outputs.remove(index);
+        }
+        if ui.button("New output window").clicked() {
+            state.request_output();
+        }
+        ui.label("Layers shown on an output render even when hidden here");
+    }
+
+    /// Strength and beat strength of every effect, starting from what the shader declared.
+    fn post(ui: &mut egui::Ui, post: &mut PostProcessor) {
+        let lut_name = post.lut_name();
+        let settings = &mut post.settings;
+        egui::Grid::new("post").show(ui, |ui| {
+            ui.label("Effect");
+            ui.label("Strength");
+            ui.label("Beat");
+            ui.end_row();
+            for effect in Effect::ALL {
+                let max = effect.max_strength();
+                let effect_settings = settings.get_mut(effect);
+                ui.label(effect.name());
+                ui.add(egui::Slider::new(&mut effect_settings.strength, 0.0..=max));
+                ui.add(egui::Slider::new(&mut effect_settings.audio, 0.0..=max));
+                ui.end_row();
+            }
+        });
+        ui.add(egui::Slider::new(&mut settings.bloom_threshold, 0.0..=1.0).text("Bloom threshold"));
+        ui.add(
+            egui::Slider::new(&mut settings.kaleidoscope_segments, 2..=16)
+                .text("Kaleidoscope segments"),
+        );
+        match lut_name {
+            Some(name) => ui.label(format!("LUT: {}", name)),
+            None => ui.label("No LUT, shaders set one with //! post lut <path>"),
+        };
+    }
+
+    /// How the fft is turned into the texture the shaders read.
+    fn analysis(ui: &mut egui::Ui, audio: &AudioProcessor) {
+        let mut analysis = audio.analysis();
+        ui.add(egui::Slider::new(&mut analysis.smoothing, 0.0..=0.99).text("Smoothing"));
+        ui.add(egui::Slider::new(&mut analysis.db_max, -60.0..=0.0).text("Max dB"));
+        // Kept below the max, settings and presets with it backwards are reset when loaded.
+        let db_min_top = (analysis.db_max - 1.0).min(-20.0);
+        ui.add(egui::Slider::new(&mut analysis.db_min, -140.0..=db_min_top).text("Min dB"));
+        egui::ComboBox::from_label("Binning")
+            .selected_text(analysis.binning.name())
+            .show_ui(ui, |ui| {
+                for binning in Binning::ALL {
+                    ui.selectable_value(&mut analysis.binning, binning, binning.name());
+                }
+            });
+        if analysis != audio.analysis() {
+            audio.set_analysis(analysis);
+        }
+    }
+
+    /// The time every pass of the frame took, in milliseconds.
+    fn timings(ui: &mut egui::Ui, timer: &FrameTimer) {
+        let ms = |d: Duration| format!("{:.2}", d.as_secs_f64() * 1000.0);
+        egui::Grid::new("timings").striped(true).show(ui, |ui| {
+            ui.label("Pass");
+            ui.label("GPU ms");
+            ui.label("CPU ms");
+            ui.end_row();
+            for timing in timer.timings() {
+                ui.label(timing.label);
+                ui.label(timing.gpu.map(ms).unwrap_or_else(|| "-".into()));
+                ui.label(ms(timing.cpu));
+                ui.end_row();
+            }
+        });
+        if !timer.has_gpu_timing() {
+            ui.label("No GPU timestamps on this device");
+        }
     }
 
     /// Rendering the UI, update MUST be called before this every frame.
@@ -148,7 +976,7 @@ impl Ui {
         queue: &wgpu::Queue,
         config: &wgpu::SurfaceConfiguration,
     ) -> Result<(), wgpu::SurfaceError> {
-        if !self.visible {
+        if !self.visible && !self.help {
             // Returning at this point pauses animations,
             // so if you want to have them continue in the background you have to
             // do something about letting the ui render but not take input.